@@ -0,0 +1,11 @@
+import path from "path"
+
+// Runtime configuration, passed in by the frontend when it spawns the backend.
+// Each account runs its own backend, so every value here is per account.
+
+// Directory holding the Baileys multi-file auth state
+export const authDir = path.resolve(process.env.WA_AUTH_DIR || "./auth")
+
+// Ports for the HTTP API and the WebSocket event stream
+export const httpPort = parseInt(process.env.WA_HTTP_PORT || "3000", 10)
+export const wsPort = parseInt(process.env.WA_WS_PORT || "8787", 10)
@@ -1,9 +1,11 @@
+import { toNumber } from "@whiskeysockets/baileys"
 import whatsappService from "../services/whatsapp.js"
 
 export default function setupMessageRoutes(app) {
-    // Send a message
+    // Send a message, as a reply if it comes with the quoted message's key
+    // and body
     app.post("/send", async (req, res) => {
-        const { jid, text } = req.body
+        const { jid, text, quoted } = req.body
 
         if (!jid || !text) {
             return res.status(400).json({
@@ -12,8 +14,86 @@ export default function setupMessageRoutes(app) {
             })
         }
 
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            // The key carries the id WhatsApp gave the message, which the
+            // client stores in place of its own so the echo through
+            // messages.upsert matches it
+            const sent = await whatsappService.sendMessage(
+                jid,
+                { text },
+                quoted ? { quoted } : undefined
+            )
+            res.json({ ok: true, key: sent.key })
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
+    // Send an image, video, audio file or document. The file is the raw
+    // request body, streamed on to WhatsApp's upload, so the other fields
+    // travel in the query string and the MIME type in Content-Type.
+    app.post("/send-media", async (req, res) => {
+        const { jid, caption, fileName } = req.query
+        const mimetype = req.get("Content-Type")
+
+        if (!jid || !mimetype) {
+            return res.status(400).json({
+                ok: false,
+                error: "Missing jid or Content-Type"
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
         try {
-            await whatsappService.sendMessage(jid, text)
+            const sent = await whatsappService.sendMessage(
+                jid,
+                mediaContent({ stream: req }, mimetype, caption, fileName)
+            )
+            res.json({ ok: true, key: sent.key })
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
+    // Star or unstar a message
+    app.post("/messages/star", async (req, res) => {
+        const { jid, id, fromMe, star } = req.body
+
+        if (!jid || !id || typeof star !== "boolean") {
+            return res.status(400).json({
+                ok: false,
+                error: "Missing jid, id or star"
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            await whatsappService.starMessage(jid, id, !!fromMe, star)
             res.json({ ok: true })
         } catch (err) {
             res.status(500).json({
@@ -23,6 +103,220 @@ export default function setupMessageRoutes(app) {
         }
     })
 
+    // React to a message. An empty text removes our reaction.
+    app.post("/messages/react", async (req, res) => {
+        const { jid, key, text } = req.body
+
+        if (!jid || !key || typeof text !== "string") {
+            return res.status(400).json({
+                ok: false,
+                error: "Missing jid, key or text"
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            await whatsappService.sendMessage(jid, { react: { text, key } })
+            res.json({ ok: true })
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
+    // Replace the text of a message we sent. Responds with when the edit
+    // was made, in milliseconds, as WhatsApp records it.
+    app.post("/messages/edit", async (req, res) => {
+        const { jid, key, text } = req.body
+
+        if (!jid || !key || !text) {
+            return res.status(400).json({
+                ok: false,
+                error: "Missing jid, key or text"
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            const sent = await whatsappService.sendMessage(jid, { text, edit: key })
+            res.json({
+                ok: true,
+                editedAt: toNumber(sent.message?.protocolMessage?.timestampMs) || Date.now()
+            })
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
+    // Delete a message for everyone, which only works for our own, or just
+    // from this account's devices
+    app.post("/messages/delete", async (req, res) => {
+        const { jid, key, timestamp, forEveryone } = req.body
+
+        if (!jid || !key || typeof forEveryone !== "boolean") {
+            return res.status(400).json({
+                ok: false,
+                error: "Missing jid, key or forEveryone"
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            if (forEveryone) {
+                await whatsappService.sendMessage(jid, { delete: key })
+            } else {
+                await whatsappService.deleteForMe(jid, key, timestamp)
+            }
+            res.json({ ok: true })
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
+    // Send read receipts for messages we received
+    app.post("/chats/read", async (req, res) => {
+        const { keys } = req.body
+
+        if (!Array.isArray(keys) || keys.length === 0) {
+            return res.status(400).json({
+                ok: false,
+                error: "Missing keys"
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            await whatsappService.readMessages(keys)
+            res.json({ ok: true })
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
+    // Mark a chat as unread. WhatsApp wants the chat's last message, as
+    // { key, messageTimestamp }, to tell which state is newer.
+    app.post("/chats/unread", async (req, res) => {
+        const { jid, lastMessage } = req.body
+
+        if (!jid || !lastMessage) {
+            return res.status(400).json({
+                ok: false,
+                error: "Missing jid or lastMessage"
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            await whatsappService.markUnread(jid, lastMessage)
+            res.json({ ok: true })
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
+    // Tell a chat we're typing or stopped, or tell everyone
+    // whether we're online when no jid is given
+    app.post("/presence", async (req, res) => {
+        const { type, jid } = req.body
+
+        if (!PRESENCE_TYPES.includes(type)) {
+            return res.status(400).json({
+                ok: false,
+                error: `Presence must be one of ${PRESENCE_TYPES.join(", ")}`
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            await whatsappService.sendPresence(type, jid)
+            res.json({ ok: true })
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
+    // Download the decrypted media of a stored message
+    app.post("/media/download", async (req, res) => {
+        const { key, message } = req.body
+
+        if (!key || !message) {
+            return res.status(400).json({
+                ok: false,
+                error: "Missing key or message"
+            })
+        }
+
+        if (!whatsappService.getSocket()) {
+            return res.status(503).json({
+                ok: false,
+                error: "WhatsApp not connected"
+            })
+        }
+
+        try {
+            const buffer = await whatsappService.downloadMedia(key, message)
+            res.type("application/octet-stream").send(buffer)
+        } catch (err) {
+            res.status(500).json({
+                ok: false,
+                error: err.toString()
+            })
+        }
+    })
+
     // Get contacts
     app.get("/contacts", async (req, res) => {
         try {
@@ -58,3 +352,20 @@ export default function setupMessageRoutes(app) {
         }
     })
 }
+
+const PRESENCE_TYPES = ["available", "unavailable", "composing", "paused"]
+
+// Message content for a file, sent as the kind WhatsApp shows it as. Audio
+// has no caption.
+function mediaContent(media, mimetype, caption, fileName) {
+    switch (mimetype.split("/")[0]) {
+        case "image":
+            return { image: media, mimetype, caption }
+        case "video":
+            return { video: media, mimetype, caption }
+        case "audio":
+            return { audio: media, mimetype }
+        default:
+            return { document: media, mimetype, fileName: fileName || "file", caption }
+    }
+}
@@ -1,12 +1,21 @@
 import express from "express"
 import { WebSocketServer } from "ws"
+import { BufferJSON } from "@whiskeysockets/baileys"
 import whatsappService from "./services/whatsapp.js"
 import WebSocketManager from "./websocket/manager.js"
 import setupMessageRoutes from "./routes/messages.js"
+import { authDir, httpPort, wsPort } from "./config.js"
 
 const app = express()
-const wss = new WebSocketServer({ port: 8787 })
-app.use(express.json())
+const wss = new WebSocketServer({ port: wsPort })
+// Messages sent back by the frontend carry binary fields encoded by BufferJSON.
+// /send-media streams the raw request body, so the parser must never touch it,
+// even when the attachment itself is a JSON file.
+app.use(express.json({
+    reviver: BufferJSON.reviver,
+    limit: "5mb",
+    type: (req) => req.path !== "/send-media" && Boolean(req.is("application/json"))
+}))
 
 // Initialize WebSocket manager
 const wsManager = new WebSocketManager(wss)
@@ -110,20 +119,22 @@ app.get("/profile-picture", async (req, res) => {
 })
 
 // Start server
-app.listen(3000, async () => {
+app.listen(httpPort, async () => {
     console.log("=".repeat(50))
     console.log("WhatsApp Backend Server Started")
     console.log("=".repeat(50))
-    console.log("HTTP API:    http://localhost:3000")
-    console.log("WebSocket:   ws://localhost:8787")
-    console.log("Health:      http://localhost:3000/health")
-    console.log("Auth Status: http://localhost:3000/auth/status")
-    console.log("Request QR:  POST http://localhost:3000/auth/request-qr")
+    console.log(`HTTP API:    http://localhost:${httpPort}`)
+    console.log(`WebSocket:   ws://localhost:${wsPort}`)
+    console.log(`Health:      http://localhost:${httpPort}/health`)
+    console.log(`Auth Status: http://localhost:${httpPort}/auth/status`)
+    console.log(`Request QR:  POST http://localhost:${httpPort}/auth/request-qr`)
     console.log("=".repeat(50))
 
     // Check if we have auth credentials and auto-initialize
     const fs = await import('fs')
-    const credsPath = './auth/creds.json'
+    const path = await import('path')
+    const credsPath = path.join(authDir, 'creds.json')
+    console.log(`Auth dir:    ${authDir}`)
 
     if (fs.existsSync(credsPath)) {
         console.log("Found existing credentials, auto-initializing WhatsApp connection...")
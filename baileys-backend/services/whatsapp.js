@@ -1,8 +1,9 @@
-import makeWASocket, { useMultiFileAuthState, DisconnectReason, fetchLatestBaileysVersion, Browsers } from "@whiskeysockets/baileys"
+import makeWASocket, { useMultiFileAuthState, DisconnectReason, fetchLatestBaileysVersion, Browsers, downloadMediaMessage } from "@whiskeysockets/baileys"
 import P from "pino"
 import fs from "fs/promises"
 import path from "path"
 import qrcode from "qrcode"
+import { authDir } from "../config.js"
 
 class WhatsAppService {
     constructor() {
@@ -22,7 +23,7 @@ class WhatsAppService {
     async clearAuthAndDatabase() {
         console.log("Clearing auth folder...")
         try {
-            const authPath = "./auth"
+            const authPath = authDir
             const files = await fs.readdir(authPath)
             for (const file of files) {
                 const filePath = path.join(authPath, file)
@@ -45,7 +46,7 @@ class WhatsAppService {
         this.currentQr = null
 
         try {
-            const { state, saveCreds } = await useMultiFileAuthState("./auth")
+            const { state, saveCreds } = await useMultiFileAuthState(authDir)
             const { version, isLatest } = await fetchLatestBaileysVersion()
 
             console.log(`Using WA version ${version.join('.')}, isLatest: ${isLatest}`)
@@ -133,6 +134,7 @@ class WhatsAppService {
                 "contacts.set", "contacts.upsert", "contacts.update",
                 "presence.update",
                 "groups.upsert", "groups.update", "group-participants.update",
+                "labels.edit", "labels.association",
                 "messaging-history.set"
             ]
 
@@ -193,9 +195,59 @@ class WhatsAppService {
         }
     }
 
-    async sendMessage(jid, content) {
+    async sendMessage(jid, content, options) {
         if (!this.sock) throw new Error("WhatsApp not connected")
-        return await this.sock.sendMessage(jid, content)
+        return await this.sock.sendMessage(jid, content, options)
+    }
+
+    // Star or unstar a message. The change syncs to the phone and other linked devices.
+    async starMessage(jid, id, fromMe, star) {
+        if (!this.sock) throw new Error("WhatsApp not connected")
+        await this.sock.chatModify({ star: { messages: [{ id, fromMe }], star } }, jid)
+    }
+
+    // Delete a message from this account's devices only. The deletion syncs
+    // to the phone and other linked devices.
+    async deleteForMe(jid, key, timestamp) {
+        if (!this.sock) throw new Error("WhatsApp not connected")
+        await this.sock.chatModify(
+            { deleteForMe: { key, timestamp, deleteMedia: false } },
+            jid
+        )
+    }
+
+    // Send read receipts, which also clears the chat's unread count on the
+    // phone and other linked devices
+    async readMessages(keys) {
+        if (!this.sock) throw new Error("WhatsApp not connected")
+        await this.sock.readMessages(keys)
+    }
+
+    // Mark a chat as unread on all devices
+    async markUnread(jid, lastMessage) {
+        if (!this.sock) throw new Error("WhatsApp not connected")
+        await this.sock.chatModify({ markRead: false, lastMessages: [lastMessage] }, jid)
+    }
+
+    // Send our presence, to one chat for typing
+    async sendPresence(type, jid) {
+        if (!this.sock) throw new Error("WhatsApp not connected")
+        await this.sock.sendPresenceUpdate(type, jid)
+    }
+
+    // Download and decrypt the media of a message the frontend stored earlier
+    async downloadMedia(key, message) {
+        if (!this.sock) throw new Error("WhatsApp not connected")
+        return await downloadMediaMessage(
+            { key, message },
+            "buffer",
+            {},
+            {
+                logger: P({ level: "silent" }),
+                // Lets Baileys ask the sender's phone to re-upload expired media
+                reuploadRequest: this.sock.updateMediaMessage
+            }
+        )
     }
 }
 
@@ -1,3 +1,5 @@
+import { BufferJSON } from "@whiskeysockets/baileys"
+
 class WebSocketManager {
     constructor(wss) {
         this.wss = wss
@@ -17,7 +19,9 @@ class WebSocketManager {
     }
 
     broadcast(data) {
-        const message = JSON.stringify(data)
+        // BufferJSON keeps binary fields (media keys, hashes) as base64 so the
+        // frontend can hand them back for media downloads
+        const message = JSON.stringify(data, BufferJSON.replacer)
         let sentCount = 0
         for (const client of this.wss.clients) {
             if (client.readyState === 1) { // OPEN
@@ -61,9 +61,6 @@ else
     echo "Note: whatsapp-icon.svg not found, using placeholder"
 fi
 
-# Create directories for runtime data
-mkdir -p "$PACKAGE_DIR/db"
-
 # Copy install script
 echo "Copying install script..."
 cp "$PROJECT_ROOT/install.sh" "$PACKAGE_DIR/"
@@ -143,8 +140,9 @@ update-desktop-database ~/.local/share/applications/
 
 ## Data Storage
 
-- Authentication data: `baileys-backend/auth/`
-- Chat database: `db/client.db`
+- Authentication data: `$XDG_DATA_HOME/org.aryan.whatsappgtk/auth/` (default `~/.local/share/...`)
+- Chat database: `$XDG_DATA_HOME/org.aryan.whatsappgtk/client.db`
+- Media cache: `$XDG_CACHE_HOME/org.aryan.whatsappgtk/` (default `~/.cache/...`)
 - Application logs: Terminal output
 
 ## Uninstallation
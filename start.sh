@@ -13,9 +13,6 @@ if [ ! -d "baileys-backend/node_modules" ]; then
     cd ..
 fi
 
-# Create db directory if it doesn't exist
-mkdir -p db
-
 # Start backend
 echo "Starting backend on port 3000 and WebSocket on port 8787..."
 cd baileys-backend
@@ -0,0 +1,374 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::models::{Account, AccountList, Database, DatabaseKey, Settings, db_key};
+use crate::paths::{self, AppPaths};
+
+/// Bumped whenever the archive layout changes incompatibly
+const FORMAT_VERSION: u32 = 1;
+
+/// `manifest.json` at the root of a backup archive. Every other file in the
+/// archive is listed with its checksum.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    pub app_id: String,
+    pub created_at: i64,
+    pub account_id: String,
+    pub account_name: String,
+    /// Whether `client.db` is encrypted, in which case restoring needs its key
+    pub encrypted: bool,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Relative to the archive root, always with `/` separators
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Write a `.tar.gz` backup of an account: a snapshot of its database, the
+/// backend's credentials and the media cache.
+///
+/// Blocks until done, so run it off the GTK thread.
+pub fn create_backup(
+    paths: &AppPaths,
+    account: &Account,
+    db: &Database,
+    archive: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let staging = staging_dir(paths, "backup")?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        db.backup_to(&staging.join("client.db")).wait()?;
+
+        // Credentials change while the backend runs, so work from a copy
+        let auth_dir = paths.auth_dir(&account.id);
+        if auth_dir.is_dir() {
+            paths::copy_dir(&auth_dir, &staging.join("auth"))?;
+        }
+        // Media files never change once written, so links are enough
+        let media_dir = paths.media_dir(&account.id);
+        if media_dir.is_dir() {
+            link_dir(&media_dir, &staging.join("media"))?;
+        }
+
+        let manifest = Manifest {
+            format: FORMAT_VERSION,
+            app_id: paths::APP_ID.to_string(),
+            created_at: now(),
+            account_id: account.id.clone(),
+            account_name: account.name.clone(),
+            encrypted: db_key::is_encrypted(&staging.join("client.db")),
+            files: list_files(&staging)?,
+        };
+        fs::write(
+            staging.join("manifest.json"),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+
+        run_tar(
+            Command::new("tar")
+                .arg("-czf")
+                .arg(archive)
+                .arg("-C")
+                .arg(&staging)
+                .arg("."),
+        )
+    })();
+
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Restore a backup into this machine's directories and return the account
+/// it was restored to.
+///
+/// The archive goes back into the account with the same id if there is one,
+/// otherwise a new account is created. Data the account already had is kept
+/// next to it with a `.pre-restore` suffix. Must run while the app is closed.
+pub fn restore_backup(
+    paths: &AppPaths,
+    accounts: &mut AccountList,
+    settings: &Settings,
+    archive: &Path,
+) -> Result<Account, Box<dyn std::error::Error>> {
+    let staging = staging_dir(paths, "restore")?;
+    let result = (|| -> Result<Account, Box<dyn std::error::Error>> {
+        run_tar(
+            Command::new("tar")
+                .arg("-xzf")
+                .arg(archive)
+                .arg("-C")
+                .arg(&staging),
+        )?;
+        let manifest = verify(&staging)?;
+
+        let account = match accounts.get(&manifest.account_id) {
+            Some(account) => account.clone(),
+            None => accounts.add(&manifest.account_name),
+        };
+        fs::create_dir_all(paths.account_dir(&account.id))?;
+
+        let database = paths.database(&account.id);
+        for suffix in ["-wal", "-shm"] {
+            let _ = fs::remove_file(paths::append_to_path(&database, suffix));
+        }
+        replace(&staging.join("client.db"), &database)?;
+        replace(&staging.join("auth"), &paths.auth_dir(&account.id))?;
+        let media_dir = paths.media_dir(&account.id);
+        if let Some(parent) = media_dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        replace(&staging.join("media"), &media_dir)?;
+
+        // Cached media was recorded under the old machine's cache directory
+        let key = database_key(settings, &database)?;
+        Database::new(&database, key.as_ref())?
+            .relocate_media(&media_dir)
+            .wait()?;
+
+        Ok(account)
+    })();
+
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Handle `--backup <archive> [account id]`. Returns the exit code.
+pub fn backup_command(
+    paths: &AppPaths,
+    accounts: &AccountList,
+    settings: &Settings,
+    args: &[String],
+) -> i32 {
+    let Some(archive) = args.first() else {
+        eprintln!("usage: whatsapp-frontend --backup <archive.tar.gz> [account id]");
+        return 2;
+    };
+    let account = match args.get(1) {
+        Some(id) => match accounts.get(id) {
+            Some(account) => account,
+            None => {
+                eprintln!("No account with id {}", id);
+                return 1;
+            }
+        },
+        None => accounts.active_account(),
+    };
+
+    let database = paths.database(&account.id);
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let key = database_key(settings, &database)?;
+        let db = Database::new(&database, key.as_ref())?;
+        create_backup(paths, account, &db, Path::new(archive))
+    })();
+    match result {
+        Ok(()) => {
+            println!("Backed up {} to {}", account.name, archive);
+            0
+        }
+        Err(e) => {
+            eprintln!("Backup failed: {}", e);
+            1
+        }
+    }
+}
+
+/// Handle `--restore <archive>`. Returns the exit code.
+pub fn restore_command(
+    paths: &AppPaths,
+    accounts: &mut AccountList,
+    settings: &Settings,
+    args: &[String],
+) -> i32 {
+    let Some(archive) = args.first() else {
+        eprintln!("usage: whatsapp-frontend --restore <archive.tar.gz>");
+        return 2;
+    };
+
+    match restore_backup(paths, accounts, settings, Path::new(archive)) {
+        Ok(account) => {
+            if let Err(e) = accounts.save(&paths.accounts_file()) {
+                eprintln!("Failed to save accounts: {}", e);
+                return 1;
+            }
+            println!("Restored {} from {}", account.name, archive);
+            0
+        }
+        Err(e) => {
+            eprintln!("Restore failed: {}", e);
+            1
+        }
+    }
+}
+
+/// Key for an account's database when running from the command line: the
+/// configured key file, or a passphrase typed on the terminal if the
+/// database is encrypted
+pub fn database_key(settings: &Settings, database: &Path) -> io::Result<Option<DatabaseKey>> {
+    if let Some(file) = &settings.database_key_file {
+        return DatabaseKey::from_file(file).map(Some);
+    }
+    if !db_key::is_encrypted(database) {
+        return Ok(None);
+    }
+
+    eprint!("Passphrase for {}: ", database.display());
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(Some(DatabaseKey::Passphrase(
+        line.trim_end_matches(['\r', '\n']).to_string(),
+    )))
+}
+
+/// Check an unpacked archive against its manifest
+fn verify(root: &Path) -> Result<Manifest, Box<dyn std::error::Error>> {
+    let manifest: Manifest = serde_json::from_str(
+        &fs::read_to_string(root.join("manifest.json"))
+            .map_err(|e| format!("Not a backup archive (no manifest.json): {}", e))?,
+    )?;
+
+    if manifest.app_id != paths::APP_ID {
+        return Err(format!("Backup belongs to {}", manifest.app_id).into());
+    }
+    if manifest.format > FORMAT_VERSION {
+        return Err(format!(
+            "Backup format {} is newer than this version supports",
+            manifest.format
+        )
+        .into());
+    }
+
+    let actual = list_files(root)?;
+    for expected in &manifest.files {
+        match actual.iter().find(|f| f.path == expected.path) {
+            Some(file) if file.sha256 == expected.sha256 && file.size == expected.size => {}
+            Some(_) => return Err(format!("Checksum mismatch for {}", expected.path).into()),
+            None => return Err(format!("Missing {}", expected.path).into()),
+        }
+    }
+    if let Some(extra) = actual
+        .iter()
+        .find(|f| !manifest.files.iter().any(|e| e.path == f.path))
+    {
+        return Err(format!("Unexpected file {}", extra.path).into());
+    }
+    if !manifest.files.iter().any(|f| f.path == "client.db") {
+        return Err("Backup contains no database".into());
+    }
+
+    Ok(manifest)
+}
+
+/// Every file below `root` except the manifest, with its checksum
+fn list_files(root: &Path) -> io::Result<Vec<ManifestFile>> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<ManifestFile>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                walk(root, &path, files)?;
+                continue;
+            }
+
+            let relative = path
+                .strip_prefix(root)
+                .unwrap()
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if relative == "manifest.json" {
+                continue;
+            }
+
+            let mut hasher = Sha256::new();
+            let size = io::copy(&mut fs::File::open(&path)?, &mut hasher)?;
+            files.push(ManifestFile {
+                path: relative,
+                size,
+                sha256: hasher
+                    .finalize()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect(),
+            });
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(root, root, &mut files)?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Scratch directory for packing or unpacking an archive
+fn staging_dir(paths: &AppPaths, purpose: &str) -> io::Result<PathBuf> {
+    let dir = paths
+        .cache_dir
+        .join(format!("{}-{}", purpose, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Hard-link a directory tree, copying where linking isn't possible
+fn link_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_dir(&entry.path(), &target)?;
+        } else if fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Move `from` to `to`, setting aside whatever was at `to`. Does nothing if
+/// the archive had no `from`.
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    if !from.exists() {
+        return Ok(());
+    }
+    if to.exists() {
+        let aside = paths::append_to_path(to, ".pre-restore");
+        if aside.is_dir() {
+            fs::remove_dir_all(&aside)?;
+        } else if aside.exists() {
+            fs::remove_file(&aside)?;
+        }
+        fs::rename(to, &aside)?;
+    }
+    paths::move_path(from, to)
+}
+
+fn run_tar(command: &mut Command) -> Result<(), Box<dyn std::error::Error>> {
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into())
+    }
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
@@ -0,0 +1,277 @@
+//! Offline tool to encrypt, decrypt or re-key a client database.
+//!
+//! Run it while the app is closed:
+//!
+//!     wa-db-crypt encrypt <client.db> [--key-file <new key>]
+//!     wa-db-crypt decrypt <client.db> [--key-file <current key>]
+//!     wa-db-crypt rekey <client.db> [--key-file <current key>] [--new-key-file <new key>]
+//!
+//! Keys not given as files are read as passphrases from standard input.
+
+// Shared with the app so both read key files the same way
+#[allow(dead_code)]
+#[path = "../models/db_key.rs"]
+mod db_key;
+#[cfg(test)]
+#[path = "../test_support.rs"]
+mod test_support;
+
+use db_key::DatabaseKey;
+use rusqlite::Connection;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("wa-db-crypt: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (command, rest) = args.split_first().ok_or(USAGE)?;
+    let (database, options) = rest.split_first().ok_or(USAGE)?;
+    let database = PathBuf::from(database);
+    let key_file = option(options, "--key-file")?;
+    let new_key_file = option(options, "--new-key-file")?;
+
+    if !database.is_file() {
+        return Err(format!("{} does not exist", database.display()).into());
+    }
+
+    match command.as_str() {
+        "encrypt" => {
+            if db_key::is_encrypted(&database) {
+                return Err("database is already encrypted".into());
+            }
+            let key = read_key(key_file, "New passphrase")?;
+            let conn = open(&database, None)?;
+            export(conn, &database, Some(&key))?;
+            println!("Encrypted {}", database.display());
+        }
+        "decrypt" => {
+            let key = read_key(key_file, "Passphrase")?;
+            let conn = open(&database, Some(&key))?;
+            export(conn, &database, None)?;
+            println!("Decrypted {}", database.display());
+        }
+        "rekey" => {
+            let key = read_key(key_file, "Current passphrase")?;
+            let new_key = read_key(new_key_file, "New passphrase")?;
+            let conn = open(&database, Some(&key))?;
+            // SQLCipher can't change the key of a database in WAL mode
+            conn.execute_batch("PRAGMA journal_mode = DELETE;")?;
+            new_key.rekey(&conn)?;
+            conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+            println!("Changed the key of {}", database.display());
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+const USAGE: &str = "usage: wa-db-crypt <encrypt|decrypt|rekey> <database> \
+                     [--key-file <path>] [--new-key-file <path>]";
+
+fn option<'a>(options: &'a [String], name: &str) -> Result<Option<&'a Path>, String> {
+    match options.iter().position(|o| o == name) {
+        Some(i) => options
+            .get(i + 1)
+            .map(|v| Some(Path::new(v.as_str())))
+            .ok_or_else(|| format!("{} needs a value", name)),
+        None => Ok(None),
+    }
+}
+
+fn read_key(file: Option<&Path>, prompt: &str) -> io::Result<DatabaseKey> {
+    if let Some(file) = file {
+        return DatabaseKey::from_file(file);
+    }
+
+    eprint!("{}: ", prompt);
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    let passphrase = line.trim_end_matches(['\r', '\n']);
+    if passphrase.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "empty passphrase",
+        ));
+    }
+    Ok(DatabaseKey::Passphrase(passphrase.to_string()))
+}
+
+/// Open the database and fold its WAL into the main file, so the export
+/// below sees every committed write
+fn open(path: &Path, key: Option<&DatabaseKey>) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    if let Some(key) = key {
+        key.apply(&conn)?;
+    }
+    conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+    Ok(conn)
+}
+
+/// Copy the database into a new file with the given key (or none), then swap
+/// it in place of the original. The original is kept as `<name>.bak` until
+/// the copy is complete.
+fn export(
+    conn: Connection,
+    path: &Path,
+    key: Option<&DatabaseKey>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target = with_suffix(path, ".tmp");
+    let backup = with_suffix(path, ".bak");
+    let _ = fs::remove_file(&target);
+
+    let key_literal = key
+        .map(|k| k.sql_literal())
+        .unwrap_or_else(|| "''".to_string());
+    conn.execute(
+        &format!("ATTACH DATABASE ?1 AS target KEY {}", key_literal),
+        [target.to_string_lossy()],
+    )?;
+    conn.query_row("SELECT sqlcipher_export('target')", [], |_| Ok(()))?;
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    conn.execute_batch(&format!("PRAGMA target.user_version = {};", version))?;
+    conn.execute_batch("DETACH DATABASE target;")?;
+    drop(conn);
+
+    fs::rename(path, &backup)?;
+    fs::rename(&target, path)?;
+    for suffix in ["-wal", "-shm"] {
+        let _ = fs::remove_file(with_suffix(path, suffix));
+    }
+    fs::remove_file(&backup)?;
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+    use test_support::TempDir;
+
+    /// A plaintext database with a little data in it, in a directory of its
+    /// own
+    struct Fixture {
+        dir: TempDir,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = TempDir::new("db-crypt-test");
+            let conn = Connection::open(dir.join("client.db")).unwrap();
+            conn.execute_batch(
+                "PRAGMA journal_mode = WAL;
+                 PRAGMA user_version = 7;
+                 CREATE TABLE messages (message_id TEXT PRIMARY KEY, content TEXT NOT NULL);
+                 INSERT INTO messages VALUES ('A1', 'hello'), ('A2', 'world');",
+            )
+            .unwrap();
+            Self { dir }
+        }
+
+        fn database(&self) -> PathBuf {
+            self.dir.join("client.db")
+        }
+
+        /// Write `contents` to a key file and return its path as an argument
+        fn key_file(&self, name: &str, contents: &str) -> String {
+            let path = self.dir.join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        }
+
+        fn run(&self, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+            let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            args.insert(1, self.database().to_string_lossy().into_owned());
+            run(&args)
+        }
+    }
+
+    /// Read the fixture's messages with `key`, failing if it doesn't unlock
+    /// the database
+    fn messages(path: &Path, key: &DatabaseKey) -> rusqlite::Result<Vec<String>> {
+        let conn = Connection::open(path)?;
+        key.apply(&conn)?;
+        let mut stmt = conn.prepare("SELECT content FROM messages ORDER BY message_id")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    #[test]
+    fn encrypt_then_open_with_key() {
+        let fixture = Fixture::new();
+        let key = fixture.key_file("key", "correct horse\n");
+        assert!(!db_key::is_encrypted(&fixture.database()));
+
+        fixture.run(&["encrypt", "--key-file", &key]).unwrap();
+
+        assert!(db_key::is_encrypted(&fixture.database()));
+        let right = DatabaseKey::Passphrase("correct horse".to_string());
+        assert_eq!(
+            messages(&fixture.database(), &right).unwrap(),
+            ["hello", "world"]
+        );
+        let wrong = DatabaseKey::Passphrase("battery staple".to_string());
+        assert!(messages(&fixture.database(), &wrong).is_err());
+
+        // The schema version survives the export
+        let conn = Connection::open(fixture.database()).unwrap();
+        right.apply(&conn).unwrap();
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 7);
+    }
+
+    #[test]
+    fn rekey_replaces_the_old_key() {
+        let fixture = Fixture::new();
+        let old_key = fixture.key_file("old", "correct horse");
+        let new_key = fixture.key_file("new", &"ab".repeat(32));
+        fixture.run(&["encrypt", "--key-file", &old_key]).unwrap();
+
+        fixture
+            .run(&["rekey", "--key-file", &old_key, "--new-key-file", &new_key])
+            .unwrap();
+
+        assert!(db_key::is_encrypted(&fixture.database()));
+        let old = DatabaseKey::Passphrase("correct horse".to_string());
+        assert!(messages(&fixture.database(), &old).is_err());
+        let new = DatabaseKey::Raw(vec![0xab; 32]);
+        assert_eq!(
+            messages(&fixture.database(), &new).unwrap(),
+            ["hello", "world"]
+        );
+    }
+
+    #[test]
+    fn decrypt_restores_plaintext() {
+        let fixture = Fixture::new();
+        let key = fixture.key_file("key", "correct horse");
+        fixture.run(&["encrypt", "--key-file", &key]).unwrap();
+
+        fixture.run(&["decrypt", "--key-file", &key]).unwrap();
+
+        assert!(!db_key::is_encrypted(&fixture.database()));
+        let conn = Connection::open(fixture.database()).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+}
@@ -1,22 +1,29 @@
+mod backup;
+mod maintenance;
 mod models;
+mod paths;
 mod services;
+mod session;
+#[cfg(test)]
+mod test_support;
 mod ui;
 
 use adw::prelude::*;
+use gtk4::gio;
 use gtk4::prelude::*;
 use libadwaita as adw;
+use std::cell::RefCell;
 use std::path::PathBuf;
 use std::process::{Child, Command};
-use std::sync::Arc;
+use std::rc::Rc;
 use std::sync::Mutex;
 
-use models::Database;
-use services::ws_client::WhatsAppEvent;
-use services::{ApiClient, WebSocketClient};
-use ui::{MainView, QrView};
+use models::{Account, AccountList, Settings};
+use paths::AppPaths;
+use session::AccountSession;
 
-// Global backend process handle
-static BACKEND_PROCESS: Mutex<Option<Child>> = Mutex::new(None);
+// Global backend process handles, one per account
+static BACKEND_PROCESSES: Mutex<Vec<Child>> = Mutex::new(Vec::new());
 
 fn get_backend_path() -> PathBuf {
     // Get the executable path
@@ -40,9 +47,13 @@ fn get_backend_path() -> PathBuf {
     }
 }
 
-fn start_backend() -> Result<Child, std::io::Error> {
+fn start_backend(paths: &AppPaths, account: &Account) -> Result<Child, std::io::Error> {
     let backend_path = get_backend_path();
-    println!("Starting backend from: {}", backend_path.display());
+    println!(
+        "Starting backend for {} from: {}",
+        account.name,
+        backend_path.display()
+    );
 
     // Check if node_modules exists, if not install dependencies
     let node_modules = backend_path.join("node_modules");
@@ -62,49 +73,212 @@ fn start_backend() -> Result<Child, std::io::Error> {
     let child = Command::new("node")
         .arg("server.js")
         .current_dir(&backend_path)
+        .env("WA_AUTH_DIR", paths.auth_dir(&account.id))
+        .env("WA_HTTP_PORT", account.http_port.to_string())
+        .env("WA_WS_PORT", account.ws_port.to_string())
         .spawn()?;
 
     println!("Backend started with PID: {}", child.id());
 
-    // Wait a moment for the server to start
-    std::thread::sleep(std::time::Duration::from_secs(2));
-
     Ok(child)
 }
 
-fn stop_backend() {
-    let mut process = BACKEND_PROCESS.lock().unwrap();
-    if let Some(mut child) = process.take() {
-        println!("Stopping backend process...");
+fn stop_backends() {
+    let mut processes = BACKEND_PROCESSES.lock().unwrap();
+    for mut child in processes.drain(..) {
+        println!("Stopping backend process {}...", child.id());
         let _ = child.kill();
         let _ = child.wait();
-        println!("Backend stopped");
     }
+    println!("Backends stopped");
 }
 
-fn main() {
-    // Start the backend server
-    match start_backend() {
-        Ok(child) => {
-            *BACKEND_PROCESS.lock().unwrap() = Some(child);
+/// Menu entry that switches to `account` through the `app.switch-account` action
+fn account_menu_item(account: &Account) -> gio::MenuItem {
+    let item = gio::MenuItem::new(Some(&account.name), None);
+    item.set_action_and_target_value(Some("app.switch-account"), Some(&account.id.to_variant()));
+    item
+}
+
+/// Ask where to save a backup of `account`, then write it on a worker thread
+fn backup_account(
+    window: &adw::ApplicationWindow,
+    paths: &AppPaths,
+    account: Account,
+    db: std::sync::Arc<models::Database>,
+) {
+    let dialog = gtk4::FileChooserNative::new(
+        Some("Back Up Account"),
+        Some(window),
+        gtk4::FileChooserAction::Save,
+        Some("Save"),
+        Some("Cancel"),
+    );
+    let date = gtk4::glib::DateTime::now_local()
+        .and_then(|now| now.format("%Y-%m-%d"))
+        .map(|s| s.to_string())
+        .unwrap_or_default();
+    dialog.set_current_name(&format!("whatsapp-{}-{}.tar.gz", account.id, date));
+
+    let window = window.clone();
+    let paths = paths.clone();
+    // Native dialogs aren't kept alive by GTK while they're shown
+    let keep_alive = RefCell::new(Some(dialog.clone()));
+    dialog.connect_response(move |dialog, response| {
+        let file = dialog.file().and_then(|f| f.path());
+        dialog.destroy();
+        keep_alive.take();
+        let (gtk4::ResponseType::Accept, Some(archive)) = (response, file) else {
+            return;
+        };
+
+        let window = window.clone();
+        let paths = paths.clone();
+        let account = account.clone();
+        let db = db.clone();
+        gtk4::glib::MainContext::default().spawn_local(async move {
+            println!("[{}] Backing up to {}", account.id, archive.display());
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            {
+                let account = account.clone();
+                let archive = archive.clone();
+                std::thread::spawn(move || {
+                    let result = backup::create_backup(&paths, &account, &db, &archive)
+                        .map_err(|e| e.to_string());
+                    let _ = tx.send(result);
+                });
+            }
+
+            let (heading, body) = match rx.await {
+                Ok(Ok(())) => (
+                    "Backup Complete",
+                    format!("{} was saved to {}", account.name, archive.display()),
+                ),
+                Ok(Err(e)) => {
+                    eprintln!("[{}] Backup failed: {}", account.id, e);
+                    ("Backup Failed", e)
+                }
+                Err(_) => ("Backup Failed", "The backup was interrupted".to_string()),
+            };
+            let message = adw::MessageDialog::new(Some(&window), Some(heading), Some(&body));
+            message.add_response("close", "Close");
+            message.present();
+        });
+    });
+    dialog.show();
+}
+
+/// Open the preferences of `account`, whose database is `db`
+fn show_preferences(
+    window: &adw::ApplicationWindow,
+    account: Account,
+    db: std::sync::Arc<models::Database>,
+) {
+    let preferences = Rc::new(ui::PreferencesWindow::new(window));
+    preferences.connect_repair({
+        let preferences = Rc::downgrade(&preferences);
+        move || {
+            let Some(preferences) = preferences.upgrade() else {
+                return;
+            };
+            let account = account.clone();
+            let db = db.clone();
+            preferences.set_repairing(true);
+            gtk4::glib::MainContext::default().spawn_local(async move {
+                println!("[{}] Checking database", account.id);
+                let result = db.maintain().await;
+                preferences.set_repairing(false);
+                match result {
+                    Ok(report) => {
+                        println!("[{}] Database check finished:\n{}", account.id, report);
+                        let heading = if report.is_damaged() {
+                            "Database Still Damaged"
+                        } else {
+                            "Database Checked"
+                        };
+                        preferences.show_report(heading, &report.to_string());
+                    }
+                    Err(e) => {
+                        eprintln!("[{}] Database check failed: {}", account.id, e);
+                        preferences.show_report("Check Failed", &e.to_string());
+                    }
+                }
+            });
         }
-        Err(e) => {
-            eprintln!("Failed to start backend: {}", e);
-            std::process::exit(1);
+    });
+    preferences.present();
+}
+
+fn main() {
+    // Resolve where our data lives and pick up files from older layouts
+    let paths = AppPaths::resolve();
+    if let Err(e) = paths.ensure_dirs() {
+        eprintln!("Failed to create data directories: {}", e);
+        std::process::exit(1);
+    }
+    paths.migrate_legacy(&get_backend_path());
+
+    let mut accounts = AccountList::load(&paths.accounts_file());
+    let settings = Settings::load(&paths.settings_file());
+
+    // Backups and repairs run without starting the UI or backends
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("--backup") => std::process::exit(backup::backup_command(
+            &paths,
+            &accounts,
+            &settings,
+            &args[2..],
+        )),
+        Some("--restore") => std::process::exit(backup::restore_command(
+            &paths,
+            &mut accounts,
+            &settings,
+            &args[2..],
+        )),
+        Some("--repair-db") => std::process::exit(maintenance::repair_command(
+            &paths,
+            &accounts,
+            &settings,
+            &args[2..],
+        )),
+        _ => {}
+    }
+
+    // Start one backend server per account
+    for account in &accounts.accounts {
+        match start_backend(&paths, account) {
+            Ok(child) => {
+                BACKEND_PROCESSES.lock().unwrap().push(child);
+            }
+            Err(e) => {
+                eprintln!("Failed to start backend: {}", e);
+                stop_backends();
+                std::process::exit(1);
+            }
         }
     }
 
+    // Wait a moment for the servers to start
+    std::thread::sleep(std::time::Duration::from_secs(2));
+
     let app = adw::Application::builder()
-        .application_id("org.aryan.whatsappgtk")
+        .application_id(paths::APP_ID)
         .build();
 
     // Setup cleanup on shutdown
     app.connect_shutdown(|_| {
         println!("Application shutting down...");
-        stop_backend();
+        stop_backends();
     });
 
-    app.connect_activate(|app| {
+    app.connect_activate(move |app| {
+        // A second launch just raises the existing window
+        if let Some(window) = app.active_window() {
+            window.present();
+            return;
+        }
+
         // Load CSS
         let provider = gtk4::CssProvider::new();
         provider.load_from_data(include_str!("../style.css"));
@@ -114,12 +288,6 @@ fn main() {
             gtk4::STYLE_PROVIDER_PRIORITY_APPLICATION,
         );
 
-        // Initialize database
-        let db = Arc::new(Database::new("../db/client.db").expect("Failed to open database"));
-
-        // Initialize API client
-        let api = Arc::new(ApiClient::new("http://localhost:3000"));
-
         // Create main window
         let window = adw::ApplicationWindow::builder()
             .application(app)
@@ -128,459 +296,197 @@ fn main() {
             .default_height(700)
             .build();
 
-        // Check if already authenticated
-        let is_authenticated = db.is_authenticated();
-
-        if is_authenticated {
-            // Show main view
-            let main_view = MainView::new(Arc::clone(&db), Arc::clone(&api));
-
-            // Load contacts from database first
-            main_view.load_contacts();
-
-            let main_view_arc = Arc::new(main_view);
-
-            // Setup WebSocket for receiving messages and contacts
-            let (_ws, rx) = WebSocketClient::new("ws://localhost:8787");
-            let main_view_clone = Arc::clone(&main_view_arc);
-            let db_clone = Arc::clone(&db);
-
-            // Handle incoming WebSocket events
-            glib::timeout_add_local(std::time::Duration::from_millis(100), {
-                let main_view = Arc::clone(&main_view_clone);
-                move || {
-                    while let Ok(event) = rx.try_recv() {
-                        match event {
-                            WhatsAppEvent::Message(msg) => {
-                                println!("[main.rs] Received message event for: {}", msg.key.jid);
-
-                                // Extract message content and type
-                                let (content, message_type, quoted_id, media_url, caption) =
-                                    if let Some(ref msg_data) = msg.message {
-                                        let mut content = String::new();
-                                        let mut msg_type = "unknown".to_string();
-                                        let mut quoted_id = None;
-                                        let mut media_url = None;
-                                        let mut caption = None;
-
-                                        // Handle text messages
-                                        if let Some(text) =
-                                            msg_data.get("conversation").and_then(|v| v.as_str())
-                                        {
-                                            content = text.to_string();
-                                            msg_type = "text".to_string();
-                                        }
-                                        // Handle extended text (with formatting, links, etc.)
-                                        else if let Some(ext_text) =
-                                            msg_data.get("extendedTextMessage")
-                                        {
-                                            if let Some(text) =
-                                                ext_text.get("text").and_then(|v| v.as_str())
-                                            {
-                                                content = text.to_string();
-                                                msg_type = "text".to_string();
-                                            }
-                                            // Check for quoted message
-                                            if let Some(context) = ext_text.get("contextInfo") {
-                                                if let Some(stanza_id) =
-                                                    context.get("stanzaId").and_then(|v| v.as_str())
-                                                {
-                                                    quoted_id = Some(stanza_id.to_string());
-                                                }
-                                            }
-                                        }
-                                        // Handle reactions
-                                        else if let Some(reaction) =
-                                            msg_data.get("reactionMessage")
-                                        {
-                                            if let Some(text) =
-                                                reaction.get("text").and_then(|v| v.as_str())
-                                            {
-                                                content = format!("Reacted with {}", text);
-                                                msg_type = "reaction".to_string();
-                                            }
-                                            if let Some(key) = reaction.get("key") {
-                                                if let Some(msg_id) =
-                                                    key.get("id").and_then(|v| v.as_str())
-                                                {
-                                                    quoted_id = Some(msg_id.to_string());
-                                                }
-                                            }
-                                        }
-                                        // Handle image messages
-                                        else if let Some(image) = msg_data.get("imageMessage") {
-                                            msg_type = "image".to_string();
-                                            content = "[Image]".to_string();
-                                            if let Some(url) =
-                                                image.get("url").and_then(|v| v.as_str())
-                                            {
-                                                media_url = Some(url.to_string());
-                                            }
-                                            if let Some(cap) =
-                                                image.get("caption").and_then(|v| v.as_str())
-                                            {
-                                                caption = Some(cap.to_string());
-                                                content = format!("[Image] {}", cap);
-                                            }
-                                        }
-                                        // Handle video messages
-                                        else if let Some(video) = msg_data.get("videoMessage") {
-                                            msg_type = "video".to_string();
-                                            content = "[Video]".to_string();
-                                            if let Some(url) =
-                                                video.get("url").and_then(|v| v.as_str())
-                                            {
-                                                media_url = Some(url.to_string());
-                                            }
-                                            if let Some(cap) =
-                                                video.get("caption").and_then(|v| v.as_str())
-                                            {
-                                                caption = Some(cap.to_string());
-                                                content = format!("[Video] {}", cap);
-                                            }
-                                        }
-                                        // Handle audio messages
-                                        else if msg_data.get("audioMessage").is_some() {
-                                            msg_type = "audio".to_string();
-                                            content = "[Audio]".to_string();
-                                        }
-                                        // Handle document messages
-                                        else if let Some(doc) = msg_data.get("documentMessage") {
-                                            msg_type = "document".to_string();
-                                            if let Some(filename) =
-                                                doc.get("fileName").and_then(|v| v.as_str())
-                                            {
-                                                content = format!("[Document: {}]", filename);
-                                            } else {
-                                                content = "[Document]".to_string();
-                                            }
-                                        }
-                                        // Handle stickers
-                                        else if msg_data.get("stickerMessage").is_some() {
-                                            msg_type = "sticker".to_string();
-                                            content = "[Sticker]".to_string();
-                                        }
-
-                                        (content, msg_type, quoted_id, media_url, caption)
-                                    } else {
-                                        (
-                                            "[Empty message]".to_string(),
-                                            "unknown".to_string(),
-                                            None,
-                                            None,
-                                            None,
-                                        )
-                                    };
-
-                                // Determine sender
-                                let sender = if msg.key.from_me {
-                                    "me".to_string()
-                                } else {
-                                    msg.key
-                                        .participant
-                                        .as_ref()
-                                        .unwrap_or(&msg.key.jid)
-                                        .split('@')
-                                        .next()
-                                        .unwrap_or("Unknown")
-                                        .to_string()
-                                };
-
-                                // Convert to Message model
-                                let message = models::Message {
-                                    id: None,
-                                    message_id: msg.key.id.clone(),
-                                    jid: msg.key.jid.clone(),
-                                    sender,
-                                    content,
-                                    timestamp: msg.timestamp,
-                                    is_from_me: msg.key.from_me,
-                                    message_type,
-                                    raw_data: Some(
-                                        serde_json::to_string(&msg.message).unwrap_or_default(),
-                                    ),
-                                    quoted_message_id: quoted_id,
-                                    media_url,
-                                    caption,
-                                };
-
-                                // Save to database
-                                if let Err(e) = db_clone.save_message(&message) {
-                                    eprintln!(
-                                        "Failed to save message {}: {}",
-                                        message.message_id, e
-                                    );
-                                } else {
-                                    println!(
-                                        "✅ Saved message: {} in chat {}",
-                                        message.message_id, message.jid
-                                    );
-                                }
-                            }
-                            WhatsAppEvent::Contact(wa_contact) => {
-                                println!(
-                                    "[main.rs] Received Contact: {} ({})",
-                                    wa_contact.name.as_ref().unwrap_or(&wa_contact.id),
-                                    wa_contact.id
-                                );
-
-                                // Convert WAContact to Contact model
-                                let contact = models::Contact {
-                                    jid: wa_contact.id.clone(),
-                                    name: wa_contact.name.or(wa_contact.notify).unwrap_or_else(
-                                        || {
-                                            wa_contact
-                                                .id
-                                                .split('@')
-                                                .next()
-                                                .unwrap_or(&wa_contact.id)
-                                                .to_string()
-                                        },
-                                    ),
-                                    last_message: None,
-                                    last_message_time: None,
-                                    unread_count: 0,
-                                    conversation_timestamp: 0,
-                                    is_group: wa_contact.id.contains("@g.us"),
-                                    archived: false,
-                                    pinned: 0,
-                                    mute_end_time: 0,
-                                    profile_picture_url: None,
-                                };
-
-                                // Save to database
-                                if let Err(e) = db_clone.save_contact(&contact) {
-                                    eprintln!("Failed to save contact {}: {}", contact.jid, e);
-                                } else {
-                                    println!("Saved contact: {}", contact.name);
-                                }
-                            }
-                            WhatsAppEvent::Chat(wa_chat) => {
-                                println!(
-                                    "[main.rs] Received Chat: {} ({})",
-                                    wa_chat.name.as_ref().unwrap_or(&wa_chat.id),
-                                    wa_chat.id
-                                );
-
-                                // Convert WAChat to Contact model
-                                let contact = models::Contact {
-                                    jid: wa_chat.id.clone(),
-                                    name: wa_chat.name.unwrap_or_else(|| {
-                                        wa_chat
-                                            .id
-                                            .split('@')
-                                            .next()
-                                            .unwrap_or(&wa_chat.id)
-                                            .to_string()
-                                    }),
-                                    last_message: None,
-                                    last_message_time: None,
-                                    unread_count: wa_chat.unread_count.unwrap_or(0),
-                                    conversation_timestamp: wa_chat
-                                        .conversation_timestamp
-                                        .unwrap_or(0)
-                                        as i64,
-                                    is_group: wa_chat.id.contains("@g.us"),
-                                    archived: wa_chat.archived.unwrap_or(false),
-                                    pinned: wa_chat.pinned.unwrap_or(0),
-                                    mute_end_time: wa_chat.mute_end_time.unwrap_or(0),
-                                    profile_picture_url: None,
-                                };
-
-                                // Save to database
-                                if let Err(e) = db_clone.save_contact(&contact) {
-                                    eprintln!("Failed to save chat {}: {}", contact.jid, e);
-                                } else {
-                                    println!("Saved chat: {}", contact.name);
-
-                                    // Update UI with the new/updated contact
-                                    if let Ok(contacts) = db_clone.get_contacts() {
-                                        main_view.update_contacts(contacts);
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    glib::Continue(true)
-                }
-            });
+        let accounts = Rc::new(RefCell::new(accounts.clone()));
 
-            // Setup send message handler
-            main_view_arc.setup_send_handler({
-                let api = Arc::clone(&api);
-                let main_view = Arc::clone(&main_view_arc);
-                move |jid, text| {
-                    if let Err(e) = api.send_message(&jid, &text) {
-                        eprintln!("Failed to send message: {}", e);
-                    } else {
-                        // Add message to UI
-                        let timestamp = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs() as i64;
-                        main_view.add_message(&jid, "me", &text, timestamp, true);
+        // Account switcher entries, shared by every account's header bar
+        let accounts_menu = gio::Menu::new();
+        for account in &accounts.borrow().accounts {
+            accounts_menu.append_item(&account_menu_item(account));
+        }
+        accounts_menu.append(Some("Add Account…"), Some("app.add-account"));
+
+        // Privacy switch for online and typing status, followed by every
+        // account's chats. Sessions look it up, so it comes first.
+        let share_presence_action = gio::SimpleAction::new_stateful(
+            "share-presence",
+            None,
+            &settings.share_presence.to_variant(),
+        );
+        share_presence_action.connect_change_state({
+            let paths = paths.clone();
+            move |action, value| {
+                let Some(share) = value.and_then(|v| v.get::<bool>()) else {
+                    return;
+                };
+                action.set_state(&share.to_variant());
+
+                let mut saved = Settings::load(&paths.settings_file());
+                saved.share_presence = share;
+                if let Err(e) = saved.save(&paths.settings_file()) {
+                    eprintln!("Failed to save settings: {}", e);
+                }
+            }
+        });
+        app.add_action(&share_presence_action);
+
+        let sessions: Rc<RefCell<Vec<AccountSession>>> = Rc::new(RefCell::new(
+            accounts
+                .borrow()
+                .accounts
+                .iter()
+                .map(|account| {
+                    AccountSession::new(account.clone(), &paths, &settings, &accounts_menu)
+                })
+                .collect(),
+        ));
+
+        // Make sure queued writes reach disk before the process exits
+        app.connect_shutdown({
+            let sessions = Rc::clone(&sessions);
+            move |_| {
+                for session in sessions.borrow().iter() {
+                    let Some(db) = session.db.get() else {
+                        continue;
+                    };
+                    if let Err(e) = db.flush().wait() {
+                        eprintln!(
+                            "Failed to flush database for {}: {}",
+                            session.account.name, e
+                        );
                     }
                 }
-            });
-
-            window.set_content(Some(&main_view_arc.widget));
-        } else {
-            // Show QR view
-            let qr_view = Arc::new(QrView::new());
-            window.set_content(Some(&qr_view.widget));
-
-            // Request QR code from backend
-            let api_clone_qr = Arc::clone(&api);
-            std::thread::spawn(move || {
-                println!("Requesting QR code from backend...");
-                if let Err(e) = api_clone_qr.request_qr() {
-                    eprintln!("Failed to request QR code: {}", e);
+            }
+        });
+
+        // Nobody is left to hear back from requests still running
+        window.connect_close_request({
+            let sessions = Rc::clone(&sessions);
+            move |_| {
+                for session in sessions.borrow().iter() {
+                    session.api.cancel();
                 }
-            });
+                gtk4::glib::Propagation::Proceed
+            }
+        });
+
+        let active_id = accounts.borrow().active_account().id.clone();
+        let switch_action = gio::SimpleAction::new_stateful(
+            "switch-account",
+            Some(gtk4::glib::VariantTy::STRING),
+            &active_id.to_variant(),
+        );
+        switch_action.connect_activate({
+            let window = window.clone();
+            let sessions = Rc::clone(&sessions);
+            let accounts = Rc::clone(&accounts);
+            let paths = paths.clone();
+            move |action, parameter| {
+                let Some(id) = parameter.and_then(|p| p.get::<String>()) else {
+                    return;
+                };
+                let sessions = sessions.borrow();
+                let Some(session) = sessions.iter().find(|s| s.account.id == id) else {
+                    return;
+                };
+
+                println!("Switching to account {}", session.account.name);
+                window.set_content(Some(&session.root));
+                window.present();
+                action.set_state(&id.to_variant());
+
+                let mut accounts = accounts.borrow_mut();
+                accounts.active = id;
+                if let Err(e) = accounts.save(&paths.accounts_file()) {
+                    eprintln!("Failed to save accounts: {}", e);
+                }
+            }
+        });
+        app.add_action(&switch_action);
+
+        let add_action = gio::SimpleAction::new("add-account", None);
+        add_action.connect_activate({
+            let window = window.clone();
+            let sessions = Rc::clone(&sessions);
+            let accounts = Rc::clone(&accounts);
+            let accounts_menu = accounts_menu.clone();
+            let switch_action = switch_action.clone();
+            let paths = paths.clone();
+            let settings = settings.clone();
+            move |_, _| {
+                let sessions = Rc::clone(&sessions);
+                let accounts = Rc::clone(&accounts);
+                let accounts_menu = accounts_menu.clone();
+                let switch_action = switch_action.clone();
+                let paths = paths.clone();
+                let settings = settings.clone();
+                ui::prompt_account_name(&window, move |name| {
+                    let account = accounts.borrow_mut().add(&name);
+                    if let Err(e) = accounts.borrow().save(&paths.accounts_file()) {
+                        eprintln!("Failed to save accounts: {}", e);
+                    }
 
-            // Setup WebSocket for QR code
-            let (_ws, rx) = WebSocketClient::new("ws://localhost:8787");
-            let qr_view_clone = Arc::clone(&qr_view);
-            let db_clone = Arc::clone(&db);
-            let api_clone = Arc::clone(&api);
-            let window_clone = window.clone();
-
-            glib::timeout_add_local(std::time::Duration::from_millis(100), move || {
-                while let Ok(event) = rx.try_recv() {
-                    match event {
-                        WhatsAppEvent::QrCode(qr) => {
-                            println!("QR Code received from backend");
-                            qr_view_clone.show_qr(&qr);
-                        }
-                        WhatsAppEvent::Connected => {
-                            println!("Connected to WhatsApp!");
-                            qr_view_clone.show_connecting();
-
-                            // Mark as authenticated
-                            let _ = db_clone.set_authenticated(true);
-
-                            // Transition to main view - events will populate contacts via WebSocket
-                            let main_view = Arc::new(MainView::new(
-                                Arc::clone(&db_clone),
-                                Arc::clone(&api_clone),
-                            ));
-
-                            main_view.load_contacts();
-
-                            // Create a NEW WebSocket connection for the main view
-                            let (_ws_main, rx_main) = WebSocketClient::new("ws://localhost:8787");
-                            let main_view_clone = Arc::clone(&main_view);
-                            let db_clone_ws = Arc::clone(&db_clone);
-
-                            glib::timeout_add_local(
-                                std::time::Duration::from_millis(100),
-                                move || {
-                                    while let Ok(event) = rx_main.try_recv() {
-                                        match event {
-                                            WhatsAppEvent::Chat(wa_chat) => {
-                                                println!(
-                                                    "Received chat: {} ({})",
-                                                    wa_chat.name.as_ref().unwrap_or(&wa_chat.id),
-                                                    wa_chat.id
-                                                );
-
-                                                let contact = models::Contact {
-                                                    jid: wa_chat.id.clone(),
-                                                    name: wa_chat.name.unwrap_or_else(|| {
-                                                        wa_chat
-                                                            .id
-                                                            .split('@')
-                                                            .next()
-                                                            .unwrap_or(&wa_chat.id)
-                                                            .to_string()
-                                                    }),
-                                                    last_message: None,
-                                                    last_message_time: None,
-                                                    unread_count: wa_chat.unread_count.unwrap_or(0),
-                                                    conversation_timestamp: wa_chat
-                                                        .conversation_timestamp
-                                                        .unwrap_or(0)
-                                                        as i64,
-                                                    is_group: wa_chat.id.contains("@g.us"),
-                                                    archived: wa_chat.archived.unwrap_or(false),
-                                                    pinned: wa_chat.pinned.unwrap_or(0),
-                                                    mute_end_time: wa_chat
-                                                        .mute_end_time
-                                                        .unwrap_or(0),
-                                                    profile_picture_url: None,
-                                                };
-
-                                                if let Err(e) = db_clone_ws.save_contact(&contact) {
-                                                    eprintln!(
-                                                        "Failed to save chat {}: {}",
-                                                        contact.jid, e
-                                                    );
-                                                } else {
-                                                    println!("Saved chat: {}", contact.name);
-                                                    if let Ok(contacts) = db_clone_ws.get_contacts()
-                                                    {
-                                                        main_view_clone.update_contacts(contacts);
-                                                    }
-                                                }
-                                            }
-                                            WhatsAppEvent::Contact(wa_contact) => {
-                                                println!(
-                                                    "Received contact: {} ({})",
-                                                    wa_contact
-                                                        .name
-                                                        .as_ref()
-                                                        .unwrap_or(&wa_contact.id),
-                                                    wa_contact.id
-                                                );
-
-                                                let contact = models::Contact {
-                                                    jid: wa_contact.id.clone(),
-                                                    name: wa_contact
-                                                        .name
-                                                        .or(wa_contact.notify)
-                                                        .unwrap_or_else(|| {
-                                                            wa_contact
-                                                                .id
-                                                                .split('@')
-                                                                .next()
-                                                                .unwrap_or(&wa_contact.id)
-                                                                .to_string()
-                                                        }),
-                                                    last_message: None,
-                                                    last_message_time: None,
-                                                    unread_count: 0,
-                                                    conversation_timestamp: 0,
-                                                    is_group: wa_contact.id.contains("@g.us"),
-                                                    archived: false,
-                                                    pinned: 0,
-                                                    mute_end_time: 0,
-                                                    profile_picture_url: None,
-                                                };
-
-                                                if let Err(e) = db_clone_ws.save_contact(&contact) {
-                                                    eprintln!("Failed to save contact: {}", e);
-                                                } else {
-                                                    println!("Saved contact: {}", contact.name);
-                                                }
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                    glib::Continue(true)
-                                },
-                            );
-
-                            window_clone.set_content(Some(&main_view.widget));
-                        }
-                        _ => {}
+                    match start_backend(&paths, &account) {
+                        Ok(child) => BACKEND_PROCESSES.lock().unwrap().push(child),
+                        Err(e) => eprintln!("Failed to start backend for {}: {}", account.name, e),
                     }
-                }
-                glib::Continue(true)
-            });
-        }
 
-        window.present();
+                    // Keep "Add Account…" as the last entry
+                    accounts_menu
+                        .insert_item(accounts_menu.n_items() - 1, &account_menu_item(&account));
+                    sessions.borrow_mut().push(AccountSession::new(
+                        account.clone(),
+                        &paths,
+                        &settings,
+                        &accounts_menu,
+                    ));
+                    switch_action.activate(Some(&account.id.to_variant()));
+                });
+            }
+        });
+        app.add_action(&add_action);
+
+        let backup_action = gio::SimpleAction::new("backup", None);
+        backup_action.connect_activate({
+            let window = window.clone();
+            let sessions = Rc::clone(&sessions);
+            let accounts = Rc::clone(&accounts);
+            let paths = paths.clone();
+            move |_, _| {
+                let active = accounts.borrow().active.clone();
+                let sessions = sessions.borrow();
+                let Some(session) = sessions.iter().find(|s| s.account.id == active) else {
+                    return;
+                };
+                let Some(db) = session.db.get().cloned() else {
+                    // Still waiting to be unlocked
+                    return;
+                };
+                backup_account(&window, &paths, session.account.clone(), db);
+            }
+        });
+        app.add_action(&backup_action);
+
+        let preferences_action = gio::SimpleAction::new("preferences", None);
+        preferences_action.connect_activate({
+            let window = window.clone();
+            let sessions = Rc::clone(&sessions);
+            let accounts = Rc::clone(&accounts);
+            move |_, _| {
+                let active = accounts.borrow().active.clone();
+                let sessions = sessions.borrow();
+                let Some(session) = sessions.iter().find(|s| s.account.id == active) else {
+                    return;
+                };
+                let Some(db) = session.db.get().cloned() else {
+                    // Still waiting to be unlocked
+                    return;
+                };
+                show_preferences(&window, session.account.clone(), db);
+            }
+        });
+        window.add_action(&preferences_action);
+
+        switch_action.activate(Some(&active_id.to_variant()));
     });
 
     app.run();
@@ -0,0 +1,46 @@
+use crate::backup;
+use crate::models::{AccountList, Database, Settings};
+use crate::paths::AppPaths;
+
+/// Handle `--repair-db [account id]`: check and repair an account's database
+/// and print the report. Returns the exit code.
+pub fn repair_command(
+    paths: &AppPaths,
+    accounts: &AccountList,
+    settings: &Settings,
+    args: &[String],
+) -> i32 {
+    let account = match args.first() {
+        Some(id) => match accounts.get(id) {
+            Some(account) => account,
+            None => {
+                eprintln!("No account with id {}", id);
+                return 1;
+            }
+        },
+        None => accounts.active_account(),
+    };
+
+    let database = paths.database(&account.id);
+    if !database.exists() {
+        eprintln!("{} has no database at {}", account.name, database.display());
+        return 1;
+    }
+    println!("Checking {} ({})", account.name, database.display());
+
+    let result = (|| -> Result<_, Box<dyn std::error::Error>> {
+        let key = backup::database_key(settings, &database)?;
+        let db = Database::new(&database, key.as_ref())?;
+        Ok(db.maintain().wait()?)
+    })();
+    match result {
+        Ok(report) => {
+            println!("{}", report);
+            if report.is_damaged() { 1 } else { 0 }
+        }
+        Err(e) => {
+            eprintln!("Repair failed: {}", e);
+            1
+        }
+    }
+}
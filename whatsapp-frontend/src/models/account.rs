@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Id of the account created on first launch. It keeps the original
+/// single-account file layout.
+pub const DEFAULT_ACCOUNT_ID: &str = "default";
+
+/// One linked WhatsApp number with its own backend process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    pub http_port: u16,
+    pub ws_port: u16,
+}
+
+impl Account {
+    pub fn api_url(&self) -> String {
+        format!("http://localhost:{}", self.http_port)
+    }
+
+    pub fn ws_url(&self) -> String {
+        format!("ws://localhost:{}", self.ws_port)
+    }
+}
+
+/// Configured accounts and the one shown last, stored as `accounts.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountList {
+    pub accounts: Vec<Account>,
+    pub active: String,
+}
+
+impl Default for AccountList {
+    fn default() -> Self {
+        Self {
+            accounts: vec![Account {
+                id: DEFAULT_ACCOUNT_ID.to_string(),
+                name: "Personal".to_string(),
+                http_port: 3000,
+                ws_port: 8787,
+            }],
+            active: DEFAULT_ACCOUNT_ID.to_string(),
+        }
+    }
+}
+
+impl AccountList {
+    /// Read the account list, falling back to the single default account
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(json) => match serde_json::from_str::<Self>(&json) {
+                Ok(list) if !list.accounts.is_empty() => list,
+                Ok(_) => Self::default(),
+                Err(e) => {
+                    eprintln!("Ignoring invalid {}: {}", path.display(), e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Account> {
+        self.accounts.iter().find(|a| a.id == id)
+    }
+
+    /// The active account, or the first one if the saved id is stale
+    pub fn active_account(&self) -> &Account {
+        self.get(&self.active).unwrap_or(&self.accounts[0])
+    }
+
+    /// Create a new account with its own id and the next free pair of ports
+    pub fn add(&mut self, name: &str) -> Account {
+        let mut n = self.accounts.len() + 1;
+        while self.get(&format!("account{}", n)).is_some() {
+            n += 1;
+        }
+
+        let account = Account {
+            id: format!("account{}", n),
+            name: name.to_string(),
+            http_port: self
+                .accounts
+                .iter()
+                .map(|a| a.http_port)
+                .max()
+                .unwrap_or(2999)
+                + 1,
+            ws_port: self
+                .accounts
+                .iter()
+                .map(|a| a.ws_port)
+                .max()
+                .unwrap_or(8786)
+                + 1,
+        };
+        self.accounts.push(account.clone());
+        account
+    }
+}
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// Partial update of a chat's state. Fields left as `None` keep their stored
+/// value, so events carrying only some fields don't reset the rest.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatUpdate {
+    pub jid: String,
+    /// Chat title, e.g. a group subject
+    pub name: Option<String>,
+    /// -1 marks the chat as unread without a count
+    pub unread_count: Option<i32>,
+    /// Messages to add to the unread count, from events that report what
+    /// just arrived rather than the total
+    pub unread_increment: Option<i32>,
+    pub conversation_timestamp: Option<i64>,
+    pub archived: Option<bool>,
+    pub pinned: Option<i64>,
+    pub mute_end_time: Option<i64>,
+    /// Lifetime of disappearing messages in seconds, 0 when turned off
+    pub ephemeral_expiration: Option<i64>,
+    /// When disappearing messages were last turned on or off
+    pub ephemeral_setting_timestamp: Option<i64>,
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 
+/// A sidebar entry: who the contact is merged with the state of the chat
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Contact {
     pub jid: String,
@@ -8,6 +9,13 @@ pub struct Contact {
     pub last_message: Option<String>,
     #[serde(default)]
     pub last_message_time: Option<i64>,
+    /// Sender of the last message, "me" for our own
+    #[serde(default)]
+    pub last_message_sender: Option<String>,
+    /// Message type of the last message (text, image, ...)
+    #[serde(default)]
+    pub last_message_type: Option<String>,
+    /// -1 for a chat marked as unread, as WhatsApp has it
     #[serde(rename = "unreadCount", default)]
     pub unread_count: i32,
     #[serde(rename = "conversationTimestamp", default)]
@@ -22,6 +30,24 @@ pub struct Contact {
     pub mute_end_time: i64,
     #[serde(default)]
     pub profile_picture_url: Option<String>,
+    /// Unsent text typed in this chat, kept in the drafts table
+    #[serde(default)]
+    pub draft: Option<String>,
+    /// Ids of the folders the chat is in
+    #[serde(default)]
+    pub folders: Vec<i64>,
+}
+
+/// Partial update of who a contact is. Fields left as `None` keep their
+/// stored value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContactUpdate {
+    pub jid: String,
+    /// Name saved in the phone's address book
+    pub name: Option<String>,
+    /// Name the contact chose for themselves
+    pub notify: Option<String>,
+    pub profile_picture_url: Option<String>,
 }
 
 impl Contact {
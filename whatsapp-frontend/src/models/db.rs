@@ -1,15 +1,177 @@
-use super::{Contact, Message};
-use rusqlite::{Connection, Result, params};
+use super::db_key::{self, DatabaseKey};
+use super::{
+    Attachment, ChatUpdate, Contact, ContactUpdate, Folder, MaintenanceReport, MediaEntry, Message,
+    MessageEdit, OutboxEntry, Reaction, RetentionPolicy, SendState,
+};
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::{Connection, OpenFlags, Result, ffi, params};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::oneshot;
 
+/// Number of read-only connections serving UI queries
+const READ_CONNECTIONS: usize = 3;
+/// Upper bound on writes committed together in one transaction
+const MAX_WRITE_BATCH: usize = 512;
+
+/// Columns read by `message_from_row`, for a query over `messages m`. An
+/// edited message shows the text of its latest edit, and one still in the
+/// outbox its send state.
+const MESSAGE_COLUMNS: &str = "m.id, m.message_id, m.jid, m.sender,
+    COALESCE((SELECT e.content FROM message_edits e WHERE e.message_id = m.message_id ORDER BY e.edited_at DESC LIMIT 1), m.content),
+    m.timestamp, m.is_from_me, m.message_type, m.raw_data, m.quoted_message_id, m.media_url, m.caption, m.expires_at, m.starred,
+    (SELECT MAX(e.edited_at) FROM message_edits e WHERE e.message_id = m.message_id),
+    (SELECT o.state FROM outbox o WHERE o.message_id = m.message_id)";
+
+/// Tables whose rows belong to a row elsewhere, and the condition matching
+/// those whose parent is gone. Every message creates its chat, so messages
+/// without one were left by a crash.
+const ORPHAN_CHECKS: &[(&str, &str)] = &[
+    ("messages", "jid NOT IN (SELECT jid FROM chats)"),
+    (
+        "message_edits",
+        "message_id NOT IN (SELECT message_id FROM messages WHERE message_id IS NOT NULL)",
+    ),
+    (
+        "reactions",
+        "message_id NOT IN (SELECT message_id FROM messages WHERE message_id IS NOT NULL)",
+    ),
+    ("chat_folders", "folder_id NOT IN (SELECT id FROM folders)"),
+    (
+        "outbox",
+        "message_id NOT IN (SELECT message_id FROM messages WHERE message_id IS NOT NULL)",
+    ),
+];
+
+type ReadJob = Box<dyn FnOnce(&Connection) + Send>;
+type BatchedJob = Box<dyn FnOnce(&Connection) -> (bool, WriteReply) + Send>;
+type WriteReply = Box<dyn FnOnce(Result<()>) + Send>;
+
+enum WriteJob {
+    /// Runs in a savepoint of the current batch's transaction
+    Batched(BatchedJob),
+    /// Runs alone, outside of any transaction, between two batches
+    Exclusive(Box<dyn FnOnce(&mut Connection) + Send>),
+}
+
+/// Handle to the SQLite database.
+///
+/// All writes go through a single background writer thread that batches
+/// queued work into one transaction, and reads are served by a small pool of
+/// read-only connections. The database runs in WAL mode so readers never wait
+/// for the writer. Every query returns a [`Pending`] result instead of blocking
+/// the caller.
 pub struct Database {
-    conn: Arc<Mutex<Connection>>,
+    writer: mpsc::Sender<WriteJob>,
+    readers: mpsc::Sender<ReadJob>,
+    key: Option<DatabaseKey>,
+}
+
+/// Result of a query or write that runs on a database thread.
+///
+/// Await it from `glib::MainContext::spawn_local`, block on it with
+/// [`Pending::wait`], or drop it to fire and forget; the work is queued as soon
+/// as the `Pending` is created either way.
+pub struct Pending<T> {
+    rx: oneshot::Receiver<Result<T>>,
+}
+
+impl<T> Pending<T> {
+    /// Block the current thread until the result is available.
+    ///
+    /// Only meant for startup and shutdown, never from a GTK callback.
+    pub fn wait(self) -> Result<T> {
+        self.rx.blocking_recv().unwrap_or_else(|_| Err(dropped()))
+    }
+}
+
+impl<T> Future for Pending<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|r| r.unwrap_or_else(|_| Err(dropped())))
+    }
+}
+
+fn dropped() -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        ffi::Error::new(ffi::SQLITE_ABORT),
+        Some("database request was dropped".to_string()),
+    )
 }
 
 impl Database {
-    pub fn new(path: &str) -> Result<Self> {
+    /// Open the database at `path`, unlocking it with `key` if it is
+    /// encrypted. A new database created with a key is encrypted from the
+    /// start.
+    pub fn new<P: AsRef<Path>>(path: P, key: Option<&DatabaseKey>) -> Result<Self> {
+        let path = path.as_ref();
         let conn = Connection::open(path)?;
+        Self::unlock(&conn, key)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;",
+        )?;
+        Self::create_schema(&conn)?;
+
+        // Readers are opened after the schema exists so they all see it
+        let (read_tx, read_rx) = mpsc::channel::<ReadJob>();
+        let read_rx = Arc::new(Mutex::new(read_rx));
+        for i in 0..READ_CONNECTIONS {
+            let reader = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            Self::unlock(&reader, key)?;
+            reader.busy_timeout(Duration::from_secs(5))?;
+            let jobs = Arc::clone(&read_rx);
+            thread::Builder::new()
+                .name(format!("db-reader-{}", i))
+                .spawn(move || Self::run_reader(reader, jobs))
+                .expect("Failed to spawn database reader thread");
+        }
+
+        let (write_tx, write_rx) = mpsc::channel::<WriteJob>();
+        thread::Builder::new()
+            .name("db-writer".to_string())
+            .spawn(move || Self::run_writer(conn, write_rx))
+            .expect("Failed to spawn database writer thread");
+
+        Ok(Self {
+            writer: write_tx,
+            readers: read_tx,
+            key: key.cloned(),
+        })
+    }
 
+    fn unlock(conn: &Connection, key: Option<&DatabaseKey>) -> Result<()> {
+        if let Some(key) = key {
+            // Without SQLCipher `PRAGMA key` is silently ignored, which would
+            // leave the data in plaintext
+            if !db_key::encryption_available(conn) {
+                return Err(rusqlite::Error::SqliteFailure(
+                    ffi::Error::new(ffi::SQLITE_MISUSE),
+                    Some("built without database encryption support".to_string()),
+                ));
+            }
+            key.apply(conn)?;
+        }
+
+        // A wrong key only shows up once the file is read
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |_| Ok(()))
+    }
+
+    fn create_schema(conn: &Connection) -> Result<()> {
         conn.execute(
             "CREATE TABLE IF NOT EXISTS contacts (
                 jid TEXT PRIMARY KEY,
@@ -32,6 +194,44 @@ impl Database {
             "ALTER TABLE contacts ADD COLUMN profile_picture_url TEXT",
             [],
         );
+        let _ = conn.execute("ALTER TABLE contacts ADD COLUMN notify TEXT", []);
+
+        // Chat state used to live in the contacts table, where every contact
+        // update reset it. The old columns are left in place but no longer
+        // read; an empty name means the contact's name is not known.
+        let has_chats: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'chats'",
+            [],
+            |row| row.get(0),
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chats (
+                jid TEXT PRIMARY KEY,
+                name TEXT,
+                last_message TEXT,
+                last_message_time INTEGER,
+                unread_count INTEGER NOT NULL DEFAULT 0,
+                conversation_timestamp INTEGER NOT NULL DEFAULT 0,
+                archived BOOLEAN NOT NULL DEFAULT 0,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                mute_end_time INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+        if !has_chats {
+            conn.execute(
+                "INSERT INTO chats (jid, last_message, last_message_time, unread_count, conversation_timestamp, archived, pinned, mute_end_time)
+                 SELECT jid, last_message, last_message_time, COALESCE(unread_count, 0), COALESCE(conversation_timestamp, 0), COALESCE(archived, 0), COALESCE(pinned, 0), COALESCE(mute_end_time, 0)
+                 FROM contacts",
+                [],
+            )?;
+            // Names that were only the number filled in as a fallback
+            conn.execute(
+                "UPDATE contacts SET name = ''
+                 WHERE instr(jid, '@') > 0 AND name = substr(jid, 1, instr(jid, '@') - 1)",
+                [],
+            )?;
+        }
 
         conn.execute(
             "CREATE TABLE IF NOT EXISTS messages (
@@ -46,7 +246,9 @@ impl Database {
                 raw_data TEXT,
                 quoted_message_id TEXT,
                 media_url TEXT,
-                caption TEXT
+                caption TEXT,
+                expires_at INTEGER,
+                starred BOOLEAN NOT NULL DEFAULT 0
             )",
             [],
         )?;
@@ -61,6 +263,224 @@ impl Database {
         let _ = conn.execute("ALTER TABLE messages ADD COLUMN quoted_message_id TEXT", []);
         let _ = conn.execute("ALTER TABLE messages ADD COLUMN media_url TEXT", []);
         let _ = conn.execute("ALTER TABLE messages ADD COLUMN caption TEXT", []);
+        let _ = conn.execute("ALTER TABLE messages ADD COLUMN expires_at INTEGER", []);
+        let _ = conn.execute(
+            "ALTER TABLE messages ADD COLUMN starred BOOLEAN NOT NULL DEFAULT 0",
+            [],
+        );
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_messages_jid_timestamp ON messages (jid, timestamp)",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_messages_expires_at ON messages (expires_at)
+             WHERE expires_at IS NOT NULL",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_messages_starred ON messages (timestamp)
+             WHERE starred",
+            [],
+        )?;
+
+        // Each chat keeps a copy of its newest message for the sidebar. The
+        // triggers below keep it current whenever messages change.
+        let added_preview = conn
+            .execute("ALTER TABLE chats ADD COLUMN last_message_sender TEXT", [])
+            .is_ok();
+        let _ = conn.execute("ALTER TABLE chats ADD COLUMN last_message_type TEXT", []);
+        let _ = conn.execute(
+            "ALTER TABLE chats ADD COLUMN ephemeral_expiration INTEGER NOT NULL DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE chats ADD COLUMN ephemeral_setting_timestamp INTEGER NOT NULL DEFAULT 0",
+            [],
+        );
+
+        // Every version of an edited message, keyed by the original's id
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS message_edits (
+                message_id TEXT NOT NULL,
+                jid TEXT NOT NULL,
+                content TEXT NOT NULL,
+                edited_at INTEGER NOT NULL,
+                raw_data TEXT,
+                PRIMARY KEY (message_id, edited_at)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_message_edits_jid ON message_edits (jid)",
+            [],
+        )?;
+
+        const REFRESH_PREVIEW: &str = "UPDATE chats
+                SET (last_message, last_message_time, last_message_sender, last_message_type) = (
+                    SELECT COALESCE((
+                               SELECT e.content FROM message_edits e
+                               WHERE e.message_id = messages.message_id
+                               ORDER BY e.edited_at DESC LIMIT 1
+                           ), content),
+                           timestamp, sender, message_type
+                    FROM messages
+                    WHERE messages.jid = chats.jid
+                    ORDER BY timestamp DESC, id DESC
+                    LIMIT 1
+                )";
+        // Recreated on every start so older databases pick up changes to them
+        conn.execute_batch(&format!(
+            "DROP TRIGGER IF EXISTS messages_preview_insert;
+             DROP TRIGGER IF EXISTS messages_preview_update;
+             DROP TRIGGER IF EXISTS messages_preview_delete;
+             DROP TRIGGER IF EXISTS message_edits_preview;
+             CREATE TRIGGER messages_preview_insert AFTER INSERT ON messages BEGIN
+                 INSERT INTO chats (jid, conversation_timestamp) VALUES (NEW.jid, NEW.timestamp)
+                 ON CONFLICT (jid) DO UPDATE
+                 SET conversation_timestamp = MAX(conversation_timestamp, NEW.timestamp);
+                 {refresh} WHERE jid = NEW.jid;
+             END;
+             CREATE TRIGGER messages_preview_update AFTER UPDATE ON messages BEGIN
+                 {refresh} WHERE jid IN (OLD.jid, NEW.jid);
+             END;
+             CREATE TRIGGER messages_preview_delete AFTER DELETE ON messages BEGIN
+                 {refresh} WHERE jid = OLD.jid;
+             END;
+             CREATE TRIGGER message_edits_preview AFTER INSERT ON message_edits BEGIN
+                 {refresh} WHERE jid = NEW.jid;
+             END;",
+            refresh = REFRESH_PREVIEW
+        ))?;
+
+        // Fill in previews for messages stored before the triggers existed
+        if added_preview {
+            conn.execute_batch(&format!(
+                "INSERT INTO chats (jid) SELECT DISTINCT jid FROM messages WHERE true
+                 ON CONFLICT (jid) DO NOTHING;
+                 UPDATE chats SET conversation_timestamp = MAX(
+                     conversation_timestamp,
+                     COALESCE((SELECT MAX(timestamp) FROM messages WHERE messages.jid = chats.jid), 0)
+                 );
+                 {};",
+                REFRESH_PREVIEW
+            ))?;
+        }
+
+        let has_reactions: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'reactions'",
+            [],
+            |row| row.get(0),
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS reactions (
+                message_id TEXT NOT NULL,
+                jid TEXT NOT NULL,
+                reactor TEXT NOT NULL,
+                emoji TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                PRIMARY KEY (message_id, reactor)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_reactions_jid ON reactions (jid)",
+            [],
+        )?;
+
+        // Reactions used to be stored as "Reacted with <emoji>" message rows
+        // pointing at their target through quoted_message_id
+        if !has_reactions {
+            conn.execute_batch(
+                "INSERT OR REPLACE INTO reactions (message_id, jid, reactor, emoji, timestamp)
+                     SELECT quoted_message_id, jid, sender, substr(content, 14), MAX(timestamp)
+                     FROM messages
+                     WHERE message_type = 'reaction' AND quoted_message_id IS NOT NULL
+                     GROUP BY quoted_message_id, sender;
+                 DELETE FROM reactions WHERE emoji = '';
+                 DELETE FROM messages WHERE message_type = 'reaction';",
+            )?;
+        }
+
+        // Messages we sent that haven't reached WhatsApp yet, in the order
+        // they were written. Each also has its row in messages.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS outbox (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id TEXT NOT NULL UNIQUE,
+                jid TEXT NOT NULL,
+                text TEXT NOT NULL,
+                state TEXT NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT
+            )",
+            [],
+        )?;
+        // Attachments are read from where they were picked when sent
+        let _ = conn.execute("ALTER TABLE outbox ADD COLUMN media_path TEXT", []);
+        let _ = conn.execute("ALTER TABLE outbox ADD COLUMN mime_type TEXT", []);
+        let _ = conn.execute("ALTER TABLE outbox ADD COLUMN media_size INTEGER", []);
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS drafts (
+                jid TEXT PRIMARY KEY,
+                text TEXT NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // Per-chat overrides of the global retention policy. A row with
+        // neither limit keeps the chat's messages forever.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chat_retention (
+                jid TEXT PRIMARY KEY,
+                keep_days INTEGER,
+                keep_messages INTEGER
+            )",
+            [],
+        )?;
+
+        // Folders of chats. Those with a label_id mirror a WhatsApp Business
+        // label and are kept in sync with it.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS folders (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                label_id TEXT UNIQUE
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chat_folders (
+                folder_id INTEGER NOT NULL,
+                jid TEXT NOT NULL,
+                PRIMARY KEY (folder_id, jid)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_chat_folders_jid ON chat_folders (jid)",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS media (
+                sha256 TEXT PRIMARY KEY,
+                mime_type TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                width INTEGER,
+                height INTEGER,
+                local_path TEXT NOT NULL,
+                last_accessed INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_media_last_accessed ON media (last_accessed)",
+            [],
+        )?;
 
         conn.execute(
             "CREATE TABLE IF NOT EXISTS session (
@@ -70,78 +490,390 @@ impl Database {
             [],
         )?;
 
-        Ok(Self {
-            conn: Arc::new(Mutex::new(conn)),
+        Ok(())
+    }
+
+    fn run_reader(conn: Connection, jobs: Arc<Mutex<mpsc::Receiver<ReadJob>>>) {
+        loop {
+            // Hold the lock only while waiting for the next job
+            let job = match jobs.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => break,
+            };
+            job(&conn);
+        }
+    }
+
+    fn run_writer(mut conn: Connection, jobs: mpsc::Receiver<WriteJob>) {
+        let mut next = jobs.recv().ok();
+        while let Some(job) = next.take() {
+            let first = match job {
+                WriteJob::Batched(job) => job,
+                WriteJob::Exclusive(job) => {
+                    job(&mut conn);
+                    next = jobs.recv().ok();
+                    continue;
+                }
+            };
+
+            // Everything queued while the previous batch was committing goes
+            // into this one, so a history sync costs one fsync per batch
+            let mut batch = vec![first];
+            while batch.len() < MAX_WRITE_BATCH {
+                match jobs.try_recv() {
+                    Ok(WriteJob::Batched(job)) => batch.push(job),
+                    // Runs once this batch is committed
+                    Ok(exclusive) => {
+                        next = Some(exclusive);
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+            let batch_size = batch.len();
+
+            let mut replies = Vec::with_capacity(batch_size);
+            let committed = conn.transaction().and_then(|tx| {
+                for job in batch.drain(..) {
+                    // Each job gets its own savepoint so one failing write
+                    // doesn't roll back the rest of the batch
+                    tx.execute_batch("SAVEPOINT job")?;
+                    let (ok, reply) = job(&tx);
+                    tx.execute_batch(if ok {
+                        "RELEASE job"
+                    } else {
+                        "ROLLBACK TO job; RELEASE job"
+                    })?;
+                    replies.push(reply);
+                }
+                tx.commit()
+            });
+
+            match committed {
+                Ok(()) => {
+                    for reply in replies {
+                        reply(Ok(()));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("[DB] Write batch of {} failed: {}", batch_size, e);
+                    for reply in replies {
+                        reply(Err(rusqlite::Error::SqliteFailure(
+                            ffi::Error::new(ffi::SQLITE_ABORT),
+                            Some(e.to_string()),
+                        )));
+                    }
+                }
+            }
+
+            if next.is_none() {
+                next = jobs.recv().ok();
+            }
+        }
+    }
+
+    /// Run `f` on one of the read connections.
+    fn read<T, F>(&self, f: F) -> Pending<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.readers.send(Box::new(move |conn: &Connection| {
+            let _ = tx.send(f(conn));
+        }));
+        Pending { rx }
+    }
+
+    /// Queue `f` on the writer thread. The result is delivered once the batch
+    /// containing it has been committed.
+    fn write<T, F>(&self, f: F) -> Pending<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let _ = self
+            .writer
+            .send(WriteJob::Batched(Box::new(move |conn: &Connection| {
+                let result = f(conn);
+                let ok = result.is_ok();
+                let reply: WriteReply = Box::new(move |committed: Result<()>| {
+                    let _ = tx.send(committed.and(result));
+                });
+                (ok, reply)
+            })));
+        Pending { rx }
+    }
+
+    /// Queue `f` on the writer thread to run on its own, outside of a
+    /// transaction, once the writes queued before it are committed. For work
+    /// like `VACUUM` that can't run inside one.
+    fn write_exclusive<T, F>(&self, f: F) -> Pending<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.writer.send(WriteJob::Exclusive(Box::new(
+            move |conn: &mut Connection| {
+                let _ = tx.send(f(conn));
+            },
+        )));
+        Pending { rx }
+    }
+
+    /// Resolves once every write queued before it has been committed.
+    pub fn flush(&self) -> Pending<()> {
+        self.write(|_| Ok(()))
+    }
+
+    /// Write a consistent copy of the database to `path` with SQLite's online
+    /// backup API, encrypted with the same key as this one. Writes queued
+    /// before the call are included.
+    pub fn backup_to(&self, path: &Path) -> Pending<()> {
+        let path = path.to_path_buf();
+        let key = self.key.clone();
+        let flushed = self.flush();
+        self.read(move |conn| {
+            flushed.wait()?;
+            let mut target = Connection::open(&path)?;
+            Self::unlock(&target, key.as_ref())?;
+
+            // Copy every page in a single step so the copy is one snapshot
+            let backup = Backup::new(conn, &mut target)?;
+            loop {
+                match backup.step(-1)? {
+                    StepResult::Done => return Ok(()),
+                    _ => thread::sleep(Duration::from_millis(50)),
+                }
+            }
         })
     }
 
-    pub fn save_contact(&self, contact: &Contact) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        println!(
-            "[DB] Attempting to save contact: {} ({})",
-            contact.name, contact.jid
-        );
-        conn.execute(
-            "INSERT OR REPLACE INTO contacts (jid, name, last_message, last_message_time, unread_count, conversation_timestamp, is_group, archived, pinned, mute_end_time, profile_picture_url)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
-            params![
-                contact.jid,
-                contact.name,
-                contact.last_message,
-                contact.last_message_time,
-                contact.unread_count,
-                contact.conversation_timestamp,
-                contact.is_group,
-                contact.archived,
-                contact.pinned,
-                contact.mute_end_time,
-                contact.profile_picture_url,
-            ],
-        )?;
-        println!("[DB] Contact saved successfully");
-        Ok(())
+    /// Check the database for damage and repair what can be: rebuild broken
+    /// indexes, drop duplicate messages and rows left pointing at nothing,
+    /// then `ANALYZE` and `VACUUM`. Other writes wait until it's done.
+    pub fn maintain(&self) -> Pending<MaintenanceReport> {
+        self.write_exclusive(|conn| {
+            let mut report = MaintenanceReport {
+                size_before: Self::size(conn)?,
+                integrity_errors: Self::integrity_errors(conn)?,
+                ..Default::default()
+            };
+
+            // No table declares foreign keys yet, so this only guards ones
+            // added later; the orphan checks below cover today's relations
+            report.foreign_key_violations = conn
+                .prepare("PRAGMA foreign_key_check")?
+                .query_map([], |_| Ok(()))?
+                .count();
+
+            let tx = conn.transaction()?;
+            // Old databases got message_id without a UNIQUE constraint, and a
+            // broken index can't be trusted to find copies either
+            report.duplicate_messages = tx.execute(
+                "DELETE FROM messages WHERE message_id IS NOT NULL AND id NOT IN (
+                     SELECT MAX(id) FROM messages NOT INDEXED
+                     WHERE message_id IS NOT NULL
+                     GROUP BY message_id
+                 )",
+                [],
+            )?;
+            // Counted before removing them, in order, so rows of a removed
+            // message count as orphans too
+            for (table, orphaned) in ORPHAN_CHECKS {
+                let count: usize = tx.query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE {}", table, orphaned),
+                    [],
+                    |row| row.get(0),
+                )?;
+                if count > 0 {
+                    tx.execute(&format!("DELETE FROM {} WHERE {}", table, orphaned), [])?;
+                }
+                report.orphans.push((table.to_string(), count));
+            }
+            tx.commit()?;
+
+            if !report.integrity_errors.is_empty() {
+                conn.execute_batch("REINDEX")?;
+                report.remaining_errors = Self::integrity_errors(conn)?;
+            }
+
+            conn.execute_batch(
+                "ANALYZE;
+                 VACUUM;",
+            )?;
+            // VACUUM goes through the WAL, the file only shrinks once it's
+            // checkpointed
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+            report.size_after = Self::size(conn)?;
+            Ok(report)
+        })
     }
 
-    pub fn get_contacts(&self) -> Result<Vec<Contact>> {
-        let conn = self.conn.lock().unwrap();
-        println!("[DB] Querying contacts from database...");
-        let mut stmt = conn.prepare(
-            "SELECT jid, name, last_message, last_message_time, unread_count, conversation_timestamp, is_group, archived, pinned, mute_end_time, profile_picture_url
-             FROM contacts 
-             ORDER BY conversation_timestamp DESC",
-        )?;
-
-        let contacts = stmt
-            .query_map([], |row| {
-                Ok(Contact {
-                    jid: row.get(0)?,
-                    name: row.get(1)?,
-                    last_message: row.get(2)?,
-                    last_message_time: row.get(3)?,
-                    unread_count: row.get(4)?,
-                    conversation_timestamp: row.get(5)?,
-                    is_group: row.get(6)?,
-                    archived: row.get(7)?,
-                    pinned: row.get(8)?,
-                    mute_end_time: row.get(9)?,
-                    profile_picture_url: row.get(10)?,
-                })
-            })?
+    fn integrity_errors(conn: &Connection) -> Result<Vec<String>> {
+        let errors = conn
+            .prepare("PRAGMA integrity_check")?
+            .query_map([], |row| row.get::<_, String>(0))?
             .collect::<Result<Vec<_>>>()?;
+        Ok(errors.into_iter().filter(|e| e != "ok").collect())
+    }
 
-        println!("[DB] Retrieved {} contacts", contacts.len());
-        Ok(contacts)
+    fn size(conn: &Connection) -> Result<u64> {
+        conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )
     }
 
-    pub fn save_message(&self, message: &Message) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        println!(
-            "[DB] Saving message: {} in chat {} (type: {})",
-            message.message_id, message.jid, message.message_type
-        );
+    /// Point cached media entries at `dir`, after a restore moved the cache
+    pub fn relocate_media(&self, dir: &Path) -> Pending<()> {
+        let dir = dir.to_string_lossy().into_owned();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE media
+                 SET local_path = ?1 || '/' || substr(sha256, 1, 2) || '/' || sha256",
+                params![dir],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Merge what an event says about a contact into the stored record,
+    /// leaving fields it doesn't mention untouched
+    pub fn update_contact(&self, update: &ContactUpdate) -> Pending<()> {
+        let update = update.clone();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO contacts (jid, name, notify, profile_picture_url)
+                 VALUES (?1, COALESCE(?2, ''), ?3, ?4)
+                 ON CONFLICT (jid) DO UPDATE SET
+                     name = COALESCE(?2, name),
+                     notify = COALESCE(?3, notify),
+                     profile_picture_url = COALESCE(?4, profile_picture_url)",
+                params![
+                    update.jid,
+                    update.name,
+                    update.notify,
+                    update.profile_picture_url,
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Merge what an event says about a chat into the stored state, leaving
+    /// fields it doesn't mention untouched. An unread increment adds to the
+    /// stored count instead of replacing it. The conversation timestamp never
+    /// moves backwards, so late history doesn't reorder the chat list, and
+    /// an older disappearing-messages setting never replaces a newer one.
+    pub fn update_chat(&self, update: &ChatUpdate) -> Pending<()> {
+        let update = update.clone();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO chats (jid, name, unread_count, conversation_timestamp, archived, pinned, mute_end_time, ephemeral_expiration, ephemeral_setting_timestamp)
+                 VALUES (?1, ?2, COALESCE(?3, ?8, 0), COALESCE(?4, 0), COALESCE(?5, 0), COALESCE(?6, 0), COALESCE(?7, 0), COALESCE(?9, 0), COALESCE(?10, 0))
+                 ON CONFLICT (jid) DO UPDATE SET
+                     name = COALESCE(?2, name),
+                     unread_count = CASE
+                         WHEN ?8 IS NOT NULL THEN MAX(unread_count, 0) + ?8
+                         ELSE COALESCE(?3, unread_count) END,
+                     conversation_timestamp = MAX(conversation_timestamp, COALESCE(?4, 0)),
+                     archived = COALESCE(?5, archived),
+                     pinned = COALESCE(?6, pinned),
+                     mute_end_time = COALESCE(?7, mute_end_time),
+                     ephemeral_expiration = CASE
+                         WHEN COALESCE(?10, ephemeral_setting_timestamp) >= ephemeral_setting_timestamp
+                         THEN COALESCE(?9, ephemeral_expiration)
+                         ELSE ephemeral_expiration END,
+                     ephemeral_setting_timestamp = MAX(ephemeral_setting_timestamp, COALESCE(?10, 0))",
+                params![
+                    update.jid,
+                    update.name,
+                    update.unread_count,
+                    update.conversation_timestamp,
+                    update.archived,
+                    update.pinned,
+                    update.mute_end_time,
+                    update.unread_increment,
+                    update.ephemeral_expiration,
+                    update.ephemeral_setting_timestamp,
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn get_contacts(&self) -> Pending<Vec<Contact>> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT j.jid,
+                        COALESCE(NULLIF(c.name, ''), ch.name, c.notify,
+                                 CASE WHEN instr(j.jid, '@') > 0
+                                      THEN substr(j.jid, 1, instr(j.jid, '@') - 1)
+                                      ELSE j.jid END),
+                        ch.last_message, ch.last_message_time,
+                        ch.last_message_sender, ch.last_message_type,
+                        COALESCE(ch.unread_count, 0), COALESCE(ch.conversation_timestamp, 0),
+                        j.jid LIKE '%@g.us',
+                        COALESCE(ch.archived, 0), COALESCE(ch.pinned, 0), COALESCE(ch.mute_end_time, 0),
+                        c.profile_picture_url, d.text,
+                        (SELECT group_concat(folder_id) FROM chat_folders f WHERE f.jid = j.jid)
+                 FROM (SELECT jid FROM contacts UNION SELECT jid FROM chats) j
+                 LEFT JOIN contacts c ON c.jid = j.jid
+                 LEFT JOIN chats ch ON ch.jid = j.jid
+                 LEFT JOIN drafts d ON d.jid = j.jid
+                 ORDER BY COALESCE(ch.conversation_timestamp, 0) DESC",
+            )?;
+
+            let contacts = stmt
+                .query_map([], |row| {
+                    Ok(Contact {
+                        jid: row.get(0)?,
+                        name: row.get(1)?,
+                        last_message: row.get(2)?,
+                        last_message_time: row.get(3)?,
+                        last_message_sender: row.get(4)?,
+                        last_message_type: row.get(5)?,
+                        unread_count: row.get(6)?,
+                        conversation_timestamp: row.get(7)?,
+                        is_group: row.get(8)?,
+                        archived: row.get(9)?,
+                        pinned: row.get(10)?,
+                        mute_end_time: row.get(11)?,
+                        profile_picture_url: row.get(12)?,
+                        draft: row.get(13)?,
+                        folders: row
+                            .get::<_, Option<String>>(14)?
+                            .map(|ids| ids.split(',').filter_map(|id| id.parse().ok()).collect())
+                            .unwrap_or_default(),
+                    })
+                })?
+                .collect::<Result<Vec<_>>>()?;
+
+            println!("[DB] Retrieved {} contacts", contacts.len());
+            Ok(contacts)
+        })
+    }
+
+    /// Store a message. Messages without an expiry of their own that were
+    /// sent while their chat had disappearing messages on expire with the
+    /// chat's setting. A star set earlier survives the message being stored
+    /// again.
+    pub fn save_message(&self, message: &Message) -> Pending<()> {
+        let message = message.clone();
+        self.write(move |conn| Self::insert_message(conn, &message))
+    }
+
+    fn insert_message(conn: &Connection, message: &Message) -> Result<()> {
         conn.execute(
-            "INSERT OR REPLACE INTO messages (message_id, jid, sender, content, timestamp, is_from_me, message_type, raw_data, quoted_message_id, media_url, caption)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            "INSERT OR REPLACE INTO messages (message_id, jid, sender, content, timestamp, is_from_me, message_type, raw_data, quoted_message_id, media_url, caption, expires_at, starred)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, COALESCE(?12, (
+                 SELECT ?5 + ephemeral_expiration FROM chats
+                 WHERE jid = ?2 AND ephemeral_expiration > 0 AND ?5 >= ephemeral_setting_timestamp
+             )), ?13 OR COALESCE((SELECT starred FROM messages WHERE message_id = ?1), 0))",
             params![
                 message.message_id,
                 message.jid,
@@ -154,82 +886,1203 @@ impl Database {
                 message.quoted_message_id,
                 message.media_url,
                 message.caption,
+                message.expires_at,
+                message.starred,
             ],
         )?;
-        println!("[DB] Message saved successfully");
         Ok(())
     }
 
-    pub fn get_messages(&self, jid: &str) -> Result<Vec<Message>> {
-        let conn = self.conn.lock().unwrap();
-        println!("[DB] Querying messages for JID: {}", jid);
-        let mut stmt = conn.prepare(
-            "SELECT id, message_id, jid, sender, content, timestamp, is_from_me, message_type, raw_data, quoted_message_id, media_url, caption
-             FROM messages 
-             WHERE jid = ?1 
-             ORDER BY timestamp ASC",
-        )?;
-
-        let messages = stmt
-            .query_map(params![jid], |row| {
-                Ok(Message {
-                    id: row.get(0)?,
-                    message_id: row.get(1)?,
-                    jid: row.get(2)?,
-                    sender: row.get(3)?,
-                    content: row.get(4)?,
-                    timestamp: row.get(5)?,
-                    is_from_me: row.get(6)?,
-                    message_type: row.get(7)?,
-                    raw_data: row.get(8)?,
-                    quoted_message_id: row.get(9)?,
-                    media_url: row.get(10)?,
-                    caption: row.get(11)?,
+    /// Store a message we're sending and queue it in the outbox, where it
+    /// stays until `outbox_sent`. A message with an attachment goes out with
+    /// its caption as text.
+    pub fn queue_message(&self, message: &Message, attachment: Option<&Attachment>) -> Pending<()> {
+        let message = message.clone();
+        let attachment = attachment.cloned();
+        self.write(move |conn| {
+            Self::insert_message(conn, &message)?;
+            let text = match attachment {
+                Some(_) => message.caption.as_deref().unwrap_or_default(),
+                None => message.content.as_str(),
+            };
+            conn.execute(
+                "INSERT INTO outbox (message_id, jid, text, media_path, mime_type, media_size)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    message.message_id,
+                    message.jid,
+                    text,
+                    attachment.as_ref().map(|a| &a.path),
+                    attachment.as_ref().map(|a| &a.mime_type),
+                    attachment.as_ref().and_then(|a| a.size),
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Outbox entries waiting to be sent, oldest first
+    pub fn get_outbox(&self) -> Pending<Vec<OutboxEntry>> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT message_id, jid, text, attempts, next_attempt, media_path, mime_type, media_size,
+                     (SELECT m.quoted_message_id FROM messages m WHERE m.message_id = outbox.message_id)
+                 FROM outbox
+                 WHERE state = ?1
+                 ORDER BY seq",
+            )?;
+            stmt.query_map(params![SendState::Pending.as_str()], |row| {
+                let media_path: Option<String> = row.get(5)?;
+                let mime_type: Option<String> = row.get(6)?;
+                let media_size: Option<u64> = row.get(7)?;
+                Ok(OutboxEntry {
+                    message_id: row.get(0)?,
+                    jid: row.get(1)?,
+                    text: row.get(2)?,
+                    attachment: media_path.map(|path| Attachment {
+                        path,
+                        mime_type: mime_type
+                            .unwrap_or_else(|| "application/octet-stream".to_string()),
+                        size: media_size,
+                    }),
+                    quoted_message_id: row.get(8)?,
+                    attempts: row.get(3)?,
+                    next_attempt: row.get(4)?,
                 })
             })?
-            .collect::<Result<Vec<_>>>()?;
+            .collect()
+        })
+    }
 
-        println!(
-            "[DB] Retrieved {} messages for JID: {}",
-            messages.len(),
-            jid
-        );
-        Ok(messages)
+    /// Take a delivered message out of the outbox and give it the id
+    /// WhatsApp assigned, along with its reactions, edits and replies. If the
+    /// echo of the message was stored under that id already, our copy is
+    /// dropped in its favour. Does nothing the second time.
+    pub fn outbox_sent(&self, message_id: &str, sent_id: &str) -> Pending<()> {
+        let message_id = message_id.to_string();
+        let sent_id = sent_id.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "DELETE FROM outbox WHERE message_id = ?1",
+                params![message_id],
+            )?;
+
+            let echoed: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM messages WHERE message_id = ?1)",
+                params![sent_id],
+                |row| row.get(0),
+            )?;
+            if echoed {
+                conn.execute(
+                    "UPDATE messages SET starred = 1
+                     WHERE message_id = ?2
+                       AND EXISTS (SELECT 1 FROM messages WHERE message_id = ?1 AND starred)",
+                    params![message_id, sent_id],
+                )?;
+                conn.execute(
+                    "DELETE FROM messages WHERE message_id = ?1",
+                    params![message_id],
+                )?;
+            } else {
+                conn.execute(
+                    "UPDATE messages SET message_id = ?2 WHERE message_id = ?1",
+                    params![message_id, sent_id],
+                )?;
+            }
+            conn.execute(
+                "UPDATE messages SET quoted_message_id = ?2 WHERE quoted_message_id = ?1",
+                params![message_id, sent_id],
+            )?;
+            conn.execute(
+                "UPDATE OR REPLACE reactions SET message_id = ?2 WHERE message_id = ?1",
+                params![message_id, sent_id],
+            )?;
+            conn.execute(
+                "UPDATE OR REPLACE message_edits SET message_id = ?2 WHERE message_id = ?1",
+                params![message_id, sent_id],
+            )?;
+            Ok(())
+        })
     }
 
-    pub fn set_session_data(&self, key: &str, value: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT OR REPLACE INTO session (key, value) VALUES (?1, ?2)",
-            params![key, value],
-        )?;
-        Ok(())
+    /// Record a failed attempt at sending a message. It's tried again at
+    /// `next_attempt`, or marked failed without one.
+    pub fn outbox_attempt_failed(
+        &self,
+        message_id: &str,
+        error: &str,
+        next_attempt: Option<i64>,
+    ) -> Pending<()> {
+        let message_id = message_id.to_string();
+        let error = error.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE outbox
+                 SET attempts = attempts + 1,
+                     last_error = ?2,
+                     state = CASE WHEN ?3 IS NULL THEN 'failed' ELSE 'pending' END,
+                     next_attempt = COALESCE(?3, next_attempt)
+                 WHERE message_id = ?1",
+                params![message_id, error, next_attempt],
+            )?;
+            Ok(())
+        })
     }
 
-    pub fn get_session_data(&self, key: &str) -> Result<Option<String>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT value FROM session WHERE key = ?1")?;
-        let mut rows = stmt.query(params![key])?;
+    /// Put a failed message back in line, with its attempts starting over
+    pub fn retry_outbox(&self, message_id: &str) -> Pending<()> {
+        let message_id = message_id.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE outbox SET state = ?2, attempts = 0, next_attempt = 0
+                 WHERE message_id = ?1 AND state = ?3",
+                params![
+                    message_id,
+                    SendState::Pending.as_str(),
+                    SendState::Failed.as_str()
+                ],
+            )?;
+            Ok(())
+        })
+    }
 
-        if let Some(row) = rows.next()? {
-            Ok(Some(row.get(0)?))
-        } else {
-            Ok(None)
-        }
+    pub fn get_messages(&self, jid: &str) -> Pending<Vec<Message>> {
+        let jid = jid.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT {MESSAGE_COLUMNS}
+                 FROM messages m
+                 WHERE m.jid = ?1
+                 ORDER BY m.timestamp ASC"
+            ))?;
+
+            let messages = stmt
+                .query_map(params![jid], Self::message_from_row)?
+                .collect::<Result<Vec<_>>>()?;
+
+            println!(
+                "[DB] Retrieved {} messages for JID: {}",
+                messages.len(),
+                jid
+            );
+            Ok(messages)
+        })
+    }
+
+    pub fn get_message(&self, message_id: &str) -> Pending<Option<Message>> {
+        let message_id = message_id.to_string();
+        self.read(move |conn| Self::find_message(conn, &message_id))
+    }
+
+    /// Messages of a chat we received, newest first: as many as it has
+    /// unread, or the last one if it has none, e.g. to send read receipts
+    /// for
+    pub fn get_unread_messages(&self, jid: &str) -> Pending<Vec<Message>> {
+        let jid = jid.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT {MESSAGE_COLUMNS}
+                 FROM messages m
+                 WHERE m.jid = ?1 AND NOT m.is_from_me
+                 ORDER BY m.timestamp DESC, m.id DESC
+                 LIMIT MAX(1, COALESCE((SELECT unread_count FROM chats WHERE jid = ?1), 0))"
+            ))?;
+            stmt.query_map(params![jid], Self::message_from_row)?
+                .collect()
+        })
+    }
+
+    /// The newest message of a chat that reached WhatsApp, whoever sent it,
+    /// e.g. to mark the chat as unread up to it
+    pub fn get_last_message(&self, jid: &str) -> Pending<Option<Message>> {
+        let jid = jid.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT {MESSAGE_COLUMNS}
+                 FROM messages m
+                 WHERE m.jid = ?1
+                   AND m.message_id NOT IN (SELECT message_id FROM outbox)
+                 ORDER BY m.timestamp DESC, m.id DESC
+                 LIMIT 1"
+            ))?;
+            let mut rows = stmt.query_map(params![jid], Self::message_from_row)?;
+            rows.next().transpose()
+        })
+    }
+
+    fn find_message(conn: &Connection, message_id: &str) -> Result<Option<Message>> {
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT {MESSAGE_COLUMNS}
+             FROM messages m
+             WHERE m.message_id = ?1"
+        ))?;
+        let mut rows = stmt.query_map(params![message_id], Self::message_from_row)?;
+        rows.next().transpose()
+    }
+
+    /// Remove a message deleted for us only, along with its reactions and
+    /// edits. Returns what was deleted, for cleaning up media.
+    pub fn delete_message(&self, message_id: &str) -> Pending<Option<Message>> {
+        let message_id = message_id.to_string();
+        self.write(move |conn| {
+            let message = Self::find_message(conn, &message_id)?;
+            conn.execute(
+                "DELETE FROM message_edits WHERE message_id = ?1",
+                params![message_id],
+            )?;
+            conn.execute(
+                "DELETE FROM reactions WHERE message_id = ?1",
+                params![message_id],
+            )?;
+            conn.execute(
+                "DELETE FROM outbox WHERE message_id = ?1",
+                params![message_id],
+            )?;
+            conn.execute(
+                "DELETE FROM messages WHERE message_id = ?1",
+                params![message_id],
+            )?;
+            Ok(message)
+        })
+    }
+
+    /// Blank out a message deleted for everyone, keeping it in place as a
+    /// "deleted" note. Its reactions and earlier versions go with it.
+    /// Returns the message as it was, for cleaning up media.
+    pub fn revoke_message(&self, message_id: &str) -> Pending<Option<Message>> {
+        let message_id = message_id.to_string();
+        self.write(move |conn| {
+            let message = Self::find_message(conn, &message_id)?;
+            // Edits first, so the chat preview doesn't show the latest one
+            conn.execute(
+                "DELETE FROM message_edits WHERE message_id = ?1",
+                params![message_id],
+            )?;
+            conn.execute(
+                "DELETE FROM reactions WHERE message_id = ?1",
+                params![message_id],
+            )?;
+            conn.execute(
+                "UPDATE messages
+                 SET content = ?2, message_type = 'revoked', raw_data = NULL,
+                     quoted_message_id = NULL, media_url = NULL, caption = NULL
+                 WHERE message_id = ?1",
+                params![message_id, Message::REVOKED_CONTENT],
+            )?;
+            Ok(message)
+        })
+    }
+
+    pub fn set_starred(&self, message_id: &str, starred: bool) -> Pending<()> {
+        let message_id = message_id.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE messages SET starred = ?2 WHERE message_id = ?1",
+                params![message_id, starred],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Starred messages of all chats, newest first, each with the name of
+    /// its chat
+    pub fn get_starred_messages(&self) -> Pending<Vec<(Message, String)>> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT {MESSAGE_COLUMNS},
+                        COALESCE(NULLIF(c.name, ''), ch.name, c.notify,
+                                 CASE WHEN instr(m.jid, '@') > 0
+                                      THEN substr(m.jid, 1, instr(m.jid, '@') - 1)
+                                      ELSE m.jid END)
+                 FROM messages m
+                 LEFT JOIN contacts c ON c.jid = m.jid
+                 LEFT JOIN chats ch ON ch.jid = m.jid
+                 WHERE m.starred
+                 ORDER BY m.timestamp DESC"
+            ))?;
+
+            let starred = stmt
+                .query_map([], |row| Ok((Self::message_from_row(row)?, row.get(16)?)))?
+                .collect::<Result<Vec<_>>>()?;
+            Ok(starred)
+        })
+    }
+
+    fn message_from_row(row: &rusqlite::Row) -> Result<Message> {
+        Ok(Message {
+            id: row.get(0)?,
+            message_id: row.get(1)?,
+            jid: row.get(2)?,
+            sender: row.get(3)?,
+            content: row.get(4)?,
+            timestamp: row.get(5)?,
+            is_from_me: row.get(6)?,
+            message_type: row.get(7)?,
+            raw_data: row.get(8)?,
+            quoted_message_id: row.get(9)?,
+            media_url: row.get(10)?,
+            caption: row.get(11)?,
+            expires_at: row.get(12)?,
+            starred: row.get(13)?,
+            edited_at: row.get(14)?,
+            send_state: row
+                .get::<_, Option<String>>(15)?
+                .as_deref()
+                .and_then(SendState::parse),
+        })
+    }
+
+    /// Delete disappearing messages that are due at `now` and messages that
+    /// fall outside their chat's retention policy, `global` for chats
+    /// without one. Starred messages are exempt from retention policies.
+    /// Returns what was deleted, for cleaning up media.
+    pub fn prune(&self, global: RetentionPolicy, now: i64) -> Pending<Vec<Message>> {
+        self.write(move |conn| {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT {MESSAGE_COLUMNS}
+                 FROM (
+                     SELECT m.*,
+                            ROW_NUMBER() OVER (
+                                PARTITION BY m.jid ORDER BY m.timestamp DESC, m.id DESC
+                            ) AS position,
+                            CASE WHEN r.jid IS NULL THEN ?1 ELSE r.keep_days END AS keep_days,
+                            CASE WHEN r.jid IS NULL THEN ?2 ELSE r.keep_messages END AS keep_messages
+                     FROM messages m
+                     LEFT JOIN chat_retention r ON r.jid = m.jid
+                 ) m
+                 WHERE m.expires_at <= ?3
+                    OR (NOT m.starred AND (
+                        m.timestamp < ?3 - m.keep_days * 86400 OR m.position > m.keep_messages
+                    ))"
+            ))?;
+            let pruned = stmt
+                .query_map(
+                    params![global.keep_days, global.keep_messages, now],
+                    Self::message_from_row,
+                )?
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut delete_message = conn.prepare_cached("DELETE FROM messages WHERE id = ?1")?;
+            let mut delete_reactions =
+                conn.prepare_cached("DELETE FROM reactions WHERE message_id = ?1")?;
+            let mut delete_edits =
+                conn.prepare_cached("DELETE FROM message_edits WHERE message_id = ?1")?;
+            let mut delete_outbox =
+                conn.prepare_cached("DELETE FROM outbox WHERE message_id = ?1")?;
+            for message in &pruned {
+                delete_message.execute(params![message.id])?;
+                delete_reactions.execute(params![message.message_id])?;
+                delete_edits.execute(params![message.message_id])?;
+                delete_outbox.execute(params![message.message_id])?;
+            }
+
+            if !pruned.is_empty() {
+                println!("[DB] Pruned {} messages", pruned.len());
+            }
+            Ok(pruned)
+        })
+    }
+
+    /// When the next disappearing message is due, if there is one
+    pub fn next_expiry(&self) -> Pending<Option<i64>> {
+        self.read(|conn| {
+            conn.query_row(
+                "SELECT MIN(expires_at) FROM messages WHERE expires_at IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// A chat's own retention policy, or `None` if it follows the global one
+    pub fn get_chat_retention(&self, jid: &str) -> Pending<Option<RetentionPolicy>> {
+        let jid = jid.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT keep_days, keep_messages FROM chat_retention WHERE jid = ?1",
+            )?;
+            let mut rows = stmt.query_map(params![jid], |row| {
+                Ok(RetentionPolicy {
+                    keep_days: row.get(0)?,
+                    keep_messages: row.get(1)?,
+                })
+            })?;
+            rows.next().transpose()
+        })
+    }
+
+    /// Give a chat its own retention policy, or `None` to have it follow the
+    /// global one again
+    pub fn set_chat_retention(&self, jid: &str, policy: Option<RetentionPolicy>) -> Pending<()> {
+        let jid = jid.to_string();
+        self.write(move |conn| {
+            match policy {
+                Some(policy) => conn.execute(
+                    "INSERT OR REPLACE INTO chat_retention (jid, keep_days, keep_messages)
+                     VALUES (?1, ?2, ?3)",
+                    params![jid, policy.keep_days, policy.keep_messages],
+                )?,
+                None => conn.execute("DELETE FROM chat_retention WHERE jid = ?1", params![jid])?,
+            };
+            Ok(())
+        })
+    }
+
+    /// Record a reaction. A newer reaction from the same person replaces
+    /// theirs, and an empty emoji removes it. Older events arriving late
+    /// (e.g. from history sync) never override newer state.
+    pub fn save_reaction(&self, reaction: &Reaction) -> Pending<()> {
+        let reaction = reaction.clone();
+        self.write(move |conn| {
+            if reaction.emoji.is_empty() {
+                conn.execute(
+                    "DELETE FROM reactions
+                     WHERE message_id = ?1 AND reactor = ?2 AND timestamp <= ?3",
+                    params![reaction.message_id, reaction.reactor, reaction.timestamp],
+                )?;
+            } else {
+                conn.execute(
+                    "INSERT INTO reactions (message_id, jid, reactor, emoji, timestamp)
+                     VALUES (?1, ?2, ?3, ?4, ?5)
+                     ON CONFLICT (message_id, reactor) DO UPDATE
+                     SET emoji = excluded.emoji, timestamp = excluded.timestamp
+                     WHERE excluded.timestamp >= reactions.timestamp",
+                    params![
+                        reaction.message_id,
+                        reaction.jid,
+                        reaction.reactor,
+                        reaction.emoji,
+                        reaction.timestamp,
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// All reactions in a chat, grouped by the message they belong to
+    pub fn get_reactions(&self, jid: &str) -> Pending<HashMap<String, Vec<Reaction>>> {
+        let jid = jid.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT message_id, jid, reactor, emoji, timestamp
+                 FROM reactions
+                 WHERE jid = ?1
+                 ORDER BY timestamp ASC",
+            )?;
+
+            let mut reactions: HashMap<String, Vec<Reaction>> = HashMap::new();
+            for reaction in stmt.query_map(params![jid], Self::reaction_from_row)? {
+                let reaction = reaction?;
+                reactions
+                    .entry(reaction.message_id.clone())
+                    .or_default()
+                    .push(reaction);
+            }
+            Ok(reactions)
+        })
+    }
+
+    /// Reactions on a single message
+    pub fn get_message_reactions(&self, message_id: &str) -> Pending<Vec<Reaction>> {
+        let message_id = message_id.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT message_id, jid, reactor, emoji, timestamp
+                 FROM reactions
+                 WHERE message_id = ?1
+                 ORDER BY timestamp ASC",
+            )?;
+
+            let reactions = stmt
+                .query_map(params![message_id], Self::reaction_from_row)?
+                .collect::<Result<Vec<_>>>()?;
+            Ok(reactions)
+        })
     }
 
+    /// Record an edit of a message. Edits may arrive before the message
+    /// itself; they apply once it does.
+    pub fn save_message_edit(&self, edit: &MessageEdit) -> Pending<()> {
+        let edit = edit.clone();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO message_edits (message_id, jid, content, edited_at, raw_data)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    edit.message_id,
+                    edit.jid,
+                    edit.content,
+                    edit.edited_at,
+                    edit.raw_data,
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Every version of a message, oldest first: the text it was sent with
+    /// followed by each edit
+    pub fn get_message_versions(&self, message_id: &str) -> Pending<Vec<MessageEdit>> {
+        let message_id = message_id.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT message_id, jid, content, timestamp, raw_data
+                 FROM messages
+                 WHERE message_id = ?1
+                 UNION ALL
+                 SELECT message_id, jid, content, edited_at, raw_data
+                 FROM message_edits
+                 WHERE message_id = ?1
+                 ORDER BY 4 ASC",
+            )?;
+
+            let versions = stmt
+                .query_map(params![message_id], |row| {
+                    Ok(MessageEdit {
+                        message_id: row.get(0)?,
+                        jid: row.get(1)?,
+                        content: row.get(2)?,
+                        edited_at: row.get(3)?,
+                        raw_data: row.get(4)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>>>()?;
+            Ok(versions)
+        })
+    }
+
+    fn reaction_from_row(row: &rusqlite::Row) -> Result<Reaction> {
+        Ok(Reaction {
+            message_id: row.get(0)?,
+            jid: row.get(1)?,
+            reactor: row.get(2)?,
+            emoji: row.get(3)?,
+            timestamp: row.get(4)?,
+        })
+    }
+
+    /// All folders in creation order
+    pub fn get_folders(&self) -> Pending<Vec<Folder>> {
+        self.read(|conn| {
+            let mut stmt =
+                conn.prepare_cached("SELECT id, name, label_id FROM folders ORDER BY id ASC")?;
+            let folders = stmt
+                .query_map([], |row| {
+                    Ok(Folder {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                        label_id: row.get(2)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>>>()?;
+            Ok(folders)
+        })
+    }
+
+    /// Add an empty folder and return its id
+    pub fn create_folder(&self, name: &str) -> Pending<i64> {
+        let name = name.to_string();
+        self.write(move |conn| {
+            conn.execute("INSERT INTO folders (name) VALUES (?1)", params![name])?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    pub fn rename_folder(&self, id: i64, name: &str) -> Pending<()> {
+        let name = name.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE folders SET name = ?2 WHERE id = ?1",
+                params![id, name],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Delete a folder. Its chats stay where they are.
+    pub fn delete_folder(&self, id: i64) -> Pending<()> {
+        self.write(move |conn| {
+            conn.execute("DELETE FROM chat_folders WHERE folder_id = ?1", params![id])?;
+            conn.execute("DELETE FROM folders WHERE id = ?1", params![id])?;
+            Ok(())
+        })
+    }
+
+    /// Put a chat into a folder or take it out
+    pub fn set_chat_folder(&self, jid: &str, folder_id: i64, member: bool) -> Pending<()> {
+        let jid = jid.to_string();
+        self.write(move |conn| {
+            if member {
+                conn.execute(
+                    "INSERT OR IGNORE INTO chat_folders (folder_id, jid) VALUES (?1, ?2)",
+                    params![folder_id, jid],
+                )?;
+            } else {
+                conn.execute(
+                    "DELETE FROM chat_folders WHERE folder_id = ?1 AND jid = ?2",
+                    params![folder_id, jid],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Create, rename or delete the folder mirroring a Business label
+    pub fn save_label(&self, label_id: &str, name: &str, deleted: bool) -> Pending<()> {
+        let label_id = label_id.to_string();
+        let name = name.to_string();
+        self.write(move |conn| {
+            if deleted {
+                conn.execute(
+                    "DELETE FROM chat_folders
+                     WHERE folder_id IN (SELECT id FROM folders WHERE label_id = ?1)",
+                    params![label_id],
+                )?;
+                conn.execute("DELETE FROM folders WHERE label_id = ?1", params![label_id])?;
+            } else {
+                conn.execute(
+                    "INSERT INTO folders (name, label_id) VALUES (?2, ?1)
+                     ON CONFLICT (label_id) DO UPDATE SET name = excluded.name",
+                    params![label_id, name],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Add a chat to a Business label's folder or remove it. A label not
+    /// seen yet gets a folder named after its id until its name arrives.
+    pub fn set_chat_label(&self, jid: &str, label_id: &str, member: bool) -> Pending<()> {
+        let jid = jid.to_string();
+        let label_id = label_id.to_string();
+        self.write(move |conn| {
+            if member {
+                conn.execute(
+                    "INSERT INTO folders (name, label_id) VALUES (?1, ?1)
+                     ON CONFLICT (label_id) DO NOTHING",
+                    params![label_id],
+                )?;
+                conn.execute(
+                    "INSERT OR IGNORE INTO chat_folders (folder_id, jid)
+                     SELECT id, ?2 FROM folders WHERE label_id = ?1",
+                    params![label_id, jid],
+                )?;
+            } else {
+                conn.execute(
+                    "DELETE FROM chat_folders
+                     WHERE jid = ?2
+                       AND folder_id IN (SELECT id FROM folders WHERE label_id = ?1)",
+                    params![label_id, jid],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Remember unsent text for a chat. Empty text clears the draft.
+    pub fn save_draft(&self, jid: &str, text: &str) -> Pending<()> {
+        let jid = jid.to_string();
+        let text = text.to_string();
+        self.write(move |conn| {
+            if text.is_empty() {
+                conn.execute("DELETE FROM drafts WHERE jid = ?1", params![jid])?;
+            } else {
+                conn.execute(
+                    "INSERT OR REPLACE INTO drafts (jid, text, updated_at)
+                     VALUES (?1, ?2, strftime('%s', 'now'))",
+                    params![jid, text],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn get_draft(&self, jid: &str) -> Pending<Option<String>> {
+        let jid = jid.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached("SELECT text FROM drafts WHERE jid = ?1")?;
+            let mut rows = stmt.query(params![jid])?;
+
+            if let Some(row) = rows.next()? {
+                Ok(Some(row.get(0)?))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    pub fn get_media(&self, sha256: &str) -> Pending<Option<MediaEntry>> {
+        let sha256 = sha256.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT sha256, mime_type, size, width, height, local_path, last_accessed
+                 FROM media
+                 WHERE sha256 = ?1",
+            )?;
+            let mut rows = stmt.query_map(params![sha256], Self::media_from_row)?;
+            rows.next().transpose()
+        })
+    }
+
+    pub fn save_media(&self, entry: &MediaEntry) -> Pending<()> {
+        let entry = entry.clone();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO media (sha256, mime_type, size, width, height, local_path, last_accessed)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    entry.sha256,
+                    entry.mime_type,
+                    entry.size,
+                    entry.width,
+                    entry.height,
+                    entry.local_path,
+                    entry.last_accessed,
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Mark a cached file as used so eviction keeps it around longer
+    pub fn touch_media(&self, sha256: &str, accessed: i64) -> Pending<()> {
+        let sha256 = sha256.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE media SET last_accessed = ?2 WHERE sha256 = ?1",
+                params![sha256, accessed],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Least recently used entries that have to go for the cache to fit in
+    /// `limit` bytes
+    pub fn media_over_limit(&self, limit: i64) -> Pending<Vec<MediaEntry>> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT sha256, mime_type, size, width, height, local_path, last_accessed
+                 FROM (
+                     SELECT *, SUM(size) OVER (
+                         ORDER BY last_accessed DESC, sha256
+                     ) AS running_size
+                     FROM media
+                 )
+                 WHERE running_size > ?1
+                 ORDER BY last_accessed ASC",
+            )?;
+            let entries = stmt
+                .query_map(params![limit], Self::media_from_row)?
+                .collect::<Result<Vec<_>>>()?;
+            Ok(entries)
+        })
+    }
+
+    pub fn delete_media(&self, sha256s: Vec<String>) -> Pending<()> {
+        self.write(move |conn| {
+            let mut stmt = conn.prepare_cached("DELETE FROM media WHERE sha256 = ?1")?;
+            for sha256 in &sha256s {
+                stmt.execute(params![sha256])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn media_from_row(row: &rusqlite::Row) -> Result<MediaEntry> {
+        Ok(MediaEntry {
+            sha256: row.get(0)?,
+            mime_type: row.get(1)?,
+            size: row.get(2)?,
+            width: row.get(3)?,
+            height: row.get(4)?,
+            local_path: row.get(5)?,
+            last_accessed: row.get(6)?,
+        })
+    }
+
+    pub fn set_session_data(&self, key: &str, value: &str) -> Pending<()> {
+        let key = key.to_string();
+        let value = value.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO session (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn get_session_data(&self, key: &str) -> Pending<Option<String>> {
+        let key = key.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare_cached("SELECT value FROM session WHERE key = ?1")?;
+            let mut rows = stmt.query(params![key])?;
+
+            if let Some(row) = rows.next()? {
+                Ok(Some(row.get(0)?))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    /// Blocking check used once at startup to pick the initial view.
     pub fn is_authenticated(&self) -> bool {
         self.get_session_data("authenticated")
+            .wait()
             .ok()
             .flatten()
             .map(|v| v == "true")
             .unwrap_or(false)
     }
 
-    pub fn set_authenticated(&self, authenticated: bool) -> Result<()> {
+    pub fn set_authenticated(&self, authenticated: bool) -> Pending<()> {
         self.set_session_data(
             "authenticated",
             if authenticated { "true" } else { "false" },
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    /// A database in a directory of its own
+    struct TempDatabase {
+        db: Database,
+        _dir: TempDir,
+    }
+
+    impl TempDatabase {
+        fn new() -> Self {
+            let dir = TempDir::new("db-test");
+            let db = Database::new(dir.join("client.db"), None).unwrap();
+            Self { db, _dir: dir }
+        }
+    }
+
+    const JID: &str = "15551234567@s.whatsapp.net";
+
+    /// What `contacts.upsert` says about the contact
+    fn contact() -> ContactUpdate {
+        ContactUpdate {
+            jid: JID.to_string(),
+            name: Some("Alice".to_string()),
+            notify: Some("Al".to_string()),
+            profile_picture_url: None,
+        }
+    }
+
+    /// What a full `chats.set` says about the chat
+    fn chat() -> ChatUpdate {
+        ChatUpdate {
+            jid: JID.to_string(),
+            unread_count: Some(3),
+            conversation_timestamp: Some(1_700_000_000),
+            archived: Some(true),
+            pinned: Some(1_700_000_100),
+            mute_end_time: Some(1_800_000_000_000),
+            ..Default::default()
+        }
+    }
+
+    fn stored(db: &Database) -> Contact {
+        db.get_contacts()
+            .wait()
+            .unwrap()
+            .into_iter()
+            .find(|contact| contact.jid == JID)
+            .expect("chat is listed")
+    }
+
+    /// Everything the contact and the full chat event set is still there
+    fn assert_merged(contact: &Contact) {
+        assert_eq!(contact.name, "Alice");
+        assert_eq!(contact.unread_count, 3);
+        assert_eq!(contact.pinned, 1_700_000_100);
+        assert!(contact.archived);
+        assert_eq!(contact.mute_end_time, 1_800_000_000_000);
+    }
+
+    #[test]
+    fn contact_then_chat() {
+        let temp = TempDatabase::new();
+        temp.db.update_contact(&contact()).wait().unwrap();
+        temp.db.update_chat(&chat()).wait().unwrap();
+
+        let stored = stored(&temp.db);
+        assert_merged(&stored);
+        assert_eq!(stored.conversation_timestamp, 1_700_000_000);
+    }
+
+    #[test]
+    fn chat_then_contact() {
+        let temp = TempDatabase::new();
+        temp.db.update_chat(&chat()).wait().unwrap();
+        temp.db.update_contact(&contact()).wait().unwrap();
+
+        let stored = stored(&temp.db);
+        assert_merged(&stored);
+        assert_eq!(stored.conversation_timestamp, 1_700_000_000);
+    }
+
+    #[test]
+    fn partial_chat_update_after_full_set() {
+        let temp = TempDatabase::new();
+        temp.db.update_contact(&contact()).wait().unwrap();
+        temp.db.update_chat(&chat()).wait().unwrap();
+        // A new message only moves the chat up
+        temp.db
+            .update_chat(&ChatUpdate {
+                jid: JID.to_string(),
+                conversation_timestamp: Some(1_700_000_500),
+                ..Default::default()
+            })
+            .wait()
+            .unwrap();
+
+        let stored = stored(&temp.db);
+        assert_merged(&stored);
+        assert_eq!(stored.conversation_timestamp, 1_700_000_500);
+    }
+
+    #[test]
+    fn older_conversation_timestamp_is_ignored() {
+        let temp = TempDatabase::new();
+        temp.db.update_contact(&contact()).wait().unwrap();
+        temp.db.update_chat(&chat()).wait().unwrap();
+        // Late history carrying the timestamp of an older message
+        temp.db
+            .update_chat(&ChatUpdate {
+                jid: JID.to_string(),
+                conversation_timestamp: Some(1_600_000_000),
+                ..Default::default()
+            })
+            .wait()
+            .unwrap();
+
+        let stored = stored(&temp.db);
+        assert_merged(&stored);
+        assert_eq!(stored.conversation_timestamp, 1_700_000_000);
+    }
+
+    #[test]
+    fn unread_increment_adds_to_the_count() {
+        let temp = TempDatabase::new();
+        let arrived = |count| ChatUpdate {
+            jid: JID.to_string(),
+            unread_increment: Some(count),
+            ..Default::default()
+        };
+        temp.db.update_contact(&contact()).wait().unwrap();
+        temp.db.update_chat(&chat()).wait().unwrap();
+
+        temp.db.update_chat(&arrived(2)).wait().unwrap();
+        assert_eq!(stored(&temp.db).unread_count, 5);
+
+        // Reading the chat on the phone sets the count outright
+        temp.db
+            .update_chat(&ChatUpdate {
+                jid: JID.to_string(),
+                unread_count: Some(0),
+                ..Default::default()
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(stored(&temp.db).unread_count, 0);
+        temp.db.update_chat(&arrived(1)).wait().unwrap();
+        assert_eq!(stored(&temp.db).unread_count, 1);
+    }
+
+    #[test]
+    fn notify_update_keeps_the_saved_name() {
+        let temp = TempDatabase::new();
+        temp.db.update_contact(&contact()).wait().unwrap();
+        temp.db.update_chat(&chat()).wait().unwrap();
+        // `contacts.update` when the contact changes their own name
+        temp.db
+            .update_contact(&ContactUpdate {
+                jid: JID.to_string(),
+                notify: Some("Ally".to_string()),
+                ..Default::default()
+            })
+            .wait()
+            .unwrap();
+
+        assert_merged(&stored(&temp.db));
+    }
+
+    #[test]
+    fn chat_name_resolves_the_same_in_either_order() {
+        let chat_name = ChatUpdate {
+            jid: JID.to_string(),
+            name: Some("Alice Smith".to_string()),
+            ..Default::default()
+        };
+
+        let before = TempDatabase::new();
+        before.db.update_chat(&chat_name).wait().unwrap();
+        assert_eq!(stored(&before.db).name, "Alice Smith");
+        before.db.update_contact(&contact()).wait().unwrap();
+
+        let after = TempDatabase::new();
+        after.db.update_contact(&contact()).wait().unwrap();
+        after.db.update_chat(&chat_name).wait().unwrap();
+
+        // The address book name wins however the events arrived
+        assert_eq!(stored(&before.db).name, "Alice");
+        assert_eq!(stored(&after.db).name, "Alice");
+    }
+
+    #[test]
+    fn maintain_reports_orphans_per_table() {
+        let temp = TempDatabase::new();
+        // A reaction to a message that was never stored
+        temp.db
+            .save_reaction(&Reaction {
+                message_id: "GONE".to_string(),
+                jid: JID.to_string(),
+                reactor: "me".to_string(),
+                emoji: "👍".to_string(),
+                timestamp: 1_700_000_000,
+            })
+            .wait()
+            .unwrap();
+
+        let report = temp.db.maintain().wait().unwrap();
+        assert_eq!(report.foreign_key_violations, 0);
+        let orphans = |table: &str| {
+            report
+                .orphans
+                .iter()
+                .find(|(name, _)| name == table)
+                .map(|(_, count)| *count)
+        };
+        assert_eq!(orphans("reactions"), Some(1));
+        assert_eq!(orphans("messages"), Some(0));
+        assert!(
+            temp.db
+                .get_message_reactions("GONE")
+                .wait()
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn last_message_includes_ours_but_not_unsent_ones() {
+        let temp = TempDatabase::new();
+        let message = |message_id: &str, timestamp, is_from_me| Message {
+            id: None,
+            message_id: message_id.to_string(),
+            jid: JID.to_string(),
+            sender: if is_from_me { "me" } else { "15551234567" }.to_string(),
+            content: message_id.to_string(),
+            timestamp,
+            is_from_me,
+            message_type: "text".to_string(),
+            raw_data: None,
+            quoted_message_id: None,
+            media_url: None,
+            caption: None,
+            expires_at: None,
+            starred: false,
+            edited_at: None,
+            send_state: None,
+        };
+        let last = |db: &Database| db.get_last_message(JID).wait().unwrap();
+        assert!(last(&temp.db).is_none());
+
+        let received = message("IN", 1_700_000_000, false);
+        temp.db.save_message(&received).wait().unwrap();
+        let sent = message("OUT", 1_700_000_100, true);
+        temp.db.save_message(&sent).wait().unwrap();
+        let queued = message("local-1", 1_700_000_200, true);
+        temp.db.queue_message(&queued, None).wait().unwrap();
+
+        // Our reply is the newest, the queued message has no WhatsApp id yet
+        assert_eq!(last(&temp.db).unwrap().message_id, "OUT");
+        let unread = temp.db.get_unread_messages(JID).wait().unwrap();
+        assert_eq!(unread[0].message_id, "IN");
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    #[test]
+    fn key_is_refused_without_encryption_support() {
+        let dir = TempDir::new("db-test");
+        let key = DatabaseKey::Passphrase("correct horse".to_string());
+        assert!(Database::new(dir.join("client.db"), Some(&key)).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    mod encryption {
+        use super::*;
+
+        /// Create an encrypted database holding the test contact, closed
+        /// again so it can be opened from scratch
+        fn fixture(dir: &TempDir, key: &DatabaseKey) -> std::path::PathBuf {
+            let path = dir.join("client.db");
+            let db = Database::new(&path, Some(key)).unwrap();
+            db.update_contact(&contact()).wait().unwrap();
+            drop(db);
+            assert!(db_key::is_encrypted(&path));
+            path
+        }
+
+        fn contact_name(db: &Database) -> String {
+            db.get_contacts()
+                .wait()
+                .unwrap()
+                .into_iter()
+                .find(|contact| contact.jid == JID)
+                .expect("contact is stored")
+                .name
+        }
+
+        #[test]
+        fn opens_with_passphrase() {
+            let dir = TempDir::new("db-test");
+            let key = DatabaseKey::Passphrase("correct horse".to_string());
+            let path = fixture(&dir, &key);
+
+            let db = Database::new(&path, Some(&key)).unwrap();
+            assert_eq!(contact_name(&db), "Alice");
+        }
+
+        #[test]
+        fn opens_with_raw_key() {
+            let dir = TempDir::new("db-test");
+            let key = DatabaseKey::Raw(vec![0x5a; 32]);
+            let path = fixture(&dir, &key);
+
+            let db = Database::new(&path, Some(&key)).unwrap();
+            assert_eq!(contact_name(&db), "Alice");
+        }
+
+        #[test]
+        fn wrong_or_missing_key_fails() {
+            let dir = TempDir::new("db-test");
+            let path = fixture(&dir, &DatabaseKey::Passphrase("correct horse".to_string()));
+
+            let wrong = DatabaseKey::Passphrase("battery staple".to_string());
+            assert!(Database::new(&path, Some(&wrong)).is_err());
+            assert!(Database::new(&path, None).is_err());
+        }
+
+        #[test]
+        fn plaintext_database_refuses_a_key() {
+            let dir = TempDir::new("db-test");
+            let path = dir.join("client.db");
+            drop(Database::new(&path, None).unwrap());
+
+            let key = DatabaseKey::Passphrase("correct horse".to_string());
+            assert!(Database::new(&path, Some(&key)).is_err());
+            // Left as it was, readable without a key
+            assert!(!db_key::is_encrypted(&path));
+            assert!(Database::new(&path, None).is_ok());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use rusqlite::Connection;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Secret that unlocks a SQLCipher-encrypted database
+#[derive(Clone)]
+pub enum DatabaseKey {
+    /// Passphrase, stretched by SQLCipher's key derivation
+    Passphrase(String),
+    /// 256-bit key used as is
+    Raw(Vec<u8>),
+}
+
+// Keep secrets out of logs
+impl fmt::Debug for DatabaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passphrase(_) => f.write_str("DatabaseKey::Passphrase(..)"),
+            Self::Raw(_) => f.write_str("DatabaseKey::Raw(..)"),
+        }
+    }
+}
+
+impl DatabaseKey {
+    /// Read a key file. A file holding exactly 64 hex digits is a raw key,
+    /// anything else is used as a passphrase. A trailing newline is ignored.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let contents = contents.trim_end_matches(['\r', '\n']);
+        if contents.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is empty", path.display()),
+            ));
+        }
+
+        if contents.len() == 64 && contents.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let raw = (0..64)
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&contents[i..i + 2], 16).unwrap())
+                .collect();
+            Ok(Self::Raw(raw))
+        } else {
+            Ok(Self::Passphrase(contents.to_string()))
+        }
+    }
+
+    /// Unlock `conn`. This has to be the first statement run on it.
+    pub fn apply(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute_batch(&format!("PRAGMA key = {};", self.sql_literal()))
+    }
+
+    /// Re-encrypt the database `conn` is unlocked for with this key
+    pub fn rekey(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute_batch(&format!("PRAGMA rekey = {};", self.sql_literal()))
+    }
+
+    /// The key as an SQL string literal, for `PRAGMA key` and `ATTACH ... KEY`
+    pub fn sql_literal(&self) -> String {
+        match self {
+            Self::Passphrase(passphrase) => format!("'{}'", passphrase.replace('\'', "''")),
+            Self::Raw(raw) => {
+                let hex: String = raw.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("\"x'{}'\"", hex)
+            }
+        }
+    }
+}
+
+/// Whether the file at `path` exists but isn't a plain SQLite database, which
+/// for our databases means it was encrypted
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    match fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(()) => &header != b"SQLite format 3\0",
+        Err(_) => false,
+    }
+}
+
+/// Whether this build is linked against SQLCipher
+pub fn encryption_available(conn: &Connection) -> bool {
+    conn.query_row("PRAGMA cipher_version", [], |row| row.get::<_, String>(0))
+        .is_ok()
+}
+
+/// Whether this build can open encrypted databases at all
+pub fn encryption_supported() -> bool {
+    Connection::open_in_memory().is_ok_and(|conn| encryption_available(&conn))
+}
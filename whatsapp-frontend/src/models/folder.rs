@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use super::Contact;
+
+/// A folder of chats, either made by the user or synced from a WhatsApp
+/// Business label
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Folder {
+    pub id: i64,
+    pub name: String,
+    /// Id of the Business label this folder mirrors. Such folders follow the
+    /// label and can't be changed locally.
+    pub label_id: Option<String>,
+}
+
+/// Which chats the sidebar lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatFilter {
+    All,
+    Unread,
+    Groups,
+    Personal,
+    Muted,
+    Archived,
+    Folder(i64),
+}
+
+impl ChatFilter {
+    /// Filters that always exist, in the order they are shown
+    pub const BUILT_IN: [ChatFilter; 6] = [
+        ChatFilter::All,
+        ChatFilter::Unread,
+        ChatFilter::Groups,
+        ChatFilter::Personal,
+        ChatFilter::Muted,
+        ChatFilter::Archived,
+    ];
+
+    /// Name of a built-in filter. Folders are shown by their own name.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChatFilter::All => "All",
+            ChatFilter::Unread => "Unread",
+            ChatFilter::Groups => "Groups",
+            ChatFilter::Personal => "Personal",
+            ChatFilter::Muted => "Muted",
+            ChatFilter::Archived => "Archived",
+            ChatFilter::Folder(_) => "",
+        }
+    }
+
+    /// Archived chats only show up under Archived, like on the phone, and
+    /// in folders they were put in
+    pub fn matches(&self, contact: &Contact) -> bool {
+        match self {
+            ChatFilter::Archived => contact.archived,
+            ChatFilter::Folder(id) => contact.folders.contains(id),
+            _ if contact.archived => false,
+            ChatFilter::All => true,
+            ChatFilter::Unread => contact.unread_count != 0,
+            ChatFilter::Groups => contact.is_group,
+            ChatFilter::Personal => !contact.is_group,
+            ChatFilter::Muted => contact.is_muted(),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+
+/// How many problems a report lists; the rest are only counted
+const LISTED_PROBLEMS: usize = 10;
+
+/// What a database check and repair found and did
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceReport {
+    /// Problems `PRAGMA integrity_check` reported before repairing
+    pub integrity_errors: Vec<String>,
+    /// Problems still reported after rebuilding the indexes
+    pub remaining_errors: Vec<String>,
+    /// Rows `PRAGMA foreign_key_check` reported
+    pub foreign_key_violations: usize,
+    /// Extra copies of a message id that were dropped, keeping the newest
+    pub duplicate_messages: usize,
+    /// Rows found pointing at a parent that is gone, per table. They were
+    /// dropped.
+    pub orphans: Vec<(String, usize)>,
+    /// File size before and after vacuuming, in bytes
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+impl MaintenanceReport {
+    /// Whether the database is still damaged after the repair
+    pub fn is_damaged(&self) -> bool {
+        !self.remaining_errors.is_empty()
+    }
+}
+
+impl fmt::Display for MaintenanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.integrity_errors.is_empty() {
+            writeln!(f, "Integrity check: ok")?;
+        } else {
+            writeln!(
+                f,
+                "Integrity check: {} problem(s)",
+                self.integrity_errors.len()
+            )?;
+            for error in self.integrity_errors.iter().take(LISTED_PROBLEMS) {
+                writeln!(f, "  {}", error)?;
+            }
+            if self.integrity_errors.len() > LISTED_PROBLEMS {
+                writeln!(
+                    f,
+                    "  … and {} more",
+                    self.integrity_errors.len() - LISTED_PROBLEMS
+                )?;
+            }
+            if self.is_damaged() {
+                writeln!(
+                    f,
+                    "Still damaged after rebuilding indexes ({} problem(s)); restore a backup",
+                    self.remaining_errors.len()
+                )?;
+            } else {
+                writeln!(f, "Repaired by rebuilding indexes")?;
+            }
+        }
+        writeln!(
+            f,
+            "Foreign key check: {}",
+            match self.foreign_key_violations {
+                0 => "ok".to_string(),
+                n => format!("{} violation(s)", n),
+            }
+        )?;
+        writeln!(f, "Duplicate messages removed: {}", self.duplicate_messages)?;
+        if self.orphans.iter().all(|(_, count)| *count == 0) {
+            writeln!(f, "Orphaned rows: none")?;
+        } else {
+            writeln!(f, "Orphaned rows removed:")?;
+            for (table, count) in self.orphans.iter().filter(|(_, count)| *count > 0) {
+                writeln!(f, "  {}: {}", table, count)?;
+            }
+        }
+        write!(
+            f,
+            "Size: {} → {}",
+            format_size(self.size_before),
+            format_size(self.size_after)
+        )
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= MB {
+        format!("{:.1} MB", bytes as f64 / MB)
+    } else {
+        format!("{} KB", bytes.div_ceil(1024))
+    }
+}
@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+/// A decrypted media file in the on-disk cache, keyed by the SHA-256 of its
+/// contents as reported by WhatsApp
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaEntry {
+    pub sha256: String, // Lowercase hex
+    pub mime_type: String,
+    pub size: i64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub local_path: String,
+    pub last_accessed: i64,
+}
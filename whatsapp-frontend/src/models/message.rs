@@ -1,4 +1,7 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::SendState;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
@@ -14,4 +17,67 @@ pub struct Message {
     pub quoted_message_id: Option<String>, // For replies
     pub media_url: Option<String>, // For media messages
     pub caption: Option<String>,  // For media captions
+    pub expires_at: Option<i64>,  // When a disappearing message is due
+    #[serde(default)]
+    pub starred: bool,
+    #[serde(default)]
+    pub edited_at: Option<i64>, // When the shown content was last edited
+    #[serde(default)]
+    pub send_state: Option<SendState>, // Set while our message is in the outbox
+}
+
+impl Message {
+    /// How long after sending a message WhatsApp accepts edits of it
+    pub const EDIT_WINDOW: i64 = 15 * 60;
+    /// How long after sending a message it can be deleted for everyone
+    pub const REVOKE_WINDOW: i64 = 60 * 60 * 60;
+    /// What a message deleted for everyone shows instead of its content
+    pub const REVOKED_CONTENT: &str = "This message was deleted";
+
+    /// The file we attached, kept in `media_url` until WhatsApp echoes the
+    /// message back with a URL of its own
+    pub fn local_media(&self) -> Option<&str> {
+        self.media_url.as_deref().filter(|url| url.starts_with('/'))
+    }
+
+    /// The `contextInfo` of a message body, which sits in whichever field
+    /// holds the content
+    pub fn context_info(body: &Value) -> Option<&Value> {
+        body.as_object()?
+            .values()
+            .find_map(|field| field.get("contextInfo"))
+    }
+
+    /// Author and text of the message this one replies to, as quoted in its
+    /// body. Stands in for the original when that isn't stored.
+    pub fn quote(&self) -> Option<(Option<String>, String)> {
+        let body: Value = serde_json::from_str(self.raw_data.as_deref()?).ok()?;
+        let context = Self::context_info(&body)?;
+        let quoted = context.get("quotedMessage")?;
+
+        let text = match quoted.get("conversation").and_then(|v| v.as_str()) {
+            Some(text) => text.to_string(),
+            None => {
+                let (field, value) = quoted.as_object()?.iter().next()?;
+                let written = value
+                    .get("text")
+                    .or_else(|| value.get("caption"))
+                    .and_then(|v| v.as_str());
+                match (written, field.as_str()) {
+                    (Some(text), _) => text.to_string(),
+                    (None, "imageMessage") => "[Image]".to_string(),
+                    (None, "videoMessage") => "[Video]".to_string(),
+                    (None, "audioMessage") => "[Audio]".to_string(),
+                    (None, "documentMessage") => "[Document]".to_string(),
+                    (None, "stickerMessage") => "[Sticker]".to_string(),
+                    (None, _) => "Message".to_string(),
+                }
+            }
+        };
+        let author = context
+            .get("participant")
+            .and_then(|v| v.as_str())
+            .map(|jid| jid.split('@').next().unwrap_or(jid).to_string());
+        Some((author, text))
+    }
 }
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// One version of an edited message. The stored message keeps the text it
+/// was sent with; what it shows is the text of its latest edit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageEdit {
+    pub message_id: String, // Message being edited
+    pub jid: String,        // Chat the message belongs to
+    pub content: String,    // Text after the edit, formatted like Message::content
+    pub edited_at: i64,
+    pub raw_data: Option<String>, // Full edit payload, kept for auditing
+}
@@ -1,7 +1,29 @@
+pub mod account;
+pub mod chat;
 pub mod contact;
 pub mod db;
+pub mod db_key;
+pub mod folder;
+pub mod maintenance;
+pub mod media;
 pub mod message;
+pub mod message_edit;
+pub mod outbox;
+pub mod reaction;
+pub mod retention;
+pub mod settings;
 
-pub use contact::Contact;
-pub use db::Database;
+pub use account::{Account, AccountList, DEFAULT_ACCOUNT_ID};
+pub use chat::ChatUpdate;
+pub use contact::{Contact, ContactUpdate};
+pub use db::{Database, Pending};
+pub use db_key::DatabaseKey;
+pub use folder::{ChatFilter, Folder};
+pub use maintenance::MaintenanceReport;
+pub use media::MediaEntry;
 pub use message::Message;
+pub use message_edit::MessageEdit;
+pub use outbox::{Attachment, OutboxEntry, SendState};
+pub use reaction::Reaction;
+pub use retention::RetentionPolicy;
+pub use settings::Settings;
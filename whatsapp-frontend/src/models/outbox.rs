@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Message;
+
+/// Where a message we sent is on its way out. Delivered messages have left
+/// the outbox and have no state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SendState {
+    /// Waiting to be sent, or to be tried again
+    Pending,
+    /// Gave up after too many attempts, until the user retries
+    Failed,
+}
+
+impl SendState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SendState::Pending => "pending",
+            SendState::Failed => "failed",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "pending" => Some(SendState::Pending),
+            "failed" => Some(SendState::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// A message waiting in the outbox to be sent
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub message_id: String,
+    pub jid: String,
+    /// The message, or the caption of an attachment
+    pub text: String,
+    pub attachment: Option<Attachment>,
+    /// Message this one replies to
+    pub quoted_message_id: Option<String>,
+    /// Failed attempts so far
+    pub attempts: u32,
+    /// Not tried again before this time
+    pub next_attempt: i64,
+}
+
+impl OutboxEntry {
+    /// Whether `message`, one of ours coming back from WhatsApp, is the one
+    /// this entry sent. A file sent from the phone meanwhile must not pass
+    /// for ours, so attachments also compare caption and size.
+    pub fn matches(&self, message: &Message) -> bool {
+        if message.jid != self.jid {
+            return false;
+        }
+        match &self.attachment {
+            Some(attachment) => {
+                message.message_type == attachment.kind()
+                    && message.content == attachment.preview(&self.text)
+                    && attachment.size.is_some()
+                    && file_length(message) == attachment.size
+            }
+            None => message.content == self.text,
+        }
+    }
+}
+
+/// Size of the file a media message carries, as its body states it. Baileys
+/// gives it as a number, a string or a `Long` split into halves.
+fn file_length(message: &Message) -> Option<u64> {
+    let body: Value = serde_json::from_str(message.raw_data.as_deref()?).ok()?;
+    let length = body
+        .as_object()?
+        .values()
+        .find_map(|field| field.get("fileLength"))?;
+    match length {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.parse().ok(),
+        Value::Object(long) => {
+            let low = long.get("low")?.as_i64()? as u32;
+            let high = long.get("high")?.as_i64()? as u32;
+            Some((high as u64) << 32 | low as u64)
+        }
+        _ => None,
+    }
+}
+
+/// A file sent along with a message, read from disk when it goes out
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub path: String,
+    pub mime_type: String,
+    /// Size of the file when it was picked, kept with the outbox entry so
+    /// its echo can be told apart even if the file moves meanwhile
+    pub size: Option<u64>,
+}
+
+impl Attachment {
+    /// Message type WhatsApp sends the file as. Anything that isn't a
+    /// picture, video or sound goes as a document.
+    pub fn kind(&self) -> &'static str {
+        match self.mime_type.split('/').next() {
+            Some("image") => "image",
+            Some("video") => "video",
+            Some("audio") => "audio",
+            _ => "document",
+        }
+    }
+
+    /// Name of the file, shown with documents
+    pub fn file_name(&self) -> &str {
+        std::path::Path::new(&self.path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("file")
+    }
+
+    /// Whether WhatsApp shows a caption with this kind of file
+    pub fn has_caption(&self) -> bool {
+        matches!(self.kind(), "image" | "video" | "document")
+    }
+
+    /// Content of a message carrying the file, in the form received media
+    /// is stored in
+    pub fn preview(&self, caption: &str) -> String {
+        match self.kind() {
+            "image" if !caption.is_empty() => format!("[Image] {}", caption),
+            "image" => "[Image]".to_string(),
+            "video" if !caption.is_empty() => format!("[Video] {}", caption),
+            "video" => "[Video]".to_string(),
+            "audio" => "[Audio]".to_string(),
+            _ => format!("[Document: {}]", self.file_name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JID: &str = "15551234567@s.whatsapp.net";
+
+    fn entry(size: Option<u64>) -> OutboxEntry {
+        OutboxEntry {
+            message_id: "local-1".to_string(),
+            jid: JID.to_string(),
+            text: "Sun & sea".to_string(),
+            attachment: Some(Attachment {
+                path: "/nonexistent/holiday.png".to_string(),
+                mime_type: "image/png".to_string(),
+                size,
+            }),
+            quoted_message_id: None,
+            attempts: 0,
+            next_attempt: 0,
+        }
+    }
+
+    /// An image coming back from WhatsApp, with the given body
+    fn echo(body: Value) -> Message {
+        Message {
+            id: None,
+            message_id: "3EB0SENT".to_string(),
+            jid: JID.to_string(),
+            sender: "me".to_string(),
+            content: "[Image] Sun & sea".to_string(),
+            timestamp: 1_700_000_000,
+            is_from_me: true,
+            message_type: "image".to_string(),
+            raw_data: Some(body.to_string()),
+            quoted_message_id: None,
+            media_url: None,
+            caption: Some("Sun & sea".to_string()),
+            expires_at: None,
+            starred: false,
+            edited_at: None,
+            send_state: None,
+        }
+    }
+
+    fn image(file_length: Value) -> Value {
+        serde_json::json!({
+            "imageMessage": { "caption": "Sun & sea", "fileLength": file_length }
+        })
+    }
+
+    #[test]
+    fn matches_recorded_size_after_the_file_is_gone() {
+        // The file was moved after sending, only the recorded size is left
+        assert!(entry(Some(300_000)).matches(&echo(image(300_000.into()))));
+        assert!(entry(Some(300_000)).matches(&echo(image("300000".into()))));
+        assert!(entry(Some(300_000)).matches(&echo(image(
+            serde_json::json!({ "low": 300_000, "high": 0, "unsigned": true })
+        ))));
+    }
+
+    #[test]
+    fn other_file_with_same_caption_does_not_match() {
+        assert!(!entry(Some(300_000)).matches(&echo(image(120_000.into()))));
+    }
+
+    #[test]
+    fn unknown_sizes_do_not_match() {
+        assert!(!entry(None).matches(&echo(image(300_000.into()))));
+        assert!(!entry(None).matches(&echo(serde_json::json!({
+            "imageMessage": { "caption": "Sun & sea" }
+        }))));
+    }
+}
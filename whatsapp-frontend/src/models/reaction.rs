@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// One person's reaction to a message. Each reactor has at most one reaction
+/// per message; a newer one replaces it and an empty emoji removes it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reaction {
+    pub message_id: String, // Message being reacted to
+    pub jid: String,        // Chat the message belongs to
+    pub reactor: String,    // Same format as Message::sender ("me" for our own)
+    pub emoji: String,
+    pub timestamp: i64,
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// How long messages are kept locally. With neither limit set everything is
+/// kept; with both, a message goes as soon as either limit is reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// Delete messages older than this many days
+    pub keep_days: Option<u32>,
+    /// Keep only this many of a chat's newest messages
+    pub keep_messages: Option<u32>,
+}
+
+impl RetentionPolicy {
+    pub const KEEP_ALL: Self = Self {
+        keep_days: None,
+        keep_messages: None,
+    };
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::RetentionPolicy;
+
+/// User preferences shared by all accounts, stored as `settings.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Size the media cache of each account is trimmed to, in megabytes
+    pub media_cache_limit_mb: u64,
+    /// Key file unlocking encrypted databases. Without one the passphrase is
+    /// asked for when an account's database turns out to be encrypted.
+    pub database_key_file: Option<PathBuf>,
+    /// How long messages are kept in chats without a rule of their own
+    pub retention: RetentionPolicy,
+    /// Whether contacts see when we're online and typing
+    pub share_presence: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            media_cache_limit_mb: 500,
+            database_key_file: None,
+            retention: RetentionPolicy::KEEP_ALL,
+            share_presence: true,
+        }
+    }
+}
+
+impl Settings {
+    /// Read the settings, falling back to defaults for anything missing
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Ignoring invalid {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+    }
+}
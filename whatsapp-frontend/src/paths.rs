@@ -0,0 +1,173 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::models::DEFAULT_ACCOUNT_ID;
+
+pub const APP_ID: &str = "org.aryan.whatsappgtk";
+
+/// Per-user data, cache and config directories, resolved following the XDG
+/// Base Directory specification
+#[derive(Debug, Clone)]
+pub struct AppPaths {
+    pub data_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    pub config_dir: PathBuf,
+}
+
+impl AppPaths {
+    pub fn resolve() -> Self {
+        Self {
+            data_dir: xdg_dir("XDG_DATA_HOME", ".local/share"),
+            cache_dir: xdg_dir("XDG_CACHE_HOME", ".cache"),
+            config_dir: xdg_dir("XDG_CONFIG_HOME", ".config"),
+        }
+    }
+
+    pub fn ensure_dirs(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.data_dir)?;
+        fs::create_dir_all(&self.cache_dir)?;
+        fs::create_dir_all(&self.config_dir)?;
+        Ok(())
+    }
+
+    /// Saved account profiles
+    pub fn accounts_file(&self) -> PathBuf {
+        self.config_dir.join("accounts.json")
+    }
+
+    /// Directory holding one account's database and credentials. The default
+    /// account lives directly in the data directory, so single-account
+    /// installs keep their existing layout.
+    pub fn account_dir(&self, account_id: &str) -> PathBuf {
+        if account_id == DEFAULT_ACCOUNT_ID {
+            self.data_dir.clone()
+        } else {
+            self.data_dir.join("accounts").join(account_id)
+        }
+    }
+
+    /// Application preferences
+    pub fn settings_file(&self) -> PathBuf {
+        self.config_dir.join("settings.json")
+    }
+
+    /// Decrypted media downloaded for an account. Laid out like
+    /// [`Self::account_dir`], but under the cache directory since everything
+    /// in it can be downloaded again.
+    pub fn media_dir(&self, account_id: &str) -> PathBuf {
+        if account_id == DEFAULT_ACCOUNT_ID {
+            self.cache_dir.join("media")
+        } else {
+            self.cache_dir
+                .join("accounts")
+                .join(account_id)
+                .join("media")
+        }
+    }
+
+    /// SQLite database holding an account's chats, contacts and messages
+    pub fn database(&self, account_id: &str) -> PathBuf {
+        self.account_dir(account_id).join("client.db")
+    }
+
+    /// Baileys credentials directory, handed to the account's backend on startup
+    pub fn auth_dir(&self, account_id: &str) -> PathBuf {
+        self.account_dir(account_id).join("auth")
+    }
+
+    /// Move data left behind by older builds, which kept the database in
+    /// `db/` relative to the working directory and the credentials in the
+    /// backend's own `auth/` folder. Nothing is touched once the new
+    /// location exists, so this only ever runs once.
+    pub fn migrate_legacy(&self, backend_path: &Path) {
+        let database = self.database(DEFAULT_ACCOUNT_ID);
+        if !database.exists() {
+            let mut candidates = vec![PathBuf::from("../db/client.db")];
+            if let Some(root) = backend_path.parent() {
+                candidates.push(root.join("db").join("client.db"));
+            }
+
+            if let Some(old) = candidates.into_iter().find(|p| p.is_file()) {
+                println!(
+                    "Migrating database from {} to {}",
+                    old.display(),
+                    database.display()
+                );
+                // WAL sidecar files belong to the database and must move with it
+                for suffix in ["", "-wal", "-shm"] {
+                    let from = append_to_path(&old, suffix);
+                    if from.exists() {
+                        if let Err(e) = move_path(&from, &append_to_path(&database, suffix)) {
+                            eprintln!("Failed to migrate {}: {}", from.display(), e);
+                        }
+                    }
+                }
+            }
+        }
+
+        let auth_dir = self.auth_dir(DEFAULT_ACCOUNT_ID);
+        let old_auth = backend_path.join("auth");
+        if !auth_dir.exists() && old_auth.join("creds.json").is_file() {
+            println!(
+                "Migrating credentials from {} to {}",
+                old_auth.display(),
+                auth_dir.display()
+            );
+            if let Err(e) = move_path(&old_auth, &auth_dir) {
+                eprintln!("Failed to migrate {}: {}", old_auth.display(), e);
+            }
+        }
+    }
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    // The spec says relative values must be ignored
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| home_dir().join(fallback))
+        .join(APP_ID)
+}
+
+fn home_dir() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+pub(crate) fn append_to_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+/// Rename `from` to `to`, falling back to copy and delete when they are on
+/// different filesystems
+pub(crate) fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if from.is_dir() {
+        copy_dir(from, to)?;
+        fs::remove_dir_all(from)
+    } else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    }
+}
+
+pub(crate) fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
@@ -1,147 +1,584 @@
-use reqwest::blocking::Client;
+use futures_util::TryStreamExt;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::{Body, Client, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::{oneshot, watch};
+use tokio_util::io::ReaderStream;
 
+use super::api_types::{
+    DeleteRequest, EditRequest, EditResponse, Empty, Envelope, LastMessage, MediaDownloadRequest,
+    Presence, PresenceRequest, Quoted, ReactRequest, ReadRequest, RequestQrResponse,
+    SendMediaQuery, SendRequest, SendResponse, StarRequest, UnreadRequest, message_key,
+};
+use crate::models::{Attachment, Message};
+
+/// How long to wait for the backend to accept a connection. Requests
+/// themselves aren't limited, the backend gives up on WhatsApp on its own.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why a call to the backend failed
+#[derive(Debug)]
+pub enum ApiError {
+    /// The backend couldn't be reached, e.g. it isn't running
+    Unreachable(reqwest::Error),
+    /// The backend is up but not connected to WhatsApp (503)
+    NotConnected,
+    /// The backend refused the request as malformed (400)
+    BadRequest(String),
+    /// The backend doesn't know the route (404)
+    NotFound(String),
+    /// The request was understood but declined, reported as `ok: false`
+    Rejected(String),
+    /// Any other failure status, usually WhatsApp itself failing
+    Server { status: u16, message: String },
+    /// The response didn't have the expected shape
+    InvalidResponse(String),
+    /// The client was shut down before the answer arrived
+    Cancelled,
+    /// A file to upload couldn't be read
+    File(std::io::Error),
+}
+
+impl ApiError {
+    fn from_status(status: StatusCode, reason: Option<String>) -> Self {
+        let reason = reason.unwrap_or_else(|| {
+            status
+                .canonical_reason()
+                .unwrap_or("Unknown error")
+                .to_string()
+        });
+        match status {
+            StatusCode::BAD_REQUEST => ApiError::BadRequest(reason),
+            StatusCode::NOT_FOUND => ApiError::NotFound(reason),
+            StatusCode::SERVICE_UNAVAILABLE => ApiError::NotConnected,
+            _ => ApiError::Server {
+                status: status.as_u16(),
+                message: reason,
+            },
+        }
+    }
+
+    /// Whether trying again later may succeed, as opposed to the request
+    /// itself being refused
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ApiError::Unreachable(_) | ApiError::NotConnected => true,
+            ApiError::Server { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Short explanation to show in the UI
+    pub fn user_message(&self) -> String {
+        match self {
+            ApiError::Unreachable(_) => "Can't reach the WhatsApp service".to_string(),
+            ApiError::NotConnected => "WhatsApp is not connected".to_string(),
+            ApiError::BadRequest(reason) => format!("Invalid request: {}", reason),
+            ApiError::NotFound(_) => "Not supported by the WhatsApp service".to_string(),
+            ApiError::Rejected(reason) => reason.clone(),
+            ApiError::Server { message, .. } => format!("WhatsApp error: {}", message),
+            ApiError::InvalidResponse(_) => {
+                "Unexpected response from the WhatsApp service".to_string()
+            }
+            ApiError::Cancelled => "Cancelled".to_string(),
+            ApiError::File(e) => format!("Can't read the attached file: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Unreachable(e) => write!(f, "backend unreachable: {}", e),
+            ApiError::NotConnected => write!(f, "WhatsApp not connected"),
+            ApiError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            ApiError::NotFound(reason) => write!(f, "not found: {}", reason),
+            ApiError::Rejected(reason) => write!(f, "rejected: {}", reason),
+            ApiError::Server { status, message } => write!(f, "HTTP {}: {}", status, message),
+            ApiError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+            ApiError::Cancelled => write!(f, "cancelled"),
+            ApiError::File(e) => write!(f, "unreadable file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Unreachable(e) => Some(e),
+            ApiError::File(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            ApiError::InvalidResponse(e.to_string())
+        } else {
+            ApiError::Unreachable(e)
+        }
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// Threads running the requests of every account's client
+const WORKER_THREADS: usize = 2;
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(WORKER_THREADS)
+            .thread_name("api-worker")
+            .enable_all()
+            .build()
+            .expect("Failed to start the API runtime")
+    })
+}
+
+/// Result of a request running on the API runtime.
+///
+/// Await it from `glib::MainContext::spawn_local`, or block on it with
+/// [`ApiCall::wait`] from a thread of your own. The request is sent as soon
+/// as the `ApiCall` is created, dropping it only discards the answer.
+pub struct ApiCall<T> {
+    rx: oneshot::Receiver<ApiResult<T>>,
+}
+
+impl<T> ApiCall<T> {
+    /// Block the current thread until the answer arrives. Never call it from
+    /// a GTK callback.
+    pub fn wait(self) -> ApiResult<T> {
+        self.rx.blocking_recv().unwrap_or(Err(ApiError::Cancelled))
+    }
+}
+
+impl<T> Future for ApiCall<T> {
+    type Output = ApiResult<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|r| r.unwrap_or(Err(ApiError::Cancelled)))
+    }
+}
+
+/// Client for one account's backend. Requests run on a shared Tokio runtime
+/// so the UI never waits on the network.
 pub struct ApiClient {
     client: Client,
     base_url: String,
+    cancelled: watch::Sender<bool>,
 }
 
 impl ApiClient {
     pub fn new(base_url: &str) -> Self {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .unwrap_or_default();
         Self {
-            client: Client::new(),
+            client,
             base_url: base_url.to_string(),
+            cancelled: watch::channel(false).0,
         }
     }
 
-    pub fn send_message(&self, jid: &str, text: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let response = self
-            .client
-            .post(&format!("{}/send", self.base_url))
-            .json(&serde_json::json!({
-                "jid": jid,
-                "text": text
-            }))
-            .send()?;
-
-        let result: Value = response.json()?;
+    /// Abort every request in flight and fail any made later with
+    /// [`ApiError::Cancelled`]. Called when the window closes.
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
 
-        if result["ok"].as_bool().unwrap_or(false) {
-            Ok(())
-        } else {
-            Err(format!("Failed to send message: {:?}", result["error"]).into())
-        }
+    /// Run `request` on the runtime unless the client is cancelled first
+    fn spawn<T, F>(&self, request: F) -> ApiCall<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = ApiResult<T>> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let mut cancelled = self.cancelled.subscribe();
+        runtime().spawn(async move {
+            let result = tokio::select! {
+                result = request => result,
+                _ = cancelled.wait_for(|&cancelled| cancelled) => Err(ApiError::Cancelled),
+            };
+            let _ = tx.send(result);
+        });
+        ApiCall { rx }
     }
 
-    pub fn get_contacts(&self) -> Result<Vec<crate::models::Contact>, Box<dyn std::error::Error>> {
-        let response = self
+    /// Send a text message, as a reply to `quoted` if given. WhatsApp's key
+    /// for it comes back once it went out.
+    pub fn send_message(
+        &self,
+        jid: &str,
+        text: &str,
+        quoted: Option<&Quoted>,
+    ) -> ApiCall<SendResponse> {
+        let request = self
             .client
-            .get(&format!("{}/contacts", self.base_url))
-            .send()?;
-
-        let result: Value = response.json()?;
-
-        // Backend returns { success: bool, contacts: [...], count: number }
-        let contacts_array = result["contacts"]
-            .as_array()
-            .ok_or("Expected 'contacts' array in response")?;
-
-        Ok(contacts_array
-            .iter()
-            .filter_map(|c| {
-                Some(crate::models::Contact {
-                    jid: c["jid"].as_str()?.to_string(),
-                    name: c["name"].as_str()?.to_string(),
-                    last_message: None,
-                    last_message_time: None,
-                    unread_count: c["unreadCount"].as_i64().unwrap_or(0) as i32,
-                    conversation_timestamp: c["conversationTimestamp"].as_i64().unwrap_or(0),
-                    is_group: c["isGroup"].as_bool().unwrap_or(false),
-                    archived: c["archived"].as_bool().unwrap_or(false),
-                    pinned: c["pinned"].as_i64().unwrap_or(0),
-                    mute_end_time: c["muteEndTime"].as_i64().unwrap_or(0),
-                    profile_picture_url: None, // Will be fetched separately if needed
-                })
-            })
-            .collect())
+            .post(format!("{}/send", self.base_url))
+            .json(&SendRequest { jid, text, quoted });
+
+        self.spawn(async move { Ok(parse::<SendResponse>(request.send().await?).await?.data) })
     }
 
-    pub fn get_profile_picture(
+    /// Send a file, with a caption unless it's empty. The file is streamed
+    /// from disk, and `uploaded` counts the bytes read from it so far.
+    pub fn send_media(
         &self,
         jid: &str,
-    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        // Based on Baileys documentation: profilePictureUrl(jid, 'image')
-        let response = self
+        attachment: &Attachment,
+        caption: &str,
+        uploaded: Arc<AtomicU64>,
+    ) -> ApiCall<SendResponse> {
+        let request = self
             .client
-            .get(&format!("{}/profile-picture", self.base_url))
-            .query(&[("jid", jid)])
-            .send()?;
+            .post(format!("{}/send-media", self.base_url))
+            .query(&SendMediaQuery {
+                jid,
+                caption,
+                file_name: attachment.file_name(),
+            })
+            .header(CONTENT_TYPE, &attachment.mime_type);
+        let path = attachment.path.clone();
 
-        if response.status().is_success() {
-            let result: Value = response.json()?;
-            Ok(result["url"].as_str().map(|s| s.to_string()))
-        } else {
-            Ok(None)
-        }
+        self.spawn(async move {
+            let file = tokio::fs::File::open(&path).await.map_err(ApiError::File)?;
+            let size = file.metadata().await.map_err(ApiError::File)?.len();
+            let stream = ReaderStream::new(file).inspect_ok(move |chunk| {
+                uploaded.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            });
+            let response = request
+                .header(CONTENT_LENGTH, size)
+                .body(Body::wrap_stream(stream))
+                .send()
+                .await?;
+            Ok(parse::<SendResponse>(response).await?.data)
+        })
     }
 
-    pub fn get_messages(
+    /// Star or unstar a message on WhatsApp, which syncs it to the phone
+    pub fn star_message(
         &self,
         jid: &str,
-    ) -> Result<Vec<crate::models::Message>, Box<dyn std::error::Error>> {
-        let response = self
+        message_id: &str,
+        from_me: bool,
+        star: bool,
+    ) -> ApiCall<()> {
+        let request = self
             .client
-            .get(&format!("{}/messages/{}", self.base_url, jid))
-            .send()?;
-
-        let messages: Vec<Value> = response.json()?;
-
-        Ok(messages
-            .iter()
-            .filter_map(|m| {
-                Some(crate::models::Message {
-                    id: None,
-                    message_id: m["message_id"].as_str()?.to_string(),
-                    jid: m["jid"].as_str()?.to_string(),
-                    sender: m["sender"].as_str()?.to_string(),
-                    content: m["content"].as_str()?.to_string(),
-                    timestamp: m["timestamp"].as_i64()?,
-                    is_from_me: m["is_from_me"].as_bool()?,
-                    message_type: m["message_type"].as_str().unwrap_or("text").to_string(),
-                    raw_data: m["raw_data"].as_str().map(|s| s.to_string()),
-                    quoted_message_id: m["quoted_message_id"].as_str().map(|s| s.to_string()),
-                    media_url: m["media_url"].as_str().map(|s| s.to_string()),
-                    caption: m["caption"].as_str().map(|s| s.to_string()),
-                })
-            })
-            .collect())
+            .post(format!("{}/messages/star", self.base_url))
+            .json(&StarRequest {
+                jid,
+                id: message_id,
+                from_me,
+                star,
+            });
+
+        self.spawn(async move { parse::<Empty>(request.send().await?).await.map(|_| ()) })
     }
 
-    pub fn request_qr(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let response = self
+    /// React to a message with `emoji`, or take our reaction back with an
+    /// empty one
+    pub fn react(&self, message: &Message, emoji: &str) -> ApiCall<()> {
+        let request = self
             .client
-            .post(&format!("{}/auth/request-qr", self.base_url))
-            .send()?;
+            .post(format!("{}/messages/react", self.base_url))
+            .json(&ReactRequest {
+                jid: &message.jid,
+                key: message_key(message),
+                text: emoji,
+            });
 
-        let result: Value = response.json()?;
+        self.spawn(async move { parse::<Empty>(request.send().await?).await.map(|_| ()) })
+    }
+
+    /// Replace the text of a message we sent. Resolves to when WhatsApp
+    /// records the edit as made, in seconds, the time the echoed edit
+    /// carries too.
+    pub fn edit_message(&self, message: &Message, text: &str) -> ApiCall<i64> {
+        let request = self
+            .client
+            .post(format!("{}/messages/edit", self.base_url))
+            .json(&EditRequest {
+                jid: &message.jid,
+                key: message_key(message),
+                text,
+            });
+
+        self.spawn(async move {
+            let edited_at = parse::<EditResponse>(request.send().await?)
+                .await?
+                .data
+                .edited_at;
+            Ok(edited_at / 1000)
+        })
+    }
+
+    /// Delete a message for everyone in the chat, or only from our own
+    /// devices
+    pub fn delete_message(&self, message: &Message, for_everyone: bool) -> ApiCall<()> {
+        let request = self
+            .client
+            .post(format!("{}/messages/delete", self.base_url))
+            .json(&DeleteRequest {
+                jid: &message.jid,
+                key: message_key(message),
+                timestamp: message.timestamp,
+                for_everyone,
+            });
+
+        self.spawn(async move { parse::<Empty>(request.send().await?).await.map(|_| ()) })
+    }
+
+    /// Send read receipts for messages we received
+    pub fn mark_read(&self, messages: &[Message]) -> ApiCall<()> {
+        let request = self
+            .client
+            .post(format!("{}/chats/read", self.base_url))
+            .json(&ReadRequest {
+                keys: messages.iter().map(message_key).collect(),
+            });
+
+        self.spawn(async move { parse::<Empty>(request.send().await?).await.map(|_| ()) })
+    }
+
+    /// Mark the chat of `last_message`, its newest one, as unread
+    pub fn mark_unread(&self, last_message: &Message) -> ApiCall<()> {
+        let request = self
+            .client
+            .post(format!("{}/chats/unread", self.base_url))
+            .json(&UnreadRequest {
+                jid: &last_message.jid,
+                last_message: LastMessage {
+                    key: message_key(last_message),
+                    message_timestamp: last_message.timestamp,
+                },
+            });
+
+        self.spawn(async move { parse::<Empty>(request.send().await?).await.map(|_| ()) })
+    }
+
+    /// Tell `jid` whether we're typing, or everyone whether we're online
+    /// when it's `None`
+    pub fn send_presence(&self, presence: Presence, jid: Option<&str>) -> ApiCall<()> {
+        let request = self
+            .client
+            .post(format!("{}/presence", self.base_url))
+            .json(&PresenceRequest { presence, jid });
+
+        self.spawn(async move { parse::<Empty>(request.send().await?).await.map(|_| ()) })
+    }
+
+    /// Fetch the decrypted contents of a media message. `message` is the
+    /// message body as received over the WebSocket.
+    pub fn download_media(&self, key: &Value, message: &Value) -> ApiCall<Vec<u8>> {
+        let request = self
+            .client
+            .post(format!("{}/media/download", self.base_url))
+            .json(&MediaDownloadRequest { key, message });
+
+        self.spawn(async move { Ok(check(request.send().await?).await?.bytes().await?.to_vec()) })
+    }
+
+    /// Ask the backend to start linking. Returns the QR code right away if
+    /// a connection was already waiting for one, as it won't be sent over
+    /// the WebSocket again.
+    pub fn request_qr(&self) -> ApiCall<Option<String>> {
+        let request = self
+            .client
+            .post(format!("{}/auth/request-qr", self.base_url));
 
-        if result["success"].as_bool().unwrap_or(false) {
+        self.spawn(async move {
+            let result = parse::<RequestQrResponse>(request.send().await?).await?;
             println!(
                 "QR request successful: {}",
-                result["message"].as_str().unwrap_or("")
+                result.message.as_deref().unwrap_or("")
             );
-            Ok(())
-        } else {
-            Err(format!("Failed to request QR: {:?}", result["message"]).into())
+            Ok(result.data.qr)
+        })
+    }
+}
+
+/// Turn a failure status into an `ApiError`, using the reason the backend
+/// gave in its JSON body if any
+async fn check(response: Response) -> ApiResult<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let reason = response
+        .json::<Envelope<Empty>>()
+        .await
+        .ok()
+        .and_then(|body| body.reason().map(str::to_string));
+    Err(ApiError::from_status(status, reason))
+}
+
+/// Decode a JSON response, treating `ok: false` or `success: false` as a
+/// rejection
+async fn parse<T: DeserializeOwned>(response: Response) -> ApiResult<Envelope<T>> {
+    let envelope: Envelope<T> = check(response).await?.json().await?;
+    if envelope.ok == Some(false) {
+        let reason = envelope.reason().unwrap_or("Request failed").to_string();
+        return Err(ApiError::Rejected(reason));
+    }
+    Ok(envelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// What the stand-in backend received
+    struct Upload {
+        request_line: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Upload {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        fn query(&self) -> Vec<(String, String)> {
+            let target = self.request_line.split(' ').nth(1).unwrap();
+            let query = target.split_once('?').map_or("", |(_, query)| query);
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
         }
     }
 
-    pub fn get_auth_status(&self) -> Result<Value, Box<dyn std::error::Error>> {
-        let response = self
-            .client
-            .get(&format!("{}/auth/status", self.base_url))
-            .send()?;
+    /// Serve one `POST /send-media` on a local port the way the backend
+    /// answers it, handing back what was uploaded
+    async fn stub_backend() -> (String, tokio::task::JoinHandle<Upload>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 8192];
+            let head_end = loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed before the headers ended");
+                received.extend_from_slice(&buf[..n]);
+                if let Some(i) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break i;
+                }
+            };
+
+            let head = String::from_utf8(received[..head_end].to_vec()).unwrap();
+            let mut lines = head.split("\r\n");
+            let request_line = lines.next().unwrap().to_string();
+            let headers: Vec<(String, String)> = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .collect();
+            let length: usize = headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+                .expect("upload has a Content-Length")
+                .1
+                .parse()
+                .unwrap();
+
+            let mut body = received[head_end + 4..].to_vec();
+            while body.len() < length {
+                let n = socket.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed before the body ended");
+                body.extend_from_slice(&buf[..n]);
+            }
+
+            let answer = r#"{"ok":true,"key":{"remoteJid":"123@s.whatsapp.net","fromMe":true,"id":"3EB0SENT"}}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                answer.len(),
+                answer
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            socket.shutdown().await.unwrap();
+
+            Upload {
+                request_line,
+                headers,
+                body,
+            }
+        });
+        (base_url, server)
+    }
+
+    #[tokio::test]
+    async fn send_media_streams_the_file() {
+        let dir = TempDir::new("send-media");
+        let path = dir.join("holiday photo.png");
+        // Large enough to go out in several chunks
+        let contents: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+        let attachment = Attachment {
+            path: path.to_string_lossy().into_owned(),
+            mime_type: "image/png".to_string(),
+            size: Some(contents.len() as u64),
+        };
+
+        let (base_url, server) = stub_backend().await;
+        let api = ApiClient::new(&base_url);
+        let uploaded = Arc::new(AtomicU64::new(0));
+        let sent = api
+            .send_media(
+                "123@s.whatsapp.net",
+                &attachment,
+                "Sun & sea",
+                Arc::clone(&uploaded),
+            )
+            .await
+            .unwrap();
+        let upload = server.await.unwrap();
 
-        Ok(response.json()?)
+        assert_eq!(sent.key.id, "3EB0SENT");
+        assert!(upload.request_line.starts_with("POST /send-media?"));
+        let query = upload.query();
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(param("jid"), Some("123@s.whatsapp.net"));
+        assert_eq!(param("caption"), Some("Sun & sea"));
+        assert_eq!(param("fileName"), Some("holiday photo.png"));
+        assert_eq!(upload.header("content-type"), Some("image/png"));
+        assert_eq!(
+            upload.header("content-length"),
+            Some(contents.len().to_string().as_str())
+        );
+        assert!(
+            upload.body == contents,
+            "uploaded body differs from the file"
+        );
+        assert_eq!(uploaded.load(Ordering::Relaxed), contents.len() as u64);
     }
 }
@@ -0,0 +1,196 @@
+//! Bodies sent to and received from the backend's HTTP routes
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::events::WAKey;
+use crate::models::Message;
+
+/// Fields every JSON response may carry next to its data. Some routes report
+/// success as `ok`, others as `success`; routes returning bare data have
+/// neither.
+#[derive(Debug, Deserialize)]
+pub struct Envelope<T> {
+    #[serde(default, alias = "success")]
+    pub ok: Option<bool>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(flatten)]
+    pub data: T,
+}
+
+impl<T> Envelope<T> {
+    /// The backend's explanation of a failure, if it gave one
+    pub fn reason(&self) -> Option<&str> {
+        self.error.as_deref().or(self.message.as_deref())
+    }
+}
+
+/// Response without data beyond the envelope
+#[derive(Debug, Deserialize)]
+pub struct Empty {}
+
+/// `POST /send`
+#[derive(Debug, Serialize)]
+pub struct SendRequest<'a> {
+    pub jid: &'a str,
+    pub text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quoted: Option<&'a Quoted>,
+}
+
+/// The message a reply quotes, in the shape Baileys builds the quote from
+#[derive(Debug, Serialize)]
+pub struct Quoted {
+    pub key: Value,
+    pub message: Value,
+}
+
+impl Quoted {
+    /// Quotes the body of `message` as received. Our own messages have none
+    /// until WhatsApp echoes them back, so until then the quote carries
+    /// just their stored text.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let body = match message.raw_data.as_deref() {
+            Some(raw) => serde_json::from_str(raw).ok()?,
+            None => serde_json::json!({ "conversation": message.content }),
+        };
+        Some(Self {
+            key: message_key(message),
+            message: body,
+        })
+    }
+}
+
+/// Key of a stored message in Baileys' format
+pub fn message_key(message: &Message) -> Value {
+    let mut key = serde_json::json!({
+        "remoteJid": message.jid,
+        "fromMe": message.is_from_me,
+        "id": message.message_id,
+    });
+    // Group messages need their author to be found
+    if message.jid.ends_with("@g.us") && !message.is_from_me {
+        key["participant"] = Value::from(format!("{}@s.whatsapp.net", message.sender));
+    }
+    key
+}
+
+/// `POST /send`, with the key WhatsApp gave the sent message
+#[derive(Debug, Deserialize)]
+pub struct SendResponse {
+    pub key: WAKey,
+}
+
+/// Query of `POST /send-media`, whose body is the file itself
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendMediaQuery<'a> {
+    pub jid: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub caption: &'a str,
+    pub file_name: &'a str,
+}
+
+/// `POST /messages/star`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StarRequest<'a> {
+    pub jid: &'a str,
+    pub id: &'a str,
+    pub from_me: bool,
+    pub star: bool,
+}
+
+/// `POST /messages/react`
+#[derive(Debug, Serialize)]
+pub struct ReactRequest<'a> {
+    pub jid: &'a str,
+    pub key: Value,
+    pub text: &'a str,
+}
+
+/// `POST /messages/edit`
+#[derive(Debug, Serialize)]
+pub struct EditRequest<'a> {
+    pub jid: &'a str,
+    pub key: Value,
+    pub text: &'a str,
+}
+
+/// `POST /messages/edit`, with when WhatsApp records the edit as made, in
+/// milliseconds
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditResponse {
+    pub edited_at: i64,
+}
+
+/// `POST /messages/delete`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRequest<'a> {
+    pub jid: &'a str,
+    pub key: Value,
+    pub timestamp: i64,
+    pub for_everyone: bool,
+}
+
+/// `POST /chats/read`
+#[derive(Debug, Serialize)]
+pub struct ReadRequest {
+    pub keys: Vec<Value>,
+}
+
+/// `POST /chats/unread`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnreadRequest<'a> {
+    pub jid: &'a str,
+    pub last_message: LastMessage,
+}
+
+/// A chat's last message, as WhatsApp wants it for changes to the chat
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastMessage {
+    pub key: Value,
+    pub message_timestamp: i64,
+}
+
+/// What we tell others about ourselves through `POST /presence`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Presence {
+    Available,
+    Unavailable,
+    Composing,
+    Paused,
+}
+
+/// `POST /presence`. Typing goes to one chat, being online to everyone.
+#[derive(Debug, Serialize)]
+pub struct PresenceRequest<'a> {
+    #[serde(rename = "type")]
+    pub presence: Presence,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jid: Option<&'a str>,
+}
+
+/// `POST /media/download`. Both are passed through from the message as
+/// received over the WebSocket.
+#[derive(Debug, Serialize)]
+pub struct MediaDownloadRequest<'a> {
+    pub key: &'a Value,
+    pub message: &'a Value,
+}
+
+/// `POST /auth/request-qr`. The QR code itself arrives over the WebSocket
+/// unless a connection was already waiting for one.
+#[derive(Debug, Deserialize)]
+pub struct RequestQrResponse {
+    #[serde(default)]
+    pub qr: Option<String>,
+}
@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 
 #[derive(Debug, Deserialize, Clone)]
@@ -7,6 +7,13 @@ pub struct WAMessage {
     #[serde(rename = "messageTimestamp")]
     pub timestamp: i64,
     pub message: Option<Value>, // Changed to Value to capture all message data
+    /// Only present on messages replayed from history
+    #[serde(default)]
+    pub starred: Option<bool>,
+    /// Set for messages that just arrived (`messages.upsert` of type
+    /// `notify`) as opposed to ones replayed from history
+    #[serde(skip)]
+    pub is_live: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -32,6 +39,20 @@ pub struct ExtendedTextMessage {
     pub text: String,
 }
 
+/// One entry of a `messages.update` event: a change to a message sent or
+/// received earlier
+#[derive(Debug, Deserialize, Clone)]
+pub struct WAMessageUpdate {
+    pub key: WAKey,
+    pub update: WAMessageChanges,
+}
+
+/// The fields of a message that changed. Anything not listed here is ignored.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct WAMessageChanges {
+    pub starred: Option<bool>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct WAContact {
     pub id: String,
@@ -39,6 +60,35 @@ pub struct WAContact {
     pub notify: Option<String>,
 }
 
+/// A WhatsApp Business label, from `labels.edit`
+#[derive(Debug, Deserialize, Clone)]
+pub struct WALabel {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// A label put on or taken off something, from `labels.association`
+#[derive(Debug, Deserialize, Clone)]
+pub struct WALabelAssociation {
+    /// "add" or "remove"
+    #[serde(rename = "type")]
+    pub action: String,
+    pub association: WALabelTarget,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WALabelTarget {
+    /// "label_jid" for chats, "label_message" for single messages
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(rename = "chatId")]
+    pub chat_id: String,
+    #[serde(rename = "labelId")]
+    pub label_id: String,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct WAChat {
     pub id: String,
@@ -47,10 +97,51 @@ pub struct WAChat {
     pub unread_count: Option<i32>,
     #[serde(rename = "conversationTimestamp")]
     pub conversation_timestamp: Option<u64>,
-    pub archived: Option<bool>,
-    pub pinned: Option<i64>,
-    #[serde(rename = "muteEndTime")]
-    pub mute_end_time: Option<i64>,
+    // `chats.update` sends null when a chat is unarchived, unpinned or
+    // unmuted, which is different from the field not being there at all
+    #[serde(default, deserialize_with = "present")]
+    pub archived: Option<Option<bool>>,
+    #[serde(default, deserialize_with = "present")]
+    pub pinned: Option<Option<i64>>,
+    #[serde(rename = "muteEndTime", default, deserialize_with = "present")]
+    pub mute_end_time: Option<Option<i64>>,
+    /// Lifetime of disappearing messages in seconds, 0 or null when off
+    #[serde(rename = "ephemeralExpiration", default, deserialize_with = "present")]
+    pub ephemeral_expiration: Option<Option<i64>>,
+    #[serde(
+        rename = "ephemeralSettingTimestamp",
+        default,
+        deserialize_with = "long"
+    )]
+    pub ephemeral_setting_timestamp: Option<i64>,
+}
+
+/// Deserialize a field that may be missing, null or set into `None`,
+/// `Some(None)` and `Some(Some(value))` respectively
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Deserialize a 64-bit protobuf field, which arrives as a number, a string
+/// or a `{low, high}` object depending on where Baileys got it from
+fn long<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::Number(n)) => n.as_i64(),
+        Some(Value::String(s)) => s.parse().ok(),
+        Some(Value::Object(map)) => {
+            let low = map.get("low").and_then(|v| v.as_i64()).unwrap_or(0);
+            let high = map.get("high").and_then(|v| v.as_i64()).unwrap_or(0);
+            Some((high << 32) | (low & 0xffff_ffff))
+        }
+        _ => None,
+    })
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -71,11 +162,23 @@ pub struct ConnectionUpdate {
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum EventPayload {
-    ConnectionUpdate(ConnectionUpdate),
-    MessagesUpsert { messages: Vec<WAMessage> },
-    ChatsSet { chats: Vec<WAChat> },
-    ChatsUpdate(Vec<WAChat>),
-    ContactsSet { contacts: Vec<WAContact> },
+    // Untagged variants are tried in order, so the most specific shapes come
+    // first. `ConnectionUpdate` has only optional fields and would match any
+    // object, and `ChatsSet` would swallow a full history set.
     MessagingHistorySet(MessagingHistorySet),
+    MessagesUpsert {
+        messages: Vec<WAMessage>,
+        #[serde(rename = "type")]
+        upsert_type: Option<String>,
+    },
+    ChatsSet {
+        chats: Vec<WAChat>,
+    },
+    ChatsUpdate(Vec<WAChat>),
+    MessagesUpdate(Vec<WAMessageUpdate>),
+    ContactsSet {
+        contacts: Vec<WAContact>,
+    },
+    ConnectionUpdate(ConnectionUpdate),
     Other(serde_json::Value),
 }
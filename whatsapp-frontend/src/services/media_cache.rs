@@ -0,0 +1,237 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+use super::ApiClient;
+use super::api_types::message_key;
+use crate::models::{Database, MediaEntry, Message};
+
+/// Message types whose body carries downloadable media, by the key Baileys
+/// stores it under
+const MEDIA_KEYS: [(&str, &str); 5] = [
+    ("image", "imageMessage"),
+    ("video", "videoMessage"),
+    ("audio", "audioMessage"),
+    ("document", "documentMessage"),
+    ("sticker", "stickerMessage"),
+];
+
+/// Decrypted media stored on disk by content hash.
+///
+/// Files live at `<dir>/<first two hex digits>/<sha256>` and are tracked in
+/// the `media` table. When the cache grows past its limit the least recently
+/// used files are deleted.
+pub struct MediaCache {
+    db: Arc<Database>,
+    api: Arc<ApiClient>,
+    dir: PathBuf,
+    limit_bytes: i64,
+}
+
+impl MediaCache {
+    pub fn new(db: Arc<Database>, api: Arc<ApiClient>, dir: PathBuf, limit_mb: u64) -> Self {
+        Self {
+            db,
+            api,
+            dir,
+            limit_bytes: (limit_mb * 1024 * 1024) as i64,
+        }
+    }
+
+    /// Return the cached file for a media message, downloading it first if
+    /// needed
+    pub async fn fetch(&self, message: &Message) -> Result<MediaEntry, Box<dyn std::error::Error>> {
+        let info = MediaInfo::from_message(message).ok_or("Message has no downloadable media")?;
+
+        if let Some(entry) = self.db.get_media(&info.sha256).await? {
+            if Path::new(&entry.local_path).is_file() {
+                self.db.touch_media(&entry.sha256, now());
+                return Ok(entry);
+            }
+        }
+
+        let bytes = self.api.download_media(&info.key, &info.body).await?;
+
+        // Verify and write on a worker thread so the UI stays responsive
+        let (tx, rx) = oneshot::channel();
+        let path = self.path_for(&info.sha256);
+        let local_path = path.to_string_lossy().into_owned();
+        let sha256 = info.sha256.clone();
+        std::thread::spawn(move || {
+            let result = store(&path, &sha256, &bytes).map(|()| bytes.len());
+            let _ = tx.send(result);
+        });
+        let size = rx.await.map_err(|_| "Media download was cancelled")??;
+
+        let entry = MediaEntry {
+            sha256: info.sha256,
+            mime_type: info.mime_type,
+            size: size as i64,
+            width: info.width,
+            height: info.height,
+            local_path,
+            last_accessed: now(),
+        };
+        self.db.save_media(&entry).await?;
+        self.evict(&entry.sha256).await;
+        Ok(entry)
+    }
+
+    /// Delete the cached files of messages that no longer exist
+    pub async fn forget(&self, messages: &[Message]) {
+        let mut removed = Vec::new();
+        for info in messages.iter().filter_map(MediaInfo::from_message) {
+            let path = self.path_for(&info.sha256);
+            match fs::remove_file(&path) {
+                Ok(()) => removed.push(info.sha256),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => removed.push(info.sha256),
+                Err(e) => eprintln!("[Media] Failed to remove {}: {}", path.display(), e),
+            }
+        }
+
+        if !removed.is_empty() {
+            println!("[Media] Removed {} files of pruned messages", removed.len());
+            if let Err(e) = self.db.delete_media(removed).await {
+                eprintln!("[Media] Failed to forget removed files: {}", e);
+            }
+        }
+    }
+
+    /// Delete least recently used files until the cache fits its limit. The
+    /// file that was just added is always kept.
+    async fn evict(&self, keep: &str) {
+        let victims = match self.db.media_over_limit(self.limit_bytes).await {
+            Ok(victims) => victims,
+            Err(e) => {
+                eprintln!("[Media] Failed to query cache size: {}", e);
+                return;
+            }
+        };
+
+        let mut removed = Vec::new();
+        for entry in victims.into_iter().filter(|e| e.sha256 != keep) {
+            match fs::remove_file(&entry.local_path) {
+                Ok(()) => removed.push(entry.sha256),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => removed.push(entry.sha256),
+                Err(e) => eprintln!("[Media] Failed to remove {}: {}", entry.local_path, e),
+            }
+        }
+
+        if !removed.is_empty() {
+            println!("[Media] Evicted {} cached files", removed.len());
+            if let Err(e) = self.db.delete_media(removed).await {
+                eprintln!("[Media] Failed to forget evicted files: {}", e);
+            }
+        }
+    }
+
+    fn path_for(&self, sha256: &str) -> PathBuf {
+        self.dir.join(&sha256[..2]).join(sha256)
+    }
+}
+
+/// What is needed to download and file one media message
+struct MediaInfo {
+    sha256: String,
+    mime_type: String,
+    width: Option<i32>,
+    height: Option<i32>,
+    /// Message key in Baileys' format
+    key: Value,
+    /// Message body as received, including the media key and CDN path
+    body: Value,
+}
+
+impl MediaInfo {
+    fn from_message(message: &Message) -> Option<Self> {
+        let (_, field) = MEDIA_KEYS
+            .iter()
+            .find(|(kind, _)| *kind == message.message_type)?;
+        let body: Value = serde_json::from_str(message.raw_data.as_deref()?).ok()?;
+        let media = body.get(*field)?;
+
+        let sha256 = decode_bytes(media.get("fileSha256")?)?;
+        if sha256.len() != 32 {
+            return None;
+        }
+
+        // Group messages need the participant for re-upload requests
+        let key = message_key(message);
+
+        let mime_type = media
+            .get("mimetype")
+            .and_then(|v| v.as_str())
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let width = media
+            .get("width")
+            .and_then(|v| v.as_i64())
+            .map(|v| v as i32);
+        let height = media
+            .get("height")
+            .and_then(|v| v.as_i64())
+            .map(|v| v as i32);
+
+        Some(Self {
+            sha256: to_hex(&sha256),
+            mime_type,
+            width,
+            height,
+            key,
+            body,
+        })
+    }
+}
+
+/// Decode a binary field. The backend encodes buffers as
+/// `{"type": "Buffer", "data": "<base64>"}`; older rows have the data as a
+/// byte array or the field as a bare base64 string.
+fn decode_bytes(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::String(s) => STANDARD.decode(s).ok(),
+        Value::Object(map) => match map.get("data")? {
+            Value::String(s) => STANDARD.decode(s).ok(),
+            Value::Array(bytes) => bytes
+                .iter()
+                .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Check the download against its expected hash and move it into place
+fn store(path: &Path, sha256: &str, bytes: &[u8]) -> Result<(), String> {
+    let actual = to_hex(&Sha256::digest(bytes));
+    if actual != sha256 {
+        return Err(format!(
+            "Checksum mismatch: expected {}, got {}",
+            sha256, actual
+        ));
+    }
+
+    let dir = path.parent().ok_or("Invalid cache path")?;
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    // Write to a temporary name first so a crash never leaves a truncated
+    // file under the final name
+    let tmp = path.with_extension("part");
+    fs::write(&tmp, bytes).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, path).map_err(|e| e.to_string())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
@@ -1,6 +1,15 @@
 pub mod api_client;
+pub mod api_types;
 pub mod events;
+pub mod media_cache;
+pub mod outbox;
+pub mod presence;
+pub mod retention;
 pub mod ws_client;
 
-pub use api_client::ApiClient;
+pub use api_client::{ApiClient, ApiError};
+pub use media_cache::MediaCache;
+pub use outbox::{OutboxSender, OutboxUpdate};
+pub use presence::PresenceSender;
+pub use retention::Pruner;
 pub use ws_client::WebSocketClient;
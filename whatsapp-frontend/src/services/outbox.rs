@@ -0,0 +1,251 @@
+use futures_util::future::{Either, select};
+use gtk4::glib;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::api_client::ApiResult;
+use super::api_types::{Quoted, SendResponse};
+use super::{ApiClient, ApiError};
+use crate::models::{Attachment, Database, Message, OutboxEntry};
+
+/// Attempts at sending a message before it's marked failed
+const MAX_ATTEMPTS: u32 = 5;
+/// Seconds before the first retry, doubled for every one after it
+const RETRY_DELAY: i64 = 5;
+/// How often the upload progress of an attachment is reported
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What happened to a message in the outbox
+pub enum OutboxUpdate<'a> {
+    /// Share of its attachment uploaded so far, from 0 to 1
+    Progress(f64),
+    /// Delivered, and given this id by WhatsApp
+    Sent(&'a str),
+    /// Given up on until the user retries
+    Failed,
+}
+
+type OnUpdate = Box<dyn Fn(&str, OutboxUpdate)>;
+
+/// Background job delivering the messages in the outbox.
+///
+/// A chat's messages go out one at a time in the order they were written,
+/// and one waiting for a retry holds back those after it. Whatever is still
+/// pending when the app quits goes out on the next start.
+pub struct OutboxSender {
+    db: Arc<Database>,
+    api: Arc<ApiClient>,
+    /// Called on the GTK thread with our id of a message and what happened
+    /// to it
+    on_update: OnUpdate,
+    /// The message being sent right now, whose echo may arrive before the
+    /// backend answers
+    in_flight: RefCell<Option<OutboxEntry>>,
+    running: Cell<bool>,
+    /// Set when kicked during a run, so it goes over the outbox once more
+    rerun: Cell<bool>,
+    timer: RefCell<Option<glib::SourceId>>,
+}
+
+impl OutboxSender {
+    pub fn new<F>(db: Arc<Database>, api: Arc<ApiClient>, on_update: F) -> Rc<Self>
+    where
+        F: Fn(&str, OutboxUpdate) + 'static,
+    {
+        Rc::new(Self {
+            db,
+            api,
+            on_update: Box::new(on_update),
+            in_flight: RefCell::new(None),
+            running: Cell::new(false),
+            rerun: Cell::new(false),
+            timer: RefCell::new(None),
+        })
+    }
+
+    /// Send whatever is waiting, e.g. once a message was queued. The queued
+    /// write has to be awaited first or it may not be seen.
+    pub fn kick(self: &Rc<Self>) {
+        if self.running.replace(true) {
+            self.rerun.set(true);
+            return;
+        }
+        if let Some(timer) = self.timer.borrow_mut().take() {
+            timer.remove();
+        }
+
+        let sender = Rc::clone(self);
+        glib::MainContext::default().spawn_local(async move {
+            let mut next_retry = sender.send_pending().await;
+            while sender.rerun.replace(false) {
+                next_retry = sender.send_pending().await;
+            }
+            sender.running.set(false);
+
+            if let Some(due) = next_retry {
+                sender.schedule(due);
+            }
+        });
+    }
+
+    /// Match our own message coming back through `messages.upsert` with the
+    /// one being sent, if it's that one. Returns whether it was.
+    pub fn echoed(self: &Rc<Self>, message: &Message) -> bool {
+        let mut in_flight = self.in_flight.borrow_mut();
+        let Some(entry) = in_flight.take_if(|entry| entry.matches(message)) else {
+            return false;
+        };
+        drop(in_flight);
+
+        let sender = Rc::clone(self);
+        let sent_id = message.message_id.clone();
+        glib::MainContext::default().spawn_local(async move {
+            sender.mark_sent(&entry.message_id, &sent_id).await;
+        });
+        true
+    }
+
+    /// Swap our id of a delivered message for WhatsApp's
+    async fn mark_sent(&self, message_id: &str, sent_id: &str) {
+        if let Err(e) = self.db.outbox_sent(message_id, sent_id).await {
+            eprintln!(
+                "[Outbox] Failed to take {} out of the outbox: {}",
+                message_id, e
+            );
+        }
+        (self.on_update)(message_id, OutboxUpdate::Sent(sent_id));
+    }
+
+    /// The message a reply quotes. It goes out without the quote if that
+    /// message is gone or can't be quoted.
+    async fn quoted(&self, entry: &OutboxEntry) -> Option<Quoted> {
+        let quoted_id = entry.quoted_message_id.as_deref()?;
+        match self.db.get_message(quoted_id).await {
+            Ok(message) => message.as_ref().and_then(Quoted::from_message),
+            Err(e) => {
+                eprintln!("[Outbox] Failed to load quoted {}: {}", quoted_id, e);
+                None
+            }
+        }
+    }
+
+    /// Upload an attachment, reporting how far it got while it goes
+    async fn send_attachment(
+        &self,
+        entry: &OutboxEntry,
+        attachment: &Attachment,
+    ) -> ApiResult<SendResponse> {
+        let size = std::fs::metadata(&attachment.path).map_or(0, |m| m.len());
+        let uploaded = Arc::new(AtomicU64::new(0));
+        let mut call =
+            self.api
+                .send_media(&entry.jid, attachment, &entry.text, Arc::clone(&uploaded));
+
+        loop {
+            match select(&mut call, glib::timeout_future(PROGRESS_INTERVAL)).await {
+                Either::Left((result, _)) => return result,
+                Either::Right(_) if size > 0 => {
+                    let done = uploaded.load(Ordering::Relaxed) as f64 / size as f64;
+                    (self.on_update)(&entry.message_id, OutboxUpdate::Progress(done.min(1.0)));
+                }
+                Either::Right(_) => {}
+            }
+        }
+    }
+
+    fn schedule(self: &Rc<Self>, due: i64) {
+        let delay = (due - now()).max(1);
+        let sender = Rc::clone(self);
+        let timer = glib::timeout_add_seconds_local_once(delay as u32, move || {
+            sender.timer.borrow_mut().take();
+            sender.kick();
+        });
+        *self.timer.borrow_mut() = Some(timer);
+    }
+
+    /// One pass over the outbox. Returns when the earliest retry is due.
+    async fn send_pending(&self) -> Option<i64> {
+        let entries = match self.db.get_outbox().await {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("[Outbox] Failed to load the outbox: {}", e);
+                return None;
+            }
+        };
+
+        // Chats with a message waiting for its retry
+        let mut held = HashSet::new();
+        let mut next_retry: Option<i64> = None;
+        for entry in entries {
+            if held.contains(&entry.jid) {
+                continue;
+            }
+            if entry.next_attempt > now() {
+                next_retry =
+                    Some(next_retry.map_or(entry.next_attempt, |due| due.min(entry.next_attempt)));
+                held.insert(entry.jid);
+                continue;
+            }
+
+            *self.in_flight.borrow_mut() = Some(entry.clone());
+            let result = match &entry.attachment {
+                Some(attachment) => self.send_attachment(&entry, attachment).await,
+                None => {
+                    let quoted = self.quoted(&entry).await;
+                    self.api
+                        .send_message(&entry.jid, &entry.text, quoted.as_ref())
+                        .await
+                }
+            };
+            // Taken already if the echo was quicker
+            if self.in_flight.take().is_none() {
+                continue;
+            }
+
+            match result {
+                Ok(sent) => self.mark_sent(&entry.message_id, &sent.key.id).await,
+                // The window is closing, the rest goes out next time
+                Err(ApiError::Cancelled) => return None,
+                Err(e) => {
+                    let attempts = entry.attempts + 1;
+                    eprintln!(
+                        "[Outbox] Failed to send {} (attempt {}): {}",
+                        entry.message_id, attempts, e
+                    );
+                    let retry_at = (e.is_transient() && attempts < MAX_ATTEMPTS)
+                        .then(|| now() + (RETRY_DELAY << (attempts - 1)));
+                    if let Err(e) = self
+                        .db
+                        .outbox_attempt_failed(&entry.message_id, &e.to_string(), retry_at)
+                        .await
+                    {
+                        eprintln!(
+                            "[Outbox] Failed to record attempt at {}: {}",
+                            entry.message_id, e
+                        );
+                    }
+
+                    match retry_at {
+                        Some(due) => {
+                            next_retry = Some(next_retry.map_or(due, |next| next.min(due)));
+                            held.insert(entry.jid);
+                        }
+                        None => (self.on_update)(&entry.message_id, OutboxUpdate::Failed),
+                    }
+                }
+            }
+        }
+        next_retry
+    }
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
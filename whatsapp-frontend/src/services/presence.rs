@@ -0,0 +1,129 @@
+use gtk4::glib;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::ApiClient;
+use super::api_types::Presence;
+
+/// How long after the last keystroke we stop showing as typing
+const PAUSE_AFTER: Duration = Duration::from_secs(5);
+/// How often "typing" is repeated while we keep typing, since the other
+/// side stops showing it after a while
+const COMPOSING_REFRESH: Duration = Duration::from_secs(10);
+
+/// The chat we last told we're typing
+struct Typing {
+    jid: String,
+    sent_at: Instant,
+    pause: glib::SourceId,
+}
+
+/// Tells the open chat when we're typing and everyone whether we're at the
+/// window, unless sharing presence is turned off.
+pub struct PresenceSender {
+    api: Arc<ApiClient>,
+    enabled: Cell<bool>,
+    typing: RefCell<Option<Typing>>,
+}
+
+impl PresenceSender {
+    pub fn new(api: Arc<ApiClient>, enabled: bool) -> Rc<Self> {
+        Rc::new(Self {
+            api,
+            enabled: Cell::new(enabled),
+            typing: RefCell::new(None),
+        })
+    }
+
+    /// Turn sharing on or off. Turning it off ends our typing and shows us
+    /// as offline.
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.enabled.replace(enabled) == enabled {
+            return;
+        }
+        if !enabled {
+            self.stop_typing();
+            self.send(Presence::Unavailable, None);
+        }
+    }
+
+    /// Note the text typed in `jid`, empty once it was sent or cleared
+    pub fn typing(self: &Rc<Self>, jid: &str, has_text: bool) {
+        if !self.enabled.get() {
+            return;
+        }
+        if !has_text {
+            self.stop_typing();
+            return;
+        }
+
+        let mut sent_at = None;
+        let previous = self.typing.borrow_mut().take();
+        if let Some(typing) = previous {
+            typing.pause.remove();
+            if typing.jid == jid {
+                sent_at = Some(typing.sent_at);
+            } else {
+                self.send(Presence::Paused, Some(&typing.jid));
+            }
+        }
+        // "Typing" goes out now and then, not on every keystroke
+        let sent_at = match sent_at {
+            Some(sent_at) if sent_at.elapsed() < COMPOSING_REFRESH => sent_at,
+            _ => {
+                self.send(Presence::Composing, Some(jid));
+                Instant::now()
+            }
+        };
+
+        let sender = Rc::downgrade(self);
+        let pause = glib::timeout_add_local_once(PAUSE_AFTER, move || {
+            let Some(sender) = sender.upgrade() else {
+                return;
+            };
+            // The timer is done, so there's nothing to remove
+            if let Some(typing) = sender.typing.borrow_mut().take() {
+                sender.send(Presence::Paused, Some(&typing.jid));
+            }
+        });
+        *self.typing.borrow_mut() = Some(Typing {
+            jid: jid.to_string(),
+            sent_at,
+            pause,
+        });
+    }
+
+    /// Show as online while the window has focus and offline otherwise
+    pub fn set_focused(&self, focused: bool) {
+        if !self.enabled.get() {
+            return;
+        }
+        if !focused {
+            self.stop_typing();
+        }
+        let presence = if focused {
+            Presence::Available
+        } else {
+            Presence::Unavailable
+        };
+        self.send(presence, None);
+    }
+
+    fn stop_typing(&self) {
+        if let Some(typing) = self.typing.borrow_mut().take() {
+            typing.pause.remove();
+            self.send(Presence::Paused, Some(&typing.jid));
+        }
+    }
+
+    fn send(&self, presence: Presence, jid: Option<&str>) {
+        let call = self.api.send_presence(presence, jid);
+        glib::MainContext::default().spawn_local(async move {
+            if let Err(e) = call.await {
+                eprintln!("[Presence] Failed to send {:?}: {}", presence, e);
+            }
+        });
+    }
+}
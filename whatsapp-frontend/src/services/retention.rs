@@ -0,0 +1,101 @@
+use gtk4::glib;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::MediaCache;
+use crate::models::{Database, Message, RetentionPolicy};
+
+/// Longest time between two pruning runs, in seconds
+const PRUNE_INTERVAL: i64 = 60 * 60;
+
+/// Background job deleting messages that expired or fall outside their
+/// chat's retention policy, along with their cached media.
+///
+/// It runs once an hour, and additionally whenever a disappearing message is
+/// due so those vanish on time.
+pub struct Pruner {
+    db: Arc<Database>,
+    media: Arc<MediaCache>,
+    policy: RetentionPolicy,
+    /// Called on the GTK thread with every batch of deleted messages
+    on_pruned: Box<dyn Fn(Vec<Message>)>,
+    /// When the pending timer fires
+    next_run: Cell<i64>,
+    timer: RefCell<Option<glib::SourceId>>,
+}
+
+impl Pruner {
+    pub fn new<F>(
+        db: Arc<Database>,
+        media: Arc<MediaCache>,
+        policy: RetentionPolicy,
+        on_pruned: F,
+    ) -> Rc<Self>
+    where
+        F: Fn(Vec<Message>) + 'static,
+    {
+        Rc::new(Self {
+            db,
+            media,
+            policy,
+            on_pruned: Box::new(on_pruned),
+            next_run: Cell::new(i64::MAX),
+            timer: RefCell::new(None),
+        })
+    }
+
+    /// Prune now and keep pruning from then on
+    pub fn start(self: &Rc<Self>) {
+        self.run();
+    }
+
+    /// Make sure a run happens by `due`, e.g. when a disappearing message
+    /// was just stored
+    pub fn schedule(self: &Rc<Self>, due: i64) {
+        if due >= self.next_run.get() {
+            return;
+        }
+        if let Some(timer) = self.timer.borrow_mut().take() {
+            timer.remove();
+        }
+
+        let delay = (due - now()).clamp(1, PRUNE_INTERVAL);
+        self.next_run.set(now() + delay);
+        let pruner = Rc::clone(self);
+        let timer = glib::timeout_add_seconds_local_once(delay as u32, move || {
+            pruner.timer.borrow_mut().take();
+            pruner.run();
+        });
+        *self.timer.borrow_mut() = Some(timer);
+    }
+
+    fn run(self: &Rc<Self>) {
+        self.next_run.set(i64::MAX);
+        let pruner = Rc::clone(self);
+        glib::MainContext::default().spawn_local(async move {
+            match pruner.db.prune(pruner.policy, now()).await {
+                Ok(pruned) if !pruned.is_empty() => {
+                    pruner.media.forget(&pruned).await;
+                    (pruner.on_pruned)(pruned);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("[Retention] Failed to prune messages: {}", e),
+            }
+
+            let next_expiry = pruner.db.next_expiry().await.unwrap_or_else(|e| {
+                eprintln!("[Retention] Failed to look up the next expiry: {}", e);
+                None
+            });
+            let due = next_expiry.unwrap_or(i64::MAX);
+            pruner.schedule(due.min(now() + PRUNE_INTERVAL));
+        });
+    }
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
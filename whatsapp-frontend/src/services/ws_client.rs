@@ -11,8 +11,13 @@ pub enum WhatsAppEvent {
     QrCode(String),
     Connected,
     Message(events::WAMessage),
+    MessageUpdate(events::WAMessageUpdate),
     Contact(events::WAContact),
     Chat(events::WAChat),
+    /// A chat from `chats.update`, which only carries what changed
+    ChatUpdate(events::WAChat),
+    Label(events::WALabel),
+    LabelAssociation(events::WALabelAssociation),
 }
 
 pub struct WebSocketClient {
@@ -85,13 +90,28 @@ impl WebSocketClient {
             }
             Some("messages.upsert") => {
                 if let Ok(data) = serde_json::from_value::<events::EventPayload>(payload.clone()) {
-                    if let events::EventPayload::MessagesUpsert { messages } = data {
-                        for msg in messages {
+                    if let events::EventPayload::MessagesUpsert {
+                        messages,
+                        upsert_type,
+                    } = data
+                    {
+                        let is_live = upsert_type.as_deref() == Some("notify");
+                        for mut msg in messages {
+                            msg.is_live = is_live;
                             tx.send(WhatsAppEvent::Message(msg))?;
                         }
                     }
                 }
             }
+            Some("messages.update") => {
+                if let Ok(data) = serde_json::from_value::<events::EventPayload>(payload.clone()) {
+                    if let events::EventPayload::MessagesUpdate(updates) = data {
+                        for update in updates {
+                            tx.send(WhatsAppEvent::MessageUpdate(update))?;
+                        }
+                    }
+                }
+            }
             Some("chats.set") => {
                 if let Ok(data) = serde_json::from_value::<events::EventPayload>(payload.clone()) {
                     if let events::EventPayload::ChatsSet { chats } = data {
@@ -105,7 +125,7 @@ impl WebSocketClient {
                 if let Ok(data) = serde_json::from_value::<events::EventPayload>(payload.clone()) {
                     if let events::EventPayload::ChatsUpdate(chats) = data {
                         for chat in chats {
-                            tx.send(WhatsAppEvent::Chat(chat))?;
+                            tx.send(WhatsAppEvent::ChatUpdate(chat))?;
                         }
                     }
                 }
@@ -119,6 +139,15 @@ impl WebSocketClient {
                     }
                 }
             }
+            Some("labels.edit") => {
+                let label: events::WALabel = serde_json::from_value(payload.clone())?;
+                tx.send(WhatsAppEvent::Label(label))?;
+            }
+            Some("labels.association") => {
+                let association: events::WALabelAssociation =
+                    serde_json::from_value(payload.clone())?;
+                tx.send(WhatsAppEvent::LabelAssociation(association))?;
+            }
             Some("messaging-history.set") => {
                 if let Ok(data) = serde_json::from_value::<events::EventPayload>(payload.clone()) {
                     if let events::EventPayload::MessagingHistorySet(history) = data {
@@ -0,0 +1,1409 @@
+use adw::prelude::*;
+use gtk4::gio;
+use gtk4::prelude::*;
+use libadwaita as adw;
+use std::cell::OnceCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc;
+use tokio::sync::oneshot;
+
+use crate::models::{
+    self, Account, Attachment, Database, DatabaseKey, Pending, RetentionPolicy, SendState,
+    Settings, db_key,
+};
+use crate::paths::AppPaths;
+use crate::services::events::{WAChat, WAContact, WAMessage};
+use crate::services::ws_client::WhatsAppEvent;
+use crate::services::{
+    ApiClient, ApiError, MediaCache, OutboxSender, OutboxUpdate, PresenceSender, Pruner,
+    WebSocketClient,
+};
+use crate::ui::{MainView, ProfileButton, QrView};
+
+/// Everything belonging to one linked account: its database, API client,
+/// event stream and the widgets shown while it is the active account
+pub struct AccountSession {
+    pub account: Account,
+    /// Set once the database is open. An encrypted database stays closed
+    /// until its passphrase is entered.
+    pub db: Rc<OnceCell<Arc<Database>>>,
+    /// Client for the account's backend
+    pub api: Arc<ApiClient>,
+    /// Window content for this account. Its child switches from the QR view
+    /// to the chat view once the account is linked.
+    pub root: adw::Bin,
+}
+
+impl AccountSession {
+    pub fn new(
+        account: Account,
+        paths: &AppPaths,
+        settings: &Settings,
+        accounts_menu: &gio::Menu,
+    ) -> Self {
+        if let Err(e) = std::fs::create_dir_all(paths.account_dir(&account.id)) {
+            eprintln!(
+                "Failed to create data directory for {}: {}",
+                account.name, e
+            );
+        }
+
+        let root = adw::Bin::new();
+        let db = Rc::new(OnceCell::new());
+        let api = Arc::new(ApiClient::new(&account.api_url()));
+        let path = paths.database(&account.id);
+
+        let key = settings.database_key_file.as_ref().and_then(|file| {
+            DatabaseKey::from_file(file)
+                .map_err(|e| eprintln!("Failed to read key file {}: {}", file.display(), e))
+                .ok()
+        });
+
+        let encrypted = db_key::is_encrypted(&path);
+        if (encrypted || key.is_some()) && !db_key::encryption_supported() {
+            // No key would ever be accepted, so don't ask for one
+            eprintln!(
+                "Database for {} needs encryption support this build lacks",
+                account.name
+            );
+            Self::show_unsupported_view(&root, &account, accounts_menu);
+            return Self {
+                account,
+                db,
+                api,
+                root,
+            };
+        }
+
+        let opened = if encrypted {
+            key.as_ref()
+                .and_then(|key| match Database::new(&path, Some(key)) {
+                    Ok(database) => Some(database),
+                    Err(e) => {
+                        eprintln!("Failed to unlock database for {}: {}", account.name, e);
+                        None
+                    }
+                })
+        } else {
+            // A key can't open a database that was never encrypted, and a
+            // prompt for one would never pass. A new database is created
+            // encrypted with it though.
+            let key = key.filter(|_| {
+                if path.exists() {
+                    eprintln!(
+                        "Database for {} is not encrypted, opening it without the key. \
+                         Run `wa-db-crypt encrypt {}` with the app closed to encrypt it.",
+                        account.name,
+                        path.display()
+                    );
+                    false
+                } else {
+                    true
+                }
+            });
+            match Database::new(&path, key.as_ref()) {
+                Ok(database) => Some(database),
+                Err(e) => panic!("Failed to open database: {}", e),
+            }
+        };
+
+        match opened {
+            Some(database) => Self::start(
+                &root,
+                &account,
+                paths,
+                settings,
+                &db,
+                database,
+                &api,
+                accounts_menu,
+            ),
+            None => {
+                // Ask for the passphrase before anything touches the database
+                let root_clone = root.clone();
+                let account_clone = account.clone();
+                let paths = paths.clone();
+                let settings = settings.clone();
+                let db = Rc::clone(&db);
+                let api = Arc::clone(&api);
+                let menu = accounts_menu.clone();
+                Self::show_unlock_view(&root, &account, accounts_menu, path, move |database| {
+                    Self::start(
+                        &root_clone,
+                        &account_clone,
+                        &paths,
+                        &settings,
+                        &db,
+                        database,
+                        &api,
+                        &menu,
+                    );
+                });
+            }
+        }
+
+        Self {
+            account,
+            db,
+            api,
+            root,
+        }
+    }
+
+    /// Bring up the account once its database is open
+    #[allow(clippy::too_many_arguments)]
+    fn start(
+        root: &adw::Bin,
+        account: &Account,
+        paths: &AppPaths,
+        settings: &Settings,
+        cell: &OnceCell<Arc<Database>>,
+        database: Database,
+        api: &Arc<ApiClient>,
+        accounts_menu: &gio::Menu,
+    ) {
+        let db = Arc::new(database);
+        let _ = cell.set(Arc::clone(&db));
+
+        let media = Arc::new(MediaCache::new(
+            Arc::clone(&db),
+            Arc::clone(api),
+            paths.media_dir(&account.id),
+            settings.media_cache_limit_mb,
+        ));
+
+        // Check if already authenticated
+        if db.is_authenticated() {
+            Self::show_main_view(
+                root,
+                account,
+                &db,
+                api,
+                &media,
+                settings.retention,
+                accounts_menu,
+            );
+        } else {
+            Self::show_qr_view(
+                root,
+                account,
+                &db,
+                api,
+                &media,
+                settings.retention,
+                accounts_menu,
+            );
+        }
+    }
+
+    /// Header of the pages shown while the database is closed, with the
+    /// account switcher so another account can still be picked
+    fn locked_header(account: &Account, accounts_menu: &gio::Menu) -> adw::HeaderBar {
+        let header = adw::HeaderBar::new();
+        header.add_css_class("flat");
+        let profile_button = ProfileButton::new();
+        profile_button.set_profile_pic(None, Some(&account.name));
+        profile_button.set_accounts_section(accounts_menu);
+        header.pack_start(profile_button.widget());
+        header
+    }
+
+    /// Shown instead of the passphrase prompt when this build can't open
+    /// encrypted databases at all
+    fn show_unsupported_view(root: &adw::Bin, account: &Account, accounts_menu: &gio::Menu) {
+        let status = adw::StatusPage::builder()
+            .icon_name("dialog-error-symbolic")
+            .title("Encryption Not Supported")
+            .description(format!(
+                "{}'s chat history is encrypted, or a database key is set, but this \
+                 build was made without database encryption. Install a build with \
+                 encryption support, or remove the key file from the settings.",
+                account.name
+            ))
+            .vexpand(true)
+            .build();
+
+        let page = gtk4::Box::new(gtk4::Orientation::Vertical, 0);
+        page.append(&Self::locked_header(account, accounts_menu));
+        page.append(&status);
+        root.set_child(Some(&page));
+    }
+
+    /// Passphrase prompt for the encrypted database at `path`. Each attempt
+    /// opens it on a thread of its own, as deriving the key takes a while,
+    /// and `on_unlocked` gets the database once a passphrase fits.
+    fn show_unlock_view<F>(
+        root: &adw::Bin,
+        account: &Account,
+        accounts_menu: &gio::Menu,
+        path: PathBuf,
+        on_unlocked: F,
+    ) where
+        F: Fn(Database) + 'static,
+    {
+        let passphrase_entry = gtk4::PasswordEntry::builder()
+            .show_peek_icon(true)
+            .placeholder_text("Passphrase")
+            .build();
+        let unlock_button = gtk4::Button::builder()
+            .label("Unlock")
+            .css_classes(vec!["suggested-action", "pill"])
+            .halign(gtk4::Align::Center)
+            .build();
+
+        let form = gtk4::Box::new(gtk4::Orientation::Vertical, 12);
+        form.set_halign(gtk4::Align::Center);
+        form.set_width_request(300);
+        form.append(&passphrase_entry);
+        form.append(&unlock_button);
+
+        let status = adw::StatusPage::builder()
+            .icon_name("dialog-password-symbolic")
+            .title("Database Locked")
+            .description(format!(
+                "Enter the passphrase for {}'s encrypted chat history",
+                account.name
+            ))
+            .child(&form)
+            .vexpand(true)
+            .build();
+
+        let page = gtk4::Box::new(gtk4::Orientation::Vertical, 0);
+        page.append(&Self::locked_header(account, accounts_menu));
+        page.append(&status);
+        root.set_child(Some(&page));
+
+        let on_unlocked = Rc::new(on_unlocked);
+        let try_unlock = Rc::new({
+            let passphrase_entry = passphrase_entry.clone();
+            let unlock_button = unlock_button.clone();
+            let status = status.clone();
+            move || {
+                let passphrase = passphrase_entry.text().to_string();
+                if passphrase.is_empty() || !unlock_button.is_sensitive() {
+                    return;
+                }
+                passphrase_entry.set_sensitive(false);
+                unlock_button.set_sensitive(false);
+                status.set_description(Some("Unlocking…"));
+
+                let (tx, rx) = oneshot::channel();
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let key = DatabaseKey::Passphrase(passphrase);
+                    let _ = tx.send(Database::new(&path, Some(&key)));
+                });
+
+                let passphrase_entry = passphrase_entry.clone();
+                let unlock_button = unlock_button.clone();
+                let status = status.clone();
+                let on_unlocked = Rc::clone(&on_unlocked);
+                glib::MainContext::default().spawn_local(async move {
+                    match rx.await {
+                        Ok(Ok(database)) => on_unlocked(database),
+                        result => {
+                            if let Ok(Err(e)) = result {
+                                eprintln!("Failed to unlock database: {}", e);
+                            }
+                            status.set_description(Some("Wrong passphrase, try again"));
+                            passphrase_entry.add_css_class("error");
+                            passphrase_entry.set_text("");
+                            passphrase_entry.set_sensitive(true);
+                            unlock_button.set_sensitive(true);
+                            passphrase_entry.grab_focus();
+                        }
+                    }
+                });
+            }
+        });
+
+        passphrase_entry.connect_activate({
+            let try_unlock = Rc::clone(&try_unlock);
+            move |_| try_unlock()
+        });
+        unlock_button.connect_clicked(move |_| try_unlock());
+    }
+
+    fn show_main_view(
+        root: &adw::Bin,
+        account: &Account,
+        db: &Arc<Database>,
+        api: &Arc<ApiClient>,
+        media: &Arc<MediaCache>,
+        retention: RetentionPolicy,
+        accounts_menu: &gio::Menu,
+    ) {
+        let main_view = Arc::new(MainView::new(
+            Arc::clone(db),
+            Arc::clone(api),
+            Arc::clone(media),
+        ));
+        main_view
+            .profile_button
+            .set_profile_pic(None, Some(&account.name));
+        main_view.profile_button.set_accounts_section(accounts_menu);
+
+        // Load contacts from database first
+        main_view.load_contacts();
+        main_view.load_folders();
+
+        // Expire disappearing messages and enforce retention policies
+        let pruner = Pruner::new(Arc::clone(db), Arc::clone(media), retention, {
+            let main_view = Arc::clone(&main_view);
+            move |pruned| main_view.remove_messages(&pruned)
+        });
+        pruner.start();
+
+        // A chat's new policy applies right away
+        main_view.setup_retention_handler({
+            let db = Arc::clone(db);
+            let pruner = Rc::clone(&pruner);
+            move |jid, policy| {
+                let pending = db.set_chat_retention(&jid, policy);
+                let pruner = Rc::clone(&pruner);
+                glib::MainContext::default().spawn_local(async move {
+                    match pending.await {
+                        Ok(()) => pruner.start(),
+                        Err(e) => eprintln!("Failed to save retention policy for {}: {}", jid, e),
+                    }
+                });
+            }
+        });
+
+        // Messages are written to the outbox and shown right away, the
+        // sender delivers them in the background
+        let sender = OutboxSender::new(Arc::clone(db), Arc::clone(api), {
+            let main_view = Arc::clone(&main_view);
+            move |message_id, update| match update {
+                OutboxUpdate::Progress(done) => main_view.set_upload_progress(message_id, done),
+                OutboxUpdate::Sent(sent_id) => main_view.set_sent(message_id, sent_id),
+                OutboxUpdate::Failed => {
+                    main_view.set_send_state(message_id, Some(SendState::Failed))
+                }
+            }
+        });
+        sender.kick();
+
+        // Setup WebSocket for receiving messages and contacts
+        let (_ws, rx) = WebSocketClient::new(&account.ws_url());
+        attach_event_handler(
+            rx,
+            account.clone(),
+            root.clone(),
+            Arc::clone(db),
+            Arc::clone(&main_view),
+            pruner,
+            Rc::clone(&sender),
+        );
+
+        // Stars are kept locally first and then synced to the phone
+        main_view.setup_star_handler({
+            let db = Arc::clone(db);
+            let api = Arc::clone(api);
+            let main_view = Arc::clone(&main_view);
+            move |message_id, starred| {
+                let saved = db.set_starred(&message_id, starred);
+                let message = db.get_message(&message_id);
+                let api = Arc::clone(&api);
+                let db = Arc::clone(&db);
+                let main_view = Arc::clone(&main_view);
+                glib::MainContext::default().spawn_local(async move {
+                    if let Err(e) = saved.await {
+                        eprintln!("Failed to save star of {}: {}", message_id, e);
+                    }
+                    let Ok(Some(message)) = message.await else {
+                        return;
+                    };
+                    if let Err(e) = api
+                        .star_message(
+                            &message.jid,
+                            &message.message_id,
+                            message.is_from_me,
+                            starred,
+                        )
+                        .await
+                    {
+                        eprintln!("Failed to star message {}: {}", message_id, e);
+                        log_write(
+                            format!("star of {}", message_id),
+                            db.set_starred(&message_id, !starred),
+                        );
+                        main_view.set_starred(&message_id, !starred);
+                        main_view.show_toast(&e.user_message());
+                    }
+                });
+            }
+        });
+
+        // Reactions show right away and are taken back if WhatsApp refuses
+        // them. Picking our current reaction again removes it.
+        main_view.setup_react_handler({
+            let db = Arc::clone(db);
+            let api = Arc::clone(api);
+            let main_view = Arc::clone(&main_view);
+            move |message_id, emoji| {
+                let message = db.get_message(&message_id);
+                let reactions = db.get_message_reactions(&message_id);
+                let api = Arc::clone(&api);
+                let db = Arc::clone(&db);
+                let main_view = Arc::clone(&main_view);
+                glib::MainContext::default().spawn_local(async move {
+                    let Ok(Some(message)) = message.await else {
+                        return;
+                    };
+                    let previous = reactions
+                        .await
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find(|reaction| reaction.reactor == "me")
+                        .map(|reaction| reaction.emoji)
+                        .unwrap_or_default();
+                    let emoji = if previous == emoji {
+                        String::new()
+                    } else {
+                        emoji
+                    };
+
+                    let reaction = models::Reaction {
+                        message_id: message.message_id.clone(),
+                        jid: message.jid.clone(),
+                        reactor: "me".to_string(),
+                        emoji: emoji.clone(),
+                        timestamp: std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs() as i64,
+                    };
+                    if let Err(e) = db.save_reaction(&reaction).await {
+                        eprintln!("Failed to save reaction: {}", e);
+                    }
+                    main_view.refresh_reactions(&message.jid, &message.message_id);
+
+                    if let Err(e) = api.react(&message, &emoji).await {
+                        eprintln!("Failed to react to {}: {}", message.message_id, e);
+                        // Same timestamp, so it replaces the one just saved
+                        let restored = models::Reaction {
+                            emoji: previous,
+                            ..reaction
+                        };
+                        if let Err(e) = db.save_reaction(&restored).await {
+                            eprintln!("Failed to restore reaction: {}", e);
+                        }
+                        main_view.refresh_reactions(&message.jid, &message.message_id);
+                        main_view.show_toast(&e.user_message());
+                    }
+                });
+            }
+        });
+
+        // Edits show once WhatsApp took them, at the time it gives them so
+        // the echoed edit replaces ours instead of adding a version
+        main_view.setup_edit_handler({
+            let db = Arc::clone(db);
+            let api = Arc::clone(api);
+            let main_view = Arc::clone(&main_view);
+            move |message_id, text| {
+                let message = db.get_message(&message_id);
+                let api = Arc::clone(&api);
+                let db = Arc::clone(&db);
+                let main_view = Arc::clone(&main_view);
+                glib::MainContext::default().spawn_local(async move {
+                    let Ok(Some(message)) = message.await else {
+                        return;
+                    };
+                    let edited_at = match api.edit_message(&message, &text).await {
+                        Ok(edited_at) => edited_at,
+                        Err(e) => {
+                            eprintln!("Failed to edit {}: {}", message.message_id, e);
+                            main_view.show_toast(&e.user_message());
+                            return;
+                        }
+                    };
+
+                    let edit = models::MessageEdit {
+                        message_id: message.message_id,
+                        jid: message.jid,
+                        content: text,
+                        edited_at,
+                        raw_data: None,
+                    };
+                    match db.save_message_edit(&edit).await {
+                        Ok(()) => main_view.refresh_message(&edit.jid, &edit.message_id),
+                        Err(e) => eprintln!("Failed to save edit: {}", e),
+                    }
+                });
+            }
+        });
+
+        // Deleted messages leave the chat, or stay as a note when deleted
+        // for everyone, once WhatsApp took the deletion
+        main_view.setup_delete_handler({
+            let db = Arc::clone(db);
+            let api = Arc::clone(api);
+            let media = Arc::clone(media);
+            let main_view = Arc::clone(&main_view);
+            move |message_id, for_everyone| {
+                let message = db.get_message(&message_id);
+                let api = Arc::clone(&api);
+                let db = Arc::clone(&db);
+                let media = Arc::clone(&media);
+                let main_view = Arc::clone(&main_view);
+                glib::MainContext::default().spawn_local(async move {
+                    let Ok(Some(message)) = message.await else {
+                        return;
+                    };
+                    if let Err(e) = api.delete_message(&message, for_everyone).await {
+                        eprintln!("Failed to delete {}: {}", message.message_id, e);
+                        main_view.show_toast(&e.user_message());
+                        return;
+                    }
+
+                    let pending = if for_everyone {
+                        db.revoke_message(&message.message_id)
+                    } else {
+                        db.delete_message(&message.message_id)
+                    };
+                    match pending.await {
+                        Ok(Some(deleted)) => {
+                            media.forget(std::slice::from_ref(&deleted)).await;
+                            if for_everyone {
+                                main_view.refresh_message(&deleted.jid, &deleted.message_id);
+                                main_view.load_contacts();
+                            } else {
+                                main_view.remove_messages(&[deleted]);
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            eprintln!("Failed to delete message {}: {}", message.message_id, e)
+                        }
+                    }
+                });
+            }
+        });
+
+        // Read chats show as read right away, and go back to their count
+        // if the receipts can't be sent
+        main_view.setup_read_handler({
+            let db = Arc::clone(db);
+            let api = Arc::clone(api);
+            let main_view = Arc::clone(&main_view);
+            move |jid, unread| {
+                let messages = db.get_unread_messages(&jid);
+                let api = Arc::clone(&api);
+                let db = Arc::clone(&db);
+                let main_view = Arc::clone(&main_view);
+                glib::MainContext::default().spawn_local(async move {
+                    let messages = messages.await.unwrap_or_else(|e| {
+                        eprintln!("Failed to load unread messages of {}: {}", jid, e);
+                        Vec::new()
+                    });
+                    let read = models::ChatUpdate {
+                        jid: jid.clone(),
+                        unread_count: Some(0),
+                        ..Default::default()
+                    };
+                    log_write(format!("chat {}", jid), db.update_chat(&read));
+                    main_view.load_contacts();
+                    if messages.is_empty() {
+                        return;
+                    }
+
+                    if let Err(e) = api.mark_read(&messages).await {
+                        eprintln!("Failed to mark {} as read: {}", jid, e);
+                        let unread = models::ChatUpdate {
+                            unread_count: Some(unread),
+                            ..read
+                        };
+                        log_write(format!("chat {}", jid), db.update_chat(&unread));
+                        main_view.load_contacts();
+                    }
+                });
+            }
+        });
+
+        main_view.setup_unread_handler({
+            let db = Arc::clone(db);
+            let api = Arc::clone(api);
+            let main_view = Arc::clone(&main_view);
+            move |jid| {
+                let last = db.get_last_message(&jid);
+                let api = Arc::clone(&api);
+                let db = Arc::clone(&db);
+                let main_view = Arc::clone(&main_view);
+                glib::MainContext::default().spawn_local(async move {
+                    let Some(last) = last.await.ok().flatten() else {
+                        main_view.show_toast("There is nothing to mark as unread");
+                        return;
+                    };
+                    if let Err(e) = api.mark_unread(&last).await {
+                        eprintln!("Failed to mark {} as unread: {}", jid, e);
+                        main_view.show_toast(&e.user_message());
+                        return;
+                    }
+
+                    let unread = models::ChatUpdate {
+                        jid: jid.clone(),
+                        unread_count: Some(-1),
+                        ..Default::default()
+                    };
+                    log_write(format!("chat {}", jid), db.update_chat(&unread));
+                    main_view.load_contacts();
+                });
+            }
+        });
+
+        // Our typing and whether we're at the window go out as presence,
+        // unless that's turned off in the preferences
+        let share_presence =
+            gio::Application::default().and_then(|app| app.lookup_action("share-presence"));
+        let sharing = |action: &gio::Action| action.state().and_then(|s| s.get::<bool>());
+        let presence = PresenceSender::new(
+            Arc::clone(api),
+            share_presence.as_ref().and_then(sharing).unwrap_or(true),
+        );
+        if let Some(action) = &share_presence {
+            let presence = Rc::downgrade(&presence);
+            action.connect_state_notify(move |action| {
+                if let (Some(presence), Some(share)) = (presence.upgrade(), sharing(action)) {
+                    presence.set_enabled(share);
+                }
+            });
+        }
+        main_view.setup_typing_handler({
+            let presence = Rc::clone(&presence);
+            move |jid, has_text| presence.typing(&jid, has_text)
+        });
+        main_view.setup_focus_handler(move |focused| presence.set_focused(focused));
+
+        main_view.setup_send_handler({
+            let db = Arc::clone(db);
+            let main_view = Arc::clone(&main_view);
+            let sender = Rc::clone(&sender);
+            move |jid, text, quoted_id| {
+                let mut message = outgoing_message(jid, "text", text);
+                message.quoted_message_id = quoted_id;
+                queue_outgoing(&db, &main_view, &sender, message, None);
+            }
+        });
+
+        main_view.setup_attach_handler({
+            let db = Arc::clone(db);
+            let main_view = Arc::clone(&main_view);
+            let sender = Rc::clone(&sender);
+            move |jid, attachment, caption| {
+                let mut message =
+                    outgoing_message(jid, attachment.kind(), attachment.preview(&caption));
+                message.caption = (!caption.is_empty()).then_some(caption);
+                // Shown from disk until WhatsApp echoes it back
+                message.media_url = Some(attachment.path.clone());
+                queue_outgoing(&db, &main_view, &sender, message, Some(attachment));
+            }
+        });
+
+        main_view.setup_retry_handler({
+            let db = Arc::clone(db);
+            let main_view = Arc::clone(&main_view);
+            move |message_id| {
+                let retried = db.retry_outbox(&message_id);
+                let main_view = Arc::clone(&main_view);
+                let sender = Rc::clone(&sender);
+                glib::MainContext::default().spawn_local(async move {
+                    match retried.await {
+                        Ok(()) => {
+                            main_view.set_send_state(&message_id, Some(SendState::Pending));
+                            sender.kick();
+                        }
+                        Err(e) => eprintln!("Failed to retry {}: {}", message_id, e),
+                    }
+                });
+            }
+        });
+
+        root.set_child(Some(&main_view.widget));
+    }
+
+    fn show_qr_view(
+        root: &adw::Bin,
+        account: &Account,
+        db: &Arc<Database>,
+        api: &Arc<ApiClient>,
+        media: &Arc<MediaCache>,
+        retention: RetentionPolicy,
+        accounts_menu: &gio::Menu,
+    ) {
+        let qr_view = Arc::new(QrView::new());
+
+        // The QR page gets its own header so the account switcher stays reachable
+        let header = adw::HeaderBar::new();
+        header.add_css_class("flat");
+        let profile_button = ProfileButton::new();
+        profile_button.set_profile_pic(None, Some(&account.name));
+        profile_button.set_accounts_section(accounts_menu);
+        header.pack_start(profile_button.widget());
+
+        let page = gtk4::Box::new(gtk4::Orientation::Vertical, 0);
+        page.append(&header);
+        qr_view.widget.set_vexpand(true);
+        page.append(&qr_view.widget);
+        root.set_child(Some(&page));
+
+        // Request QR code from backend. A freshly added account's backend may
+        // still be starting, so retry for a little while.
+        let api_clone_qr = Arc::clone(api);
+        let (qr_tx, qr_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            println!("Requesting QR code from backend...");
+            for attempt in 1..=5 {
+                match api_clone_qr.request_qr().wait() {
+                    Ok(qr) => {
+                        if let Some(qr) = qr {
+                            let _ = qr_tx.send(qr);
+                        }
+                        break;
+                    }
+                    // e.g. already linked, asking again won't help
+                    Err(ApiError::Rejected(reason)) => {
+                        eprintln!("QR code request declined: {}", reason);
+                        break;
+                    }
+                    Err(ApiError::Cancelled) => break,
+                    Err(e) => {
+                        eprintln!("Failed to request QR code (attempt {}): {}", attempt, e);
+                        std::thread::sleep(std::time::Duration::from_secs(2));
+                    }
+                }
+            }
+        });
+
+        // Setup WebSocket for QR code
+        let (_ws, rx) = WebSocketClient::new(&account.ws_url());
+        let root = root.clone();
+        let account = account.clone();
+        let db = Arc::clone(db);
+        let api = Arc::clone(api);
+        let media = Arc::clone(media);
+        let accounts_menu = accounts_menu.clone();
+
+        glib::timeout_add_local(std::time::Duration::from_millis(100), move || {
+            // A QR code the backend already had when we asked for one
+            while let Ok(qr) = qr_rx.try_recv() {
+                qr_view.show_qr(&qr);
+            }
+            while let Ok(event) = rx.try_recv() {
+                match event {
+                    WhatsAppEvent::QrCode(qr) => {
+                        println!("QR Code received from backend");
+                        qr_view.show_qr(&qr);
+                    }
+                    WhatsAppEvent::Connected => {
+                        println!("Connected to WhatsApp!");
+                        qr_view.show_connecting();
+
+                        // Mark as authenticated
+                        log_write(
+                            "authentication state".to_string(),
+                            db.set_authenticated(true),
+                        );
+
+                        // Transition to main view - events will populate contacts via WebSocket
+                        Self::show_main_view(
+                            &root,
+                            &account,
+                            &db,
+                            &api,
+                            &media,
+                            retention,
+                            &accounts_menu,
+                        );
+
+                        // The main view has its own event stream from here on
+                        return glib::Continue(false);
+                    }
+                    _ => {}
+                }
+            }
+            glib::Continue(true)
+        });
+    }
+}
+
+/// Determine who sent a message: "me" for our own, otherwise the sender's number
+fn sender_of(msg: &WAMessage) -> String {
+    if msg.key.from_me {
+        "me".to_string()
+    } else {
+        msg.key
+            .participant
+            .as_ref()
+            .unwrap_or(&msg.key.jid)
+            .split('@')
+            .next()
+            .unwrap_or("Unknown")
+            .to_string()
+    }
+}
+
+/// Extract the reaction carried by a `reactionMessage`, if this is one. An
+/// empty `text` means the reaction was removed.
+fn reaction_from_wa(msg: &WAMessage) -> Option<models::Reaction> {
+    let reaction = msg.message.as_ref()?.get("reactionMessage")?;
+    let message_id = reaction.get("key")?.get("id")?.as_str()?;
+
+    Some(models::Reaction {
+        message_id: message_id.to_string(),
+        jid: msg.key.jid.clone(),
+        reactor: sender_of(msg),
+        emoji: reaction
+            .get("text")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string(),
+        timestamp: msg.timestamp,
+    })
+}
+
+/// Extract the new disappearing-messages setting if this message announces
+/// a change of it
+fn ephemeral_setting_from_wa(msg: &WAMessage) -> Option<models::ChatUpdate> {
+    let protocol = msg.message.as_ref()?.get("protocolMessage")?;
+    let kind = protocol.get("type")?;
+    if kind != "EPHEMERAL_SETTING" && kind != 3 {
+        return None;
+    }
+
+    Some(models::ChatUpdate {
+        jid: msg.key.jid.clone(),
+        ephemeral_expiration: Some(
+            protocol
+                .get("ephemeralExpiration")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(0),
+        ),
+        ephemeral_setting_timestamp: Some(msg.timestamp),
+        ..Default::default()
+    })
+}
+
+/// Extract the new version of an earlier message if this message edits one.
+/// Edits are `protocolMessage`s of type `MESSAGE_EDIT`, usually wrapped in an
+/// `editedMessage`.
+fn edit_from_wa(msg: &WAMessage) -> Option<models::MessageEdit> {
+    let mut body = msg.message.as_ref()?;
+    for wrapper in ["ephemeralMessage", "editedMessage"] {
+        if let Some(inner) = body.get(wrapper).and_then(|w| w.get("message")) {
+            body = inner;
+        }
+    }
+    let protocol = body.get("protocolMessage")?;
+    let kind = protocol.get("type")?;
+    if kind != "MESSAGE_EDIT" && kind != 14 {
+        return None;
+    }
+    let message_id = protocol.get("key")?.get("id")?.as_str()?;
+    let edited = protocol.get("editedMessage")?;
+
+    // The new content is a message body of its own, formatted the same way
+    let content = message_from_wa(&WAMessage {
+        message: Some(edited.clone()),
+        ..msg.clone()
+    })
+    .content;
+    let edited_at = protocol
+        .get("timestampMs")
+        .and_then(|t| t.as_i64().or_else(|| t.as_str()?.parse().ok()))
+        .map(|ms| ms / 1000)
+        .unwrap_or(msg.timestamp);
+
+    Some(models::MessageEdit {
+        message_id: message_id.to_string(),
+        jid: msg.key.jid.clone(),
+        content,
+        edited_at,
+        raw_data: Some(body.to_string()),
+    })
+}
+
+/// Id of the message deleted for everyone if this message deletes one.
+/// Deletions are `protocolMessage`s of type `REVOKE`, which as the first of
+/// the enum may be left out.
+fn revoke_from_wa(msg: &WAMessage) -> Option<String> {
+    let mut body = msg.message.as_ref()?;
+    if let Some(inner) = body.get("ephemeralMessage").and_then(|e| e.get("message")) {
+        body = inner;
+    }
+    let protocol = body.get("protocolMessage")?;
+    let kind = protocol.get("type");
+    if kind.is_some_and(|kind| kind != "REVOKE" && kind != 0) {
+        return None;
+    }
+    let message_id = protocol.get("key")?.get("id")?.as_str()?;
+
+    Some(message_id.to_string())
+}
+
+/// Convert a Baileys message into the stored message model
+fn message_from_wa(msg: &WAMessage) -> models::Message {
+    // Messages in chats with disappearing messages on may come wrapped
+    let message_body = msg.message.as_ref().map(|body| {
+        body.get("ephemeralMessage")
+            .and_then(|e| e.get("message"))
+            .unwrap_or(body)
+    });
+
+    // Extract message content and type
+    let (content, message_type, quoted_id, media_url, caption) =
+        if let Some(msg_data) = message_body {
+            let mut content = String::new();
+            let mut msg_type = "unknown".to_string();
+            let mut quoted_id = None;
+            let mut media_url = None;
+            let mut caption = None;
+
+            // Handle text messages
+            if let Some(text) = msg_data.get("conversation").and_then(|v| v.as_str()) {
+                content = text.to_string();
+                msg_type = "text".to_string();
+            }
+            // Handle extended text (with formatting, links, etc.)
+            else if let Some(ext_text) = msg_data.get("extendedTextMessage") {
+                if let Some(text) = ext_text.get("text").and_then(|v| v.as_str()) {
+                    content = text.to_string();
+                    msg_type = "text".to_string();
+                }
+            }
+            // Handle image messages
+            else if let Some(image) = msg_data.get("imageMessage") {
+                msg_type = "image".to_string();
+                content = "[Image]".to_string();
+                if let Some(url) = image.get("url").and_then(|v| v.as_str()) {
+                    media_url = Some(url.to_string());
+                }
+                if let Some(cap) = image.get("caption").and_then(|v| v.as_str()) {
+                    caption = Some(cap.to_string());
+                    content = format!("[Image] {}", cap);
+                }
+            }
+            // Handle video messages
+            else if let Some(video) = msg_data.get("videoMessage") {
+                msg_type = "video".to_string();
+                content = "[Video]".to_string();
+                if let Some(url) = video.get("url").and_then(|v| v.as_str()) {
+                    media_url = Some(url.to_string());
+                }
+                if let Some(cap) = video.get("caption").and_then(|v| v.as_str()) {
+                    caption = Some(cap.to_string());
+                    content = format!("[Video] {}", cap);
+                }
+            }
+            // Handle audio messages
+            else if msg_data.get("audioMessage").is_some() {
+                msg_type = "audio".to_string();
+                content = "[Audio]".to_string();
+            }
+            // Handle document messages
+            else if let Some(doc) = msg_data.get("documentMessage") {
+                msg_type = "document".to_string();
+                if let Some(filename) = doc.get("fileName").and_then(|v| v.as_str()) {
+                    content = format!("[Document: {}]", filename);
+                } else {
+                    content = "[Document]".to_string();
+                }
+            }
+            // Handle stickers
+            else if msg_data.get("stickerMessage").is_some() {
+                msg_type = "sticker".to_string();
+                content = "[Sticker]".to_string();
+            }
+
+            // Replies of any kind name the message they quote
+            if let Some(context) = models::Message::context_info(msg_data) {
+                if let Some(stanza_id) = context.get("stanzaId").and_then(|v| v.as_str()) {
+                    quoted_id = Some(stanza_id.to_string());
+                }
+            }
+
+            (content, msg_type, quoted_id, media_url, caption)
+        } else {
+            (
+                "[Empty message]".to_string(),
+                "unknown".to_string(),
+                None,
+                None,
+                None,
+            )
+        };
+
+    let sender = sender_of(msg);
+
+    // Disappearing messages say how long they live in their context info
+    let expiration = message_body
+        .and_then(|b| b.as_object())
+        .and_then(|fields| {
+            fields
+                .values()
+                .find_map(|f| f.get("contextInfo")?.get("expiration")?.as_i64())
+        })
+        .filter(|&seconds| seconds > 0);
+
+    // Convert to Message model
+    models::Message {
+        id: None,
+        message_id: msg.key.id.clone(),
+        jid: msg.key.jid.clone(),
+        sender,
+        content,
+        timestamp: msg.timestamp,
+        is_from_me: msg.key.from_me,
+        message_type,
+        raw_data: Some(serde_json::to_string(&message_body).unwrap_or_default()),
+        quoted_message_id: quoted_id,
+        media_url,
+        caption,
+        expires_at: expiration.map(|seconds| msg.timestamp + seconds),
+        starred: msg.starred.unwrap_or(false),
+        edited_at: None,
+        send_state: None,
+    }
+}
+
+/// What a Baileys contact event says about the contact
+fn contact_from_wa(wa_contact: WAContact) -> models::ContactUpdate {
+    models::ContactUpdate {
+        jid: wa_contact.id,
+        name: wa_contact.name,
+        notify: wa_contact.notify,
+        profile_picture_url: None,
+    }
+}
+
+/// What a Baileys chat event says about the chat. Cleared fields come back
+/// as their zero value.
+fn chat_from_wa(wa_chat: WAChat) -> models::ChatUpdate {
+    models::ChatUpdate {
+        jid: wa_chat.id,
+        name: wa_chat.name,
+        unread_count: wa_chat.unread_count,
+        unread_increment: None,
+        conversation_timestamp: wa_chat.conversation_timestamp.map(|t| t as i64),
+        archived: wa_chat.archived.map(|a| a.unwrap_or(false)),
+        pinned: wa_chat.pinned.map(|p| p.unwrap_or(0)),
+        mute_end_time: wa_chat.mute_end_time.map(|m| m.unwrap_or(0)),
+        ephemeral_expiration: wa_chat.ephemeral_expiration.map(|e| e.unwrap_or(0)),
+        ephemeral_setting_timestamp: wa_chat.ephemeral_setting_timestamp,
+    }
+}
+
+/// What a `chats.update` says about the chat. A positive unread count there
+/// is how many messages just arrived, while 0 means the chat was read.
+fn chat_update_from_wa(wa_chat: WAChat) -> models::ChatUpdate {
+    let mut chat = chat_from_wa(wa_chat);
+    if let Some(count) = chat.unread_count.filter(|&count| count > 0) {
+        chat.unread_count = None;
+        chat.unread_increment = Some(count);
+    }
+    chat
+}
+
+/// A message of ours as it's shown before it went out
+fn outgoing_message(jid: String, message_type: &str, content: String) -> models::Message {
+    models::Message {
+        id: None,
+        message_id: local_message_id(),
+        jid,
+        sender: "me".to_string(),
+        content,
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64,
+        is_from_me: true,
+        message_type: message_type.to_string(),
+        raw_data: None,
+        quoted_message_id: None,
+        media_url: None,
+        caption: None,
+        expires_at: None,
+        starred: false,
+        edited_at: None,
+        send_state: Some(SendState::Pending),
+    }
+}
+
+/// Show a message we're sending and put it in the outbox for the sender
+fn queue_outgoing(
+    db: &Arc<Database>,
+    main_view: &Arc<MainView>,
+    sender: &Rc<OutboxSender>,
+    message: models::Message,
+    attachment: Option<Attachment>,
+) {
+    main_view.add_message(&message);
+
+    let queued = db.queue_message(&message, attachment.as_ref());
+    let main_view = Arc::clone(main_view);
+    let sender = Rc::clone(sender);
+    glib::MainContext::default().spawn_local(async move {
+        if let Err(e) = queued.await {
+            eprintln!("Failed to queue message: {}", e);
+            main_view.set_send_state(&message.message_id, Some(SendState::Failed));
+            return;
+        }
+        // Our message is now the chat's latest
+        main_view.load_contacts();
+        sender.kick();
+    });
+}
+
+/// Id for a message we send, until WhatsApp gives it one of its own
+fn local_message_id() -> String {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    format!("local-{}-{}", now, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Report a failed write once the writer thread gets to it, without blocking
+/// the event loop
+fn log_write(what: String, pending: Pending<()>) {
+    glib::MainContext::default().spawn_local(async move {
+        if let Err(e) = pending.await {
+            eprintln!("Failed to save {}: {}", what, e);
+        }
+    });
+}
+
+/// Poll WebSocket events, queue them for the database writer and refresh the
+/// sidebar once per poll if any chat or its last message changed
+fn attach_event_handler(
+    rx: mpsc::Receiver<WhatsAppEvent>,
+    account: Account,
+    root: adw::Bin,
+    db: Arc<Database>,
+    main_view: Arc<MainView>,
+    pruner: Rc<Pruner>,
+    sender: Rc<OutboxSender>,
+) {
+    glib::timeout_add_local(std::time::Duration::from_millis(100), move || {
+        let mut chats_changed = false;
+        let mut folders_changed = false;
+
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                WhatsAppEvent::Message(msg) => {
+                    println!(
+                        "[{}] Received message event for: {}",
+                        account.id, msg.key.jid
+                    );
+
+                    // Reactions attach to their target message instead of
+                    // showing up as messages of their own
+                    if let Some(reaction) = reaction_from_wa(&msg) {
+                        let pending = db.save_reaction(&reaction);
+                        let main_view = Arc::clone(&main_view);
+                        glib::MainContext::default().spawn_local(async move {
+                            match pending.await {
+                                Ok(()) => {
+                                    main_view.refresh_reactions(&reaction.jid, &reaction.message_id)
+                                }
+                                Err(e) => eprintln!("Failed to save reaction: {}", e),
+                            }
+                        });
+                        continue;
+                    }
+
+                    // So do changes of the disappearing-messages setting
+                    if let Some(chat) = ephemeral_setting_from_wa(&msg) {
+                        println!(
+                            "[{}] Disappearing messages in {} set to {:?}s",
+                            account.id, chat.jid, chat.ephemeral_expiration
+                        );
+                        log_write(format!("chat {}", chat.jid), db.update_chat(&chat));
+                        continue;
+                    }
+
+                    // Edits add a version to the message they change
+                    if let Some(edit) = edit_from_wa(&msg) {
+                        let pending = db.save_message_edit(&edit);
+                        let main_view = Arc::clone(&main_view);
+                        glib::MainContext::default().spawn_local(async move {
+                            match pending.await {
+                                Ok(()) => main_view.refresh_message(&edit.jid, &edit.message_id),
+                                Err(e) => eprintln!("Failed to save edit: {}", e),
+                            }
+                        });
+                        chats_changed = true;
+                        continue;
+                    }
+
+                    // Deletions for everyone blank out the message they delete
+                    if let Some(message_id) = revoke_from_wa(&msg) {
+                        println!(
+                            "[{}] Message {} deleted for everyone",
+                            account.id, message_id
+                        );
+                        let pending = db.revoke_message(&message_id);
+                        let main_view = Arc::clone(&main_view);
+                        let jid = msg.key.jid.clone();
+                        glib::MainContext::default().spawn_local(async move {
+                            match pending.await {
+                                Ok(_) => main_view.refresh_message(&jid, &message_id),
+                                Err(e) => eprintln!("Failed to delete message: {}", e),
+                            }
+                        });
+                        chats_changed = true;
+                        continue;
+                    }
+
+                    let message = message_from_wa(&msg);
+                    if msg.is_live && !msg.key.from_me {
+                        notify_incoming(&account, &root, &message);
+                    }
+                    // Our own message coming back under WhatsApp's id takes
+                    // the place of the copy being sent from here
+                    if msg.key.from_me && sender.echoed(&message) {
+                        println!(
+                            "[{}] Echo of sent message {}",
+                            account.id, message.message_id
+                        );
+                    }
+                    log_write(
+                        format!("message {}", message.message_id),
+                        db.save_message(&message),
+                    );
+                    // New messages change the chat's preview and position
+                    chats_changed = true;
+                }
+                WhatsAppEvent::MessageUpdate(update) => {
+                    // Starred or unstarred on another device
+                    if let Some(starred) = update.update.starred {
+                        println!(
+                            "[{}] Message {} starred: {}",
+                            account.id, update.key.id, starred
+                        );
+                        log_write(
+                            format!("star of {}", update.key.id),
+                            db.set_starred(&update.key.id, starred),
+                        );
+                        main_view.set_starred(&update.key.id, starred);
+                    }
+                }
+                WhatsAppEvent::Contact(wa_contact) => {
+                    println!(
+                        "[{}] Received Contact: {} ({})",
+                        account.id,
+                        wa_contact.name.as_ref().unwrap_or(&wa_contact.id),
+                        wa_contact.id
+                    );
+
+                    let contact = contact_from_wa(wa_contact);
+                    log_write(
+                        format!("contact {}", contact.jid),
+                        db.update_contact(&contact),
+                    );
+                }
+                WhatsAppEvent::Chat(wa_chat) => {
+                    println!(
+                        "[{}] Received Chat: {} ({})",
+                        account.id,
+                        wa_chat.name.as_ref().unwrap_or(&wa_chat.id),
+                        wa_chat.id
+                    );
+
+                    let chat = chat_from_wa(wa_chat);
+                    log_write(format!("chat {}", chat.jid), db.update_chat(&chat));
+                    chats_changed = true;
+                }
+                WhatsAppEvent::ChatUpdate(wa_chat) => {
+                    let chat = chat_update_from_wa(wa_chat);
+                    log_write(format!("chat {}", chat.jid), db.update_chat(&chat));
+                    chats_changed = true;
+                }
+                WhatsAppEvent::Label(label) => {
+                    println!(
+                        "[{}] Received label: {} ({})",
+                        account.id, label.name, label.id
+                    );
+                    log_write(
+                        format!("label {}", label.id),
+                        db.save_label(&label.id, &label.name, label.deleted),
+                    );
+                    folders_changed = true;
+                    chats_changed = true;
+                }
+                WhatsAppEvent::LabelAssociation(association) => {
+                    // Labels on single messages have no folder to go in
+                    let target = association.association;
+                    if target.kind != "label_jid" {
+                        continue;
+                    }
+                    log_write(
+                        format!("label {} of {}", target.label_id, target.chat_id),
+                        db.set_chat_label(
+                            &target.chat_id,
+                            &target.label_id,
+                            association.action == "add",
+                        ),
+                    );
+                    folders_changed = true;
+                    chats_changed = true;
+                }
+                _ => {}
+            }
+        }
+
+        if folders_changed {
+            main_view.load_folders();
+        }
+        if chats_changed {
+            let db = Arc::clone(&db);
+            let main_view = Arc::clone(&main_view);
+            let pruner = Rc::clone(&pruner);
+            glib::MainContext::default().spawn_local(async move {
+                // Wait for this poll's writes to land before re-reading
+                let _ = db.flush().await;
+                if let Ok(contacts) = db.get_contacts().await {
+                    main_view.update_contacts(contacts);
+                }
+                // New disappearing messages may be due before the next run
+                if let Ok(Some(due)) = db.next_expiry().await {
+                    pruner.schedule(due);
+                }
+            });
+        }
+
+        glib::Continue(true)
+    });
+}
+
+/// Show a desktop notification for an incoming message, unless this account's
+/// chats are already on screen in the focused window
+fn notify_incoming(account: &Account, root: &adw::Bin, message: &models::Message) {
+    let on_screen = root.is_mapped()
+        && root
+            .root()
+            .and_then(|r| r.downcast::<gtk4::Window>().ok())
+            .is_some_and(|w| w.is_active());
+    if on_screen {
+        return;
+    }
+
+    let Some(app) = gio::Application::default() else {
+        return;
+    };
+
+    let notification = gio::Notification::new(&format!("{} · {}", message.sender, account.name));
+    notification.set_body(Some(&message.content));
+    // Clicking the notification brings up the account it came from
+    notification
+        .set_default_action_and_target_value("app.switch-account", Some(&account.id.to_variant()));
+    app.send_notification(
+        Some(&format!("{}:{}", account.id, message.jid)),
+        &notification,
+    );
+}
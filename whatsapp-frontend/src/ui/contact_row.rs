@@ -6,6 +6,10 @@ use libadwaita as adw;
 pub struct ContactRow {
     pub widget: GtkBox,
     pub jid: String,
+    last_message: Option<String>,
+    draft_label: Label,
+    type_icon: gtk4::Image,
+    preview_label: Label,
 }
 
 impl ContactRow {
@@ -40,17 +44,33 @@ impl ContactRow {
 
         middle_box.append(&name_label);
 
-        // Last message preview
-        if let Some(msg) = &contact.last_message {
-            let msg_label = Label::builder()
-                .label(msg)
-                .halign(gtk4::Align::Start)
-                .ellipsize(gtk4::pango::EllipsizeMode::End)
-                .max_width_chars(40)
-                .css_classes(vec!["dim-label", "caption"])
-                .build();
-            middle_box.append(&msg_label);
+        // Last message preview, replaced by the draft when there is one
+        let preview_box = GtkBox::new(Orientation::Horizontal, 4);
+        let draft_label = Label::builder()
+            .label("Draft:")
+            .css_classes(vec!["accent", "caption"])
+            .build();
+        let type_icon = gtk4::Image::builder()
+            .pixel_size(12)
+            .css_classes(vec!["dim-label"])
+            .build();
+        if let Some(icon) = contact
+            .last_message_type
+            .as_deref()
+            .and_then(type_icon_name)
+        {
+            type_icon.set_icon_name(Some(icon));
         }
+        let preview_label = Label::builder()
+            .halign(gtk4::Align::Start)
+            .ellipsize(gtk4::pango::EllipsizeMode::End)
+            .max_width_chars(40)
+            .css_classes(vec!["dim-label", "caption"])
+            .build();
+        preview_box.append(&draft_label);
+        preview_box.append(&type_icon);
+        preview_box.append(&preview_label);
+        middle_box.append(&preview_box);
 
         row.append(&middle_box);
 
@@ -69,10 +89,14 @@ impl ContactRow {
             right_box.append(&time_label);
         }
 
-        // Unread count badge
-        if contact.unread_count > 0 {
+        // Unread count badge, empty for a chat marked as unread
+        if contact.unread_count != 0 {
+            let count = match contact.unread_count {
+                n if n > 0 => n.to_string(),
+                _ => String::new(),
+            };
             let unread_label = Label::builder()
-                .label(&contact.unread_count.to_string())
+                .label(&count)
                 .halign(gtk4::Align::End)
                 .css_classes(vec!["badge", "accent"])
                 .width_request(24)
@@ -92,10 +116,28 @@ impl ContactRow {
 
         row.append(&right_box);
 
-        Self {
+        let contact_row = Self {
             widget: row,
             jid: contact.jid.clone(),
-        }
+            last_message: message_preview(contact),
+            draft_label,
+            type_icon,
+            preview_label,
+        };
+        contact_row.set_draft(contact.draft.as_deref());
+        contact_row
+    }
+
+    /// Show "Draft: …" instead of the last message while there is unsent text
+    pub fn set_draft(&self, draft: Option<&str>) {
+        let draft = draft.filter(|d| !d.is_empty());
+        self.draft_label.set_visible(draft.is_some());
+        self.type_icon
+            .set_visible(draft.is_none() && self.type_icon.icon_name().is_some());
+
+        let preview = draft.or(self.last_message.as_deref());
+        self.preview_label.set_label(preview.unwrap_or(""));
+        self.preview_label.set_visible(preview.is_some());
     }
 
     fn create_avatar(contact: &Contact) -> adw::Avatar {
@@ -143,10 +185,31 @@ impl ContactRow {
     }
 }
 
-fn format_timestamp(timestamp_ms: i64) -> String {
+/// Last message line, prefixed with who sent it where that isn't obvious
+fn message_preview(contact: &Contact) -> Option<String> {
+    let text = contact.last_message.as_deref()?;
+    Some(match contact.last_message_sender.as_deref() {
+        Some("me") => format!("You: {}", text),
+        Some(sender) if contact.is_group => format!("{}: {}", sender, text),
+        _ => text.to_string(),
+    })
+}
+
+fn type_icon_name(message_type: &str) -> Option<&'static str> {
+    match message_type {
+        "image" => Some("image-x-generic-symbolic"),
+        "video" => Some("video-x-generic-symbolic"),
+        "audio" => Some("audio-x-generic-symbolic"),
+        "document" => Some("x-office-document-symbolic"),
+        "sticker" => Some("face-smile-symbolic"),
+        _ => None,
+    }
+}
+
+/// Relative time of a chat's last activity. WhatsApp timestamps are in seconds.
+fn format_timestamp(timestamp_secs: i64) -> String {
     use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-    let timestamp_secs = timestamp_ms / 1000;
     let msg_time = UNIX_EPOCH + Duration::from_secs(timestamp_secs as u64);
     let now = SystemTime::now();
 
@@ -2,11 +2,19 @@ use gtk4::glib;
 use gtk4::prelude::*;
 use gtk4::{Box as GtkBox, Button, Entry, ListBox, Orientation, ScrolledWindow};
 use libadwaita as adw;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use super::{ContactRow, MessageRow};
-use crate::models::{Contact, Database};
-use crate::services::ApiClient;
+use super::{
+    ContactRow, MessageRow, ProfileButton, StarredView, confirm_message_delete, pick_attachment,
+    prompt_folder_name, prompt_message_edit,
+};
+use crate::models::{
+    Attachment, ChatFilter, Contact, Database, Folder, Message, RetentionPolicy, SendState,
+};
+use crate::services::{ApiClient, MediaCache};
 
 // Ensure CSS is loaded for message bubbles
 fn ensure_css_loaded() {
@@ -27,6 +35,32 @@ fn ensure_css_loaded() {
             .message-received {
                 border-radius: 16px;
                 border-top-left-radius: 4px;
+            }
+            .message-highlight {
+                box-shadow: 0 0 0 2px @accent_color;
+            }
+            .edited-button {
+                min-height: 0;
+                padding: 0 4px;
+            }
+            .message-quote {
+                padding: 4px 8px;
+                border-left: 3px solid currentColor;
+                border-radius: 4px;
+                background: alpha(currentColor, 0.08);
+            }
+            .message-revoked {
+                font-style: italic;
+                opacity: 0.7;
+            }
+            .reply-bar {
+                border-left: 3px solid @accent_color;
+                padding-left: 8px;
+            }
+            .filter-tab {
+                min-height: 0;
+                padding: 2px 10px;
+                border-radius: 999px;
             }",
         );
 
@@ -40,20 +74,46 @@ fn ensure_css_loaded() {
 
 pub struct MainView {
     pub widget: adw::OverlaySplitView,
-    contacts_list: ListBox,
+    pub profile_button: ProfileButton,
+    chat_list: ChatList,
     messages_box: GtkBox,
     messages_scrolled: ScrolledWindow,
     message_entry: Entry,
     send_button: Button,
+    attach_button: Button,
+    reply_bar: ReplyBar,
     chat_title: adw::WindowTitle,
+    toasts: adw::ToastOverlay,
     current_contact: Arc<Mutex<Option<String>>>,
+    // Set while the entry is filled in programmatically
+    restoring_draft: Rc<Cell<bool>>,
+    // Rows of the open chat by message id, for in-place updates
+    message_rows: Arc<Mutex<HashMap<String, MessageRow>>>,
+    // Message to scroll to once the chat being opened has loaded
+    scroll_target: Arc<Mutex<Option<String>>>,
+    // Open chat's retention choice, one of RETENTION_CHOICES
+    retention_action: gtk4::gio::SimpleAction,
+    // Stars or unstars the message given as target
+    star_action: gtk4::gio::SimpleAction,
+    // Sends the failed message given as target again
+    retry_action: gtk4::gio::SimpleAction,
+    // Reacts to a message, targeted with its id and the emoji
+    react_action: gtk4::gio::SimpleAction,
+    // Edits the message given as target
+    edit_action: gtk4::gio::SimpleAction,
+    // Deletes a message, targeted with its id and whether for everyone
+    delete_action: gtk4::gio::SimpleAction,
+    // Read or unread state of the chat given as target
+    read_action: gtk4::gio::SimpleAction,
+    unread_action: gtk4::gio::SimpleAction,
     db: Arc<Database>,
     #[allow(dead_code)]
     api: Arc<ApiClient>,
+    media: Arc<MediaCache>,
 }
 
 impl MainView {
-    pub fn new(db: Arc<Database>, api: Arc<ApiClient>) -> Self {
+    pub fn new(db: Arc<Database>, api: Arc<ApiClient>, media: Arc<MediaCache>) -> Self {
         ensure_css_loaded();
 
         // Sidebar - Contacts list
@@ -65,6 +125,10 @@ impl MainView {
         let title = adw::WindowTitle::new("Chats", "");
         header.set_title_widget(Some(&title));
 
+        // Account switcher
+        let profile_button = ProfileButton::new();
+        header.pack_start(profile_button.widget());
+
         // Add search button to the header
         let search_button = Button::builder()
             .icon_name("system-search-symbolic")
@@ -73,8 +137,26 @@ impl MainView {
         search_button.add_css_class("flat");
         header.pack_end(&search_button);
 
+        let starred_button = Button::builder()
+            .icon_name("starred-symbolic")
+            .tooltip_text("Starred messages")
+            .build();
+        starred_button.add_css_class("flat");
+        header.pack_end(&starred_button);
+
         sidebar.append(&header);
 
+        // Filter tabs: the built-in filters followed by the folders
+        let filters_box = GtkBox::new(Orientation::Horizontal, 6);
+        filters_box.set_margin_start(12);
+        filters_box.set_margin_end(12);
+        filters_box.set_margin_bottom(6);
+        let filters_scrolled = ScrolledWindow::builder()
+            .vscrollbar_policy(gtk4::PolicyType::Never)
+            .child(&filters_box)
+            .build();
+        sidebar.append(&filters_scrolled);
+
         // Contacts list in scrolled window
         let contacts_list = ListBox::new();
         contacts_list.set_css_classes(&["navigation-sidebar"]);
@@ -95,6 +177,23 @@ impl MainView {
         chat_header.add_css_class("flat");
         let chat_title = adw::WindowTitle::new("Select a chat", "");
         chat_header.set_title_widget(Some(&chat_title));
+
+        // Per-chat options, acting on whichever chat is open
+        let retention_menu = gtk4::gio::Menu::new();
+        for (id, label) in RETENTION_CHOICES {
+            retention_menu.append(Some(label), Some(&format!("chat.retention::{}", id)));
+        }
+        let folder_menu = gtk4::gio::Menu::new();
+        let chat_menu = gtk4::gio::Menu::new();
+        chat_menu.append_submenu(Some("Keep Messages"), &retention_menu);
+        chat_menu.append_submenu(Some("Folders"), &folder_menu);
+        let chat_menu_button = gtk4::MenuButton::builder()
+            .icon_name("view-more-symbolic")
+            .tooltip_text("Chat options")
+            .menu_model(&chat_menu)
+            .build();
+        chat_menu_button.add_css_class("flat");
+        chat_header.pack_end(&chat_menu_button);
         content.append(&chat_header);
 
         // Messages area with Box instead of ListBox
@@ -118,6 +217,9 @@ impl MainView {
         let input_separator = gtk4::Separator::new(Orientation::Horizontal);
         input_container.append(&input_separator);
 
+        let reply_bar = ReplyBar::new();
+        input_container.append(&reply_bar.widget);
+
         let input_box = GtkBox::new(Orientation::Horizontal, 8);
         input_box.set_margin_start(15);
         input_box.set_margin_end(15);
@@ -128,6 +230,7 @@ impl MainView {
         let attach_button = Button::from_icon_name("list-add-symbolic");
         attach_button.add_css_class("flat");
         attach_button.add_css_class("circular");
+        attach_button.set_tooltip_text(Some("Attach a file"));
         input_box.append(&attach_button);
 
         // Emoji button
@@ -156,33 +259,136 @@ impl MainView {
         // Create OverlaySplitView
         let split_view = adw::OverlaySplitView::new();
         split_view.set_sidebar(Some(&sidebar));
-        split_view.set_content(Some(&content));
+        // Failures of actions on the open chat show up as toasts
+        let toasts = adw::ToastOverlay::new();
+        toasts.set_child(Some(&content));
+        split_view.set_content(Some(&toasts));
         split_view.set_show_sidebar(true);
         split_view.set_collapsed(false);
         split_view.set_sidebar_width_fraction(0.3);
         split_view.set_min_sidebar_width(280.0);
         split_view.set_max_sidebar_width(400.0);
 
+        let chat_actions = gtk4::gio::SimpleActionGroup::new();
+        let retention_action = gtk4::gio::SimpleAction::new_stateful(
+            "retention",
+            Some(glib::VariantTy::STRING),
+            &"default".to_variant(),
+        );
+        retention_action.set_enabled(false);
+        chat_actions.add_action(&retention_action);
+        let star_action =
+            gtk4::gio::SimpleAction::new("toggle-star", Some(glib::VariantTy::STRING));
+        chat_actions.add_action(&star_action);
+        let retry_action =
+            gtk4::gio::SimpleAction::new("retry-send", Some(glib::VariantTy::STRING));
+        chat_actions.add_action(&retry_action);
+        let react_action =
+            gtk4::gio::SimpleAction::new("react", Some(&glib::VariantType::new("(ss)").unwrap()));
+        chat_actions.add_action(&react_action);
+        let edit_action = gtk4::gio::SimpleAction::new("edit", Some(glib::VariantTy::STRING));
+        chat_actions.add_action(&edit_action);
+        let delete_action =
+            gtk4::gio::SimpleAction::new("delete", Some(&glib::VariantType::new("(sb)").unwrap()));
+        chat_actions.add_action(&delete_action);
+        let versions_action =
+            gtk4::gio::SimpleAction::new("show-versions", Some(glib::VariantTy::STRING));
+        chat_actions.add_action(&versions_action);
+        let reply_action = gtk4::gio::SimpleAction::new("reply", Some(glib::VariantTy::STRING));
+        chat_actions.add_action(&reply_action);
+        let quoted_action =
+            gtk4::gio::SimpleAction::new("open-quoted", Some(glib::VariantTy::STRING));
+        chat_actions.add_action(&quoted_action);
+        split_view.insert_action_group("chat", Some(&chat_actions));
+
+        let sidebar_actions = gtk4::gio::SimpleActionGroup::new();
+        let filter_action = gtk4::gio::SimpleAction::new_stateful(
+            "filter",
+            Some(glib::VariantTy::STRING),
+            &filter_id(ChatFilter::All).to_variant(),
+        );
+        sidebar_actions.add_action(&filter_action);
+        let new_folder_action = gtk4::gio::SimpleAction::new("new-folder", None);
+        sidebar_actions.add_action(&new_folder_action);
+        let rename_folder_action =
+            gtk4::gio::SimpleAction::new("rename-folder", Some(glib::VariantTy::INT64));
+        sidebar_actions.add_action(&rename_folder_action);
+        let delete_folder_action =
+            gtk4::gio::SimpleAction::new("delete-folder", Some(glib::VariantTy::INT64));
+        sidebar_actions.add_action(&delete_folder_action);
+        let read_action = gtk4::gio::SimpleAction::new("mark-read", Some(glib::VariantTy::STRING));
+        sidebar_actions.add_action(&read_action);
+        let unread_action =
+            gtk4::gio::SimpleAction::new("mark-unread", Some(glib::VariantTy::STRING));
+        sidebar_actions.add_action(&unread_action);
+        split_view.insert_action_group("sidebar", Some(&sidebar_actions));
+
+        let current_contact = Arc::new(Mutex::new(None));
+        let chat_list = ChatList {
+            db: Arc::clone(&db),
+            contacts_list: contacts_list.clone(),
+            contact_rows: Arc::new(Mutex::new(HashMap::new())),
+            current_contact: Arc::clone(&current_contact),
+            contacts: Arc::new(Mutex::new(Vec::new())),
+            filter: Arc::new(Mutex::new(ChatFilter::All)),
+            filter_action: filter_action.clone(),
+            filters_box,
+            folders: Arc::new(Mutex::new(Vec::new())),
+            folder_menu,
+            chat_actions,
+            read_action: read_action.clone(),
+        };
+        chat_list.populate_filters();
+
+        // Set while the entry is filled in programmatically, so switching
+        // chats doesn't overwrite the draft of the chat being opened
+        let restoring_draft = Rc::new(Cell::new(false));
+
         let main_view = Self {
             widget: split_view,
-            contacts_list: contacts_list.clone(),
+            profile_button,
+            chat_list: chat_list.clone(),
             messages_box: messages_box.clone(),
             messages_scrolled: messages_scrolled.clone(),
             message_entry: message_entry.clone(),
             send_button: send_button.clone(),
+            attach_button,
+            reply_bar: reply_bar.clone(),
             chat_title: chat_title.clone(),
-            current_contact: Arc::new(Mutex::new(None)),
+            toasts,
+            current_contact,
+            restoring_draft: Rc::clone(&restoring_draft),
+            message_rows: Arc::new(Mutex::new(HashMap::new())),
+            scroll_target: Arc::new(Mutex::new(None)),
+            retention_action: retention_action.clone(),
+            star_action,
+            retry_action,
+            react_action,
+            edit_action,
+            delete_action,
+            read_action,
+            unread_action,
             db: Arc::clone(&db),
             api,
+            media: Arc::clone(&media),
         };
 
         // Connect signals - clone everything we need before moving
         let current_contact_clone = Arc::clone(&main_view.current_contact);
         let send_button_clone = send_button.clone();
+        let db_draft = Arc::clone(&db);
+        let restoring_draft_clone = Rc::clone(&restoring_draft);
         message_entry.connect_changed(move |entry| {
             let has_text = !entry.text().is_empty();
-            let has_contact = current_contact_clone.lock().unwrap().is_some();
-            send_button_clone.set_sensitive(has_text && has_contact);
+            let current = current_contact_clone.lock().unwrap();
+            send_button_clone.set_sensitive(has_text && current.is_some());
+
+            // Keep the draft on disk as it's typed so it survives a restart
+            if let Some(jid) = current.as_ref() {
+                if !restoring_draft_clone.get() {
+                    db_draft.save_draft(jid, &entry.text());
+                }
+            }
         });
 
         // Connect row activation handler once
@@ -190,9 +396,15 @@ impl MainView {
         let messages_scrolled_clone = messages_scrolled.clone();
         let db_clone = Arc::clone(&db);
         let current_contact_clone2 = Arc::clone(&main_view.current_contact);
+        let message_rows_clone = Arc::clone(&main_view.message_rows);
+        let media_clone = Arc::clone(&media);
         let message_entry_clone = main_view.message_entry.clone();
         let send_button_clone2 = main_view.send_button.clone();
         let chat_title_clone = main_view.chat_title.clone();
+        let chat_list_clone = chat_list.clone();
+        let retention_action_clone = retention_action.clone();
+        let scroll_target_clone = Arc::clone(&main_view.scroll_target);
+        let reply_bar_clone = reply_bar.clone();
 
         contacts_list.connect_row_activated(move |_, row| {
             // Get the JID from the row's widget name
@@ -210,60 +422,362 @@ impl MainView {
                 };
                 chat_title_clone.set_title(&display_name);
 
-                *current_contact_clone2.lock().unwrap() = Some(jid.to_string());
+                let previous = current_contact_clone2
+                    .lock()
+                    .unwrap()
+                    .replace(jid.to_string());
+
+                if previous.as_deref() != Some(jid.as_str()) {
+                    // The chat we leave shows its draft in the sidebar, the
+                    // one we open gets its draft back in the entry
+                    let contact_rows = chat_list_clone.contact_rows.lock().unwrap();
+                    if let Some(row) = previous.and_then(|prev| contact_rows.get(&prev)) {
+                        row.set_draft(Some(message_entry_clone.text().as_str()));
+                    }
+                    if let Some(row) = contact_rows.get(jid.as_str()) {
+                        row.set_draft(None);
+                    }
+
+                    reply_bar_clone.clear();
+                    restoring_draft.set(true);
+                    message_entry_clone.set_text("");
+                    restoring_draft.set(false);
+                    Self::restore_draft(
+                        &db_clone,
+                        &message_entry_clone,
+                        &current_contact_clone2,
+                        &restoring_draft,
+                        &jid,
+                    );
+                }
+
                 Self::load_messages_static(
                     &messages_box_clone,
                     &messages_scrolled_clone,
                     &db_clone,
+                    &current_contact_clone2,
+                    &message_rows_clone,
+                    &scroll_target_clone,
+                    &media_clone,
+                    &jid,
+                );
+                Self::load_retention(
+                    &db_clone,
+                    &retention_action_clone,
+                    &current_contact_clone2,
                     &jid,
                 );
+                chat_list_clone.show_chat_folders(&jid);
+                chat_list_clone.mark_read(&jid);
                 send_button_clone2.set_sensitive(!message_entry_clone.text().is_empty());
             } else {
                 println!("[MainView] Warning: Row has no JID set!");
             }
         });
 
+        // Reaching the bottom of the open chat reads what came in meanwhile
+        let chat_list_clone = chat_list.clone();
+        messages_scrolled
+            .vadjustment()
+            .connect_value_changed(move |adj| {
+                if adj.value() + adj.page_size() < adj.upper() - 1.0 {
+                    return;
+                }
+                let current = chat_list_clone.current_contact.lock().unwrap().clone();
+                if let Some(jid) = current {
+                    chat_list_clone.mark_read(&jid);
+                }
+            });
+
+        // Earlier versions of an edited message, from its "Edited" label
+        let db_versions = Arc::clone(&db);
+        let message_rows_clone = Arc::clone(&main_view.message_rows);
+        versions_action.connect_activate(move |_, parameter| {
+            let Some(message_id) = parameter.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let pending = db_versions.get_message_versions(&message_id);
+            let message_rows = Arc::clone(&message_rows_clone);
+            glib::MainContext::default().spawn_local(async move {
+                match pending.await {
+                    Ok(versions) => {
+                        if let Some(row) = message_rows.lock().unwrap().get(&message_id) {
+                            row.show_versions(&versions);
+                        }
+                    }
+                    Err(e) => eprintln!("[MainView] Error loading versions: {}", e),
+                }
+            });
+        });
+
+        // Replying shows the message above the composer until it's sent
+        let db_reply = Arc::clone(&db);
+        let message_entry_clone = message_entry.clone();
+        reply_action.connect_activate(move |_, parameter| {
+            let Some(message_id) = parameter.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let pending = db_reply.get_message(&message_id);
+            let reply_bar = reply_bar.clone();
+            let message_entry = message_entry_clone.clone();
+            glib::MainContext::default().spawn_local(async move {
+                match pending.await {
+                    Ok(Some(message)) => {
+                        reply_bar.show(&message);
+                        message_entry.grab_focus();
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!("[MainView] Error loading message: {}", e),
+                }
+            });
+        });
+
+        // The quote in a reply jumps to the original if it's loaded
+        let messages_box_clone = messages_box.clone();
+        let messages_scrolled_clone = messages_scrolled.clone();
+        let message_rows_clone = Arc::clone(&main_view.message_rows);
+        let toasts_clone = main_view.toasts.clone();
+        quoted_action.connect_activate(move |_, parameter| {
+            let Some(message_id) = parameter.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let row = message_rows_clone.lock().unwrap().get(&message_id).cloned();
+            match row {
+                Some(row) => {
+                    Self::scroll_to_row(&messages_scrolled_clone, &messages_box_clone, &row);
+                }
+                None => {
+                    toasts_clone.add_toast(adw::Toast::new("The original message isn't available"))
+                }
+            }
+        });
+
+        // Starred messages of all chats, each opening its chat in place
+        let db_starred = Arc::clone(&db);
+        let chat_list_clone = chat_list.clone();
+        let scroll_target_clone = Arc::clone(&main_view.scroll_target);
+        starred_button.connect_clicked(move |button| {
+            let pending = db_starred.get_starred_messages();
+            let button = button.clone();
+            let chat_list = chat_list_clone.clone();
+            let scroll_target = Arc::clone(&scroll_target_clone);
+            glib::MainContext::default().spawn_local(async move {
+                let starred = match pending.await {
+                    Ok(starred) => starred,
+                    Err(e) => {
+                        eprintln!("[MainView] Error loading starred messages: {}", e);
+                        return;
+                    }
+                };
+
+                let window = button.root().and_downcast::<gtk4::Window>();
+                let view = StarredView::new(window.as_ref(), &starred);
+                view.connect_open(move |jid, message_id| {
+                    Self::open_message(&chat_list, &scroll_target, jid, message_id);
+                });
+                view.present();
+            });
+        });
+
+        // Switching tabs refilters the chats already loaded
+        let chat_list_clone = chat_list.clone();
+        filter_action.connect_activate(move |action, parameter| {
+            let Some(id) = parameter.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let Some(filter) = filter_from_id(&id) else {
+                return;
+            };
+            action.set_state(&id.to_variant());
+            *chat_list_clone.filter.lock().unwrap() = filter;
+            chat_list_clone.populate();
+        });
+
+        let chat_list_clone = chat_list.clone();
+        new_folder_action.connect_activate(move |_, _| {
+            let chat_list = chat_list_clone.clone();
+            chat_list_clone.prompt_name("New Folder", "", move |name| {
+                let pending = chat_list.db.create_folder(&name);
+                let chat_list = chat_list.clone();
+                glib::MainContext::default().spawn_local(async move {
+                    match pending.await {
+                        Ok(id) => {
+                            chat_list.load_folders();
+                            chat_list
+                                .filter_action
+                                .activate(Some(&filter_id(ChatFilter::Folder(id)).to_variant()));
+                        }
+                        Err(e) => eprintln!("[MainView] Error creating folder: {}", e),
+                    }
+                });
+            });
+        });
+
+        let chat_list_clone = chat_list.clone();
+        rename_folder_action.connect_activate(move |_, parameter| {
+            let Some(id) = parameter.and_then(|p| p.get::<i64>()) else {
+                return;
+            };
+            let Some(folder) = chat_list_clone.folder(id) else {
+                return;
+            };
+            let chat_list = chat_list_clone.clone();
+            chat_list_clone.prompt_name("Rename Folder", &folder.name, move |name| {
+                let pending = chat_list.db.rename_folder(id, &name);
+                let chat_list = chat_list.clone();
+                glib::MainContext::default().spawn_local(async move {
+                    if let Err(e) = pending.await {
+                        eprintln!("[MainView] Error renaming folder: {}", e);
+                    }
+                    chat_list.load_folders();
+                });
+            });
+        });
+
+        let chat_list_clone = chat_list.clone();
+        delete_folder_action.connect_activate(move |_, parameter| {
+            let Some(id) = parameter.and_then(|p| p.get::<i64>()) else {
+                return;
+            };
+            let pending = chat_list_clone.db.delete_folder(id);
+            let chat_list = chat_list_clone.clone();
+            glib::MainContext::default().spawn_local(async move {
+                if let Err(e) = pending.await {
+                    eprintln!("[MainView] Error deleting folder: {}", e);
+                }
+                chat_list.load_folders();
+                chat_list.reload();
+            });
+        });
+
         main_view
     }
 
-    pub fn load_contacts(&self) {
-        if let Ok(contacts) = self.db.get_contacts() {
-            self.update_contacts(contacts);
+    /// Open a chat and scroll to one of its messages
+    fn open_message(
+        chat_list: &ChatList,
+        scroll_target: &Arc<Mutex<Option<String>>>,
+        jid: &str,
+        message_id: &str,
+    ) {
+        // The chat may be hidden by the current filter
+        let row = chat_list.find_row(jid).or_else(|| {
+            let archived = chat_list
+                .contacts
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|c| c.jid == jid)?
+                .archived;
+            let filter = if archived {
+                ChatFilter::Archived
+            } else {
+                ChatFilter::All
+            };
+            chat_list
+                .filter_action
+                .activate(Some(&filter_id(filter).to_variant()));
+            chat_list.find_row(jid)
+        });
+
+        match row {
+            Some(row) => {
+                *scroll_target.lock().unwrap() = Some(message_id.to_string());
+                row.emit_activate();
+            }
+            None => println!("[MainView] No sidebar row for {}", jid),
         }
     }
 
-    pub fn update_contacts(&self, contacts: Vec<Contact>) {
-        println!(
-            "[MainView] update_contacts called with {} contacts",
-            contacts.len()
-        );
+    /// Show the open chat's retention policy in the chat menu
+    fn load_retention(
+        db: &Database,
+        retention_action: &gtk4::gio::SimpleAction,
+        current_contact: &Arc<Mutex<Option<String>>>,
+        jid: &str,
+    ) {
+        retention_action.set_enabled(false);
+        let pending = db.get_chat_retention(jid);
+        let retention_action = retention_action.clone();
+        let current_contact = Arc::clone(current_contact);
+        let jid = jid.to_string();
 
-        // Clear existing
-        while let Some(child) = self.contacts_list.first_child() {
-            self.contacts_list.remove(&child);
-        }
-        println!("[MainView] Cleared existing contacts from list");
+        glib::MainContext::default().spawn_local(async move {
+            let policy = match pending.await {
+                Ok(policy) => policy,
+                Err(e) => {
+                    eprintln!("[MainView] Error loading retention for {}: {}", jid, e);
+                    return;
+                }
+            };
 
-        for contact in contacts {
-            let contact_row = ContactRow::new(&contact);
+            if current_contact.lock().unwrap().as_deref() == Some(jid.as_str()) {
+                retention_action.set_state(&retention_choice(policy).to_variant());
+                retention_action.set_enabled(true);
+            }
+        });
+    }
 
-            // Use ListBoxRow directly - no button wrapper
-            let row = gtk4::ListBoxRow::new();
-            row.set_child(Some(&contact_row.widget));
-            row.set_activatable(true);
+    /// Put a chat's saved draft back into the entry once it has loaded,
+    /// unless the user already started typing or moved on to another chat
+    fn restore_draft(
+        db: &Database,
+        message_entry: &Entry,
+        current_contact: &Arc<Mutex<Option<String>>>,
+        restoring_draft: &Rc<Cell<bool>>,
+        jid: &str,
+    ) {
+        let pending = db.get_draft(jid);
+        let message_entry = message_entry.clone();
+        let current_contact = Arc::clone(current_contact);
+        let restoring_draft = Rc::clone(restoring_draft);
+        let jid = jid.to_string();
 
-            // Store the JID in the row's name so we can retrieve it later
-            row.set_widget_name(&contact.jid);
+        glib::MainContext::default().spawn_local(async move {
+            let draft = match pending.await {
+                Ok(Some(draft)) => draft,
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("[MainView] Error loading draft for {}: {}", jid, e);
+                    return;
+                }
+            };
 
-            self.contacts_list.append(&row);
-        }
+            if current_contact.lock().unwrap().as_deref() == Some(jid.as_str())
+                && message_entry.text().is_empty()
+            {
+                restoring_draft.set(true);
+                message_entry.set_text(&draft);
+                message_entry.set_position(-1);
+                restoring_draft.set(false);
+            }
+        });
+    }
 
-        println!("[MainView] Finished updating contacts list");
+    /// Reload the sidebar once earlier writes have landed
+    pub fn load_contacts(&self) {
+        self.chat_list.reload();
+    }
+
+    pub fn update_contacts(&self, contacts: Vec<Contact>) {
+        self.chat_list.set_contacts(contacts);
+    }
+
+    /// Reload folders into the filter tabs and the chat menu, after they
+    /// were changed or synced
+    pub fn load_folders(&self) {
+        self.chat_list.load_folders();
     }
+
+    #[allow(clippy::too_many_arguments)]
     fn load_messages_static(
         messages_box: &GtkBox,
         messages_scrolled: &ScrolledWindow,
         db: &Database,
+        current_contact: &Arc<Mutex<Option<String>>>,
+        message_rows: &Arc<Mutex<HashMap<String, MessageRow>>>,
+        scroll_target: &Arc<Mutex<Option<String>>>,
+        media: &Arc<MediaCache>,
         jid: &str,
     ) {
         println!("[MainView] load_messages_static called for JID: {}", jid);
@@ -272,86 +786,1005 @@ impl MainView {
         while let Some(child) = messages_box.first_child() {
             messages_box.remove(&child);
         }
+        message_rows.lock().unwrap().clear();
         println!("[MainView] Cleared existing messages");
 
-        // Load and display messages
-        match db.get_messages(jid) {
-            Ok(messages) => {
-                println!("[MainView] Loaded {} messages for {}", messages.len(), jid);
-                for msg in messages {
-                    let row = MessageRow::new(&msg.content, msg.is_from_me, msg.timestamp);
-                    messages_box.append(&row.widget);
+        let messages_box = messages_box.clone();
+        let messages_scrolled = messages_scrolled.clone();
+        let current_contact = Arc::clone(current_contact);
+        let message_rows = Arc::clone(message_rows);
+        let target = scroll_target.lock().unwrap().take();
+        let media = Arc::clone(media);
+        let pending = db.get_messages(jid);
+        let pending_reactions = db.get_reactions(jid);
+        let jid = jid.to_string();
+
+        glib::MainContext::default().spawn_local(async move {
+            let result = pending.await;
+            let reactions = pending_reactions.await.unwrap_or_else(|e| {
+                eprintln!("[MainView] Error loading reactions for {}: {}", jid, e);
+                HashMap::new()
+            });
+
+            // Another chat may have been opened while the query was running
+            if current_contact.lock().unwrap().as_deref() != Some(jid.as_str()) {
+                return;
+            }
+
+            // Load and display messages
+            match result {
+                Ok(messages) => {
+                    println!("[MainView] Loaded {} messages for {}", messages.len(), jid);
+                    // What replies quote, when the original is loaded too
+                    let quotable: HashMap<String, (String, String)> = messages
+                        .iter()
+                        .map(|m| {
+                            let author = quote_author(m).to_string();
+                            (m.message_id.clone(), (author, m.content.clone()))
+                        })
+                        .collect();
+                    let mut rows = message_rows.lock().unwrap();
+                    for msg in messages {
+                        let row = MessageRow::new(
+                            &msg.message_id,
+                            &msg.content,
+                            msg.is_from_me,
+                            msg.timestamp,
+                        );
+                        row.set_starred(msg.starred);
+                        row.set_send_state(msg.send_state);
+                        row.set_message_type(&msg.message_type);
+                        if msg.edited_at.is_some() {
+                            row.set_content(&msg.content, true);
+                        }
+                        if let Some(reactions) = reactions.get(&msg.message_id) {
+                            row.set_reactions(reactions);
+                        }
+                        if let Some(quoted_id) = &msg.quoted_message_id {
+                            match quotable.get(quoted_id) {
+                                Some((author, text)) => {
+                                    row.set_quote(Some(author), text, quoted_id)
+                                }
+                                None => {
+                                    if let Some((author, text)) = msg.quote() {
+                                        row.set_quote(author.as_deref(), &text, quoted_id);
+                                    }
+                                }
+                            }
+                        }
+                        messages_box.append(&row.widget);
+                        Self::show_media(&media, &row, &msg);
+                        rows.insert(msg.message_id, row);
+                    }
+                }
+                Err(e) => {
+                    println!("[MainView] Error loading messages for {}: {}", jid, e);
+                }
+            }
+
+            // Scroll to the message asked for, or else to the bottom, after
+            // messages are loaded
+            let target = target.and_then(|id| message_rows.lock().unwrap().get(&id).cloned());
+            glib::idle_add_local_once(move || {
+                let shown = target.is_some_and(|row| {
+                    Self::scroll_to_row(&messages_scrolled, &messages_box, &row)
+                });
+                if !shown {
+                    let adj = messages_scrolled.vadjustment();
+                    adj.set_value(adj.upper() - adj.page_size());
                 }
+            });
+        });
+    }
+
+    /// Center a message of the open chat and point it out. Returns false if
+    /// it isn't laid out yet.
+    fn scroll_to_row(
+        messages_scrolled: &ScrolledWindow,
+        messages_box: &GtkBox,
+        row: &MessageRow,
+    ) -> bool {
+        let Some((_, y)) = row.widget.translate_coordinates(messages_box, 0.0, 0.0) else {
+            return false;
+        };
+        let adj = messages_scrolled.vadjustment();
+        let height = row.widget.height() as f64;
+        adj.set_value(y + height / 2.0 - adj.page_size() / 2.0);
+        row.highlight();
+        true
+    }
+
+    /// Show the picture of an image or sticker message, ours straight from
+    /// disk until WhatsApp has it
+    fn show_media(media: &Arc<MediaCache>, row: &MessageRow, message: &Message) {
+        match message.local_media() {
+            Some(path) if message.message_type == "image" => row.set_local_image(path),
+            Some(_) => {}
+            None if message.message_type == "image" || message.message_type == "sticker" => {
+                Self::load_image(media, row, message.clone())
             }
-            Err(e) => {
-                println!("[MainView] Error loading messages for {}: {}", jid, e);
+            None => {}
+        }
+    }
+
+    /// Fill in a row's picture from the media cache, downloading it if needed
+    fn load_image(media: &Arc<MediaCache>, row: &MessageRow, message: Message) {
+        let media = Arc::clone(media);
+        let row = row.clone();
+        glib::MainContext::default().spawn_local(async move {
+            match media.fetch(&message).await {
+                Ok(entry) => row.set_image(&entry),
+                Err(e) => eprintln!(
+                    "[MainView] Failed to load media for {}: {}",
+                    message.message_id, e
+                ),
             }
+        });
+    }
+
+    /// Show a message we just wrote, if its chat is open
+    pub fn add_message(&self, message: &Message) {
+        if self.current_contact.lock().unwrap().as_deref() != Some(message.jid.as_str()) {
+            return;
         }
 
-        // Scroll to bottom after messages are loaded
-        let scrolled_clone = messages_scrolled.clone();
+        let row = MessageRow::new(
+            &message.message_id,
+            &message.content,
+            message.is_from_me,
+            message.timestamp,
+        );
+        row.set_send_state(message.send_state);
+        row.set_message_type(&message.message_type);
+        self.messages_box.append(&row.widget);
+        Self::show_media(&self.media, &row, message);
+        if let Some(quoted_id) = message.quoted_message_id.clone() {
+            let pending = self.db.get_message(&quoted_id);
+            let row = row.clone();
+            glib::MainContext::default().spawn_local(async move {
+                if let Ok(Some(quoted)) = pending.await {
+                    row.set_quote(Some(quote_author(&quoted)), &quoted.content, &quoted_id);
+                }
+            });
+        }
+        self.message_rows
+            .lock()
+            .unwrap()
+            .insert(message.message_id.clone(), row);
+
+        // Scroll to bottom
+        let scrolled = self.messages_scrolled.clone();
         glib::idle_add_local_once(move || {
-            let adj = scrolled_clone.vadjustment();
+            let adj = scrolled.vadjustment();
             adj.set_value(adj.upper() - adj.page_size());
         });
     }
 
-    pub fn add_message(
-        &self,
-        jid: &str,
-        sender: &str,
-        content: &str,
-        timestamp: i64,
-        is_from_me: bool,
-    ) {
-        // Save to DB
-        let message = crate::models::Message {
-            id: None,
-            message_id: format!("{}_{}", timestamp, sender), // Generate ID from timestamp+sender
-            jid: jid.to_string(),
-            sender: sender.to_string(),
-            content: content.to_string(),
-            timestamp,
-            is_from_me,
-            message_type: "text".to_string(),
-            raw_data: None,
-            quoted_message_id: None,
-            media_url: None,
-            caption: None,
-        };
-        let _ = self.db.save_message(&message);
-
-        // Update UI if this is the current chat
-        if let Some(current) = self.current_contact.lock().unwrap().as_ref() {
-            if current == jid {
-                let row = MessageRow::new(content, is_from_me, timestamp);
-                self.messages_box.append(&row.widget);
-
-                // Scroll to bottom
-                let scrolled = self.messages_scrolled.clone();
-                glib::idle_add_local_once(move || {
-                    let adj = scrolled.vadjustment();
-                    adj.set_value(adj.upper() - adj.page_size());
-                });
+    /// Take deleted messages off screen and refresh the previews they were in
+    pub fn remove_messages(&self, messages: &[Message]) {
+        let current = self.current_contact.lock().unwrap().clone();
+        let mut rows = self.message_rows.lock().unwrap();
+        for message in messages {
+            if current.as_deref() != Some(message.jid.as_str()) {
+                continue;
+            }
+            if let Some(row) = rows.remove(&message.message_id) {
+                self.messages_box.remove(&row.widget);
             }
         }
+        drop(rows);
+
+        self.load_contacts();
     }
 
-    pub fn setup_send_handler<F>(&self, callback: F)
+    /// Reload the reaction chips of a message if its chat is open
+    pub fn refresh_reactions(&self, jid: &str, message_id: &str) {
+        if self.current_contact.lock().unwrap().as_deref() != Some(jid) {
+            return;
+        }
+
+        let message_rows = Arc::clone(&self.message_rows);
+        let pending = self.db.get_message_reactions(message_id);
+        let message_id = message_id.to_string();
+        glib::MainContext::default().spawn_local(async move {
+            match pending.await {
+                Ok(reactions) => {
+                    if let Some(row) = message_rows.lock().unwrap().get(&message_id) {
+                        row.set_reactions(&reactions);
+                    }
+                }
+                Err(e) => eprintln!("[MainView] Error loading reactions: {}", e),
+            }
+        });
+    }
+
+    /// Reload a message's content if its chat is open, after it was edited
+    /// or deleted for everyone
+    pub fn refresh_message(&self, jid: &str, message_id: &str) {
+        if self.current_contact.lock().unwrap().as_deref() != Some(jid) {
+            return;
+        }
+
+        let message_rows = Arc::clone(&self.message_rows);
+        let pending = self.db.get_message(message_id);
+        let message_id = message_id.to_string();
+        glib::MainContext::default().spawn_local(async move {
+            match pending.await {
+                Ok(Some(message)) => {
+                    if let Some(row) = message_rows.lock().unwrap().get(&message_id) {
+                        row.set_content(&message.content, message.edited_at.is_some());
+                        row.set_message_type(&message.message_type);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("[MainView] Error loading message: {}", e),
+            }
+        });
+    }
+
+    /// Briefly show `text` over the chat, e.g. why sending failed
+    pub fn show_toast(&self, text: &str) {
+        self.toasts.add_toast(adw::Toast::new(text));
+    }
+
+    /// Show a message's star if its chat is open
+    pub fn set_starred(&self, message_id: &str, starred: bool) {
+        if let Some(row) = self.message_rows.lock().unwrap().get(message_id) {
+            row.set_starred(starred);
+        }
+    }
+
+    /// Update the state icon of our message if its chat is open
+    pub fn set_send_state(&self, message_id: &str, state: Option<SendState>) {
+        if let Some(row) = self.message_rows.lock().unwrap().get(message_id) {
+            row.set_send_state(state);
+        }
+    }
+
+    /// Show how much of our attachment has been uploaded if its chat is open
+    pub fn set_upload_progress(&self, message_id: &str, fraction: f64) {
+        if let Some(row) = self.message_rows.lock().unwrap().get(message_id) {
+            row.set_upload_progress(fraction);
+        }
+    }
+
+    /// Re-key our message's row under the id WhatsApp gave it, once sent
+    pub fn set_sent(&self, message_id: &str, sent_id: &str) {
+        let mut rows = self.message_rows.lock().unwrap();
+        if let Some(row) = rows.remove(message_id) {
+            row.set_message_id(sent_id);
+            row.set_send_state(None);
+            rows.insert(sent_id.to_string(), row);
+        }
+    }
+
+    /// Call `callback` with the id of a failed message when sending it is
+    /// retried
+    pub fn setup_retry_handler<F>(&self, callback: F)
+    where
+        F: Fn(String) + 'static,
+    {
+        self.retry_action.connect_activate(move |_, parameter| {
+            if let Some(message_id) = parameter.and_then(|p| p.get::<String>()) {
+                callback(message_id);
+            }
+        });
+    }
+
+    /// Call `callback` with a message id and an emoji picked to react to it
+    pub fn setup_react_handler<F>(&self, callback: F)
+    where
+        F: Fn(String, String) + 'static,
+    {
+        self.react_action.connect_activate(move |_, parameter| {
+            if let Some((message_id, emoji)) = parameter.and_then(|p| p.get::<(String, String)>()) {
+                callback(message_id, emoji);
+            }
+        });
+    }
+
+    /// Call `callback` with the id of our message and its new text once
+    /// editing it is confirmed
+    pub fn setup_edit_handler<F>(&self, callback: F)
     where
         F: Fn(String, String) + 'static,
+    {
+        let message_rows = Arc::clone(&self.message_rows);
+        let messages_box = self.messages_box.clone();
+        let callback = Rc::new(callback);
+
+        self.edit_action.connect_activate(move |_, parameter| {
+            let Some(message_id) = parameter.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let Some(text) = message_rows
+                .lock()
+                .unwrap()
+                .get(&message_id)
+                .map(|row| row.content())
+            else {
+                return;
+            };
+            let Some(window) = messages_box.root().and_downcast::<gtk4::Window>() else {
+                return;
+            };
+            let callback = Rc::clone(&callback);
+            prompt_message_edit(&window, &text, move |text| {
+                callback(message_id.clone(), text)
+            });
+        });
+    }
+
+    /// Call `callback` with a message id and whether to delete it for
+    /// everyone, once deleting it is confirmed
+    pub fn setup_delete_handler<F>(&self, callback: F)
+    where
+        F: Fn(String, bool) + 'static,
+    {
+        let messages_box = self.messages_box.clone();
+        let callback = Rc::new(callback);
+
+        self.delete_action.connect_activate(move |_, parameter| {
+            let Some((message_id, for_everyone)) =
+                parameter.and_then(|p| p.get::<(String, bool)>())
+            else {
+                return;
+            };
+            let Some(window) = messages_box.root().and_downcast::<gtk4::Window>() else {
+                return;
+            };
+            let callback = Rc::clone(&callback);
+            confirm_message_delete(&window, for_everyone, move || {
+                callback(message_id.clone(), for_everyone)
+            });
+        });
+    }
+
+    /// Call `callback` with a chat to send read receipts for and how many
+    /// messages it had unread, when it is opened, scrolled to the bottom or
+    /// marked as read. The sidebar shows it as read right away.
+    pub fn setup_read_handler<F>(&self, callback: F)
+    where
+        F: Fn(String, i32) + 'static,
+    {
+        let chat_list = self.chat_list.clone();
+
+        self.read_action.connect_activate(move |_, parameter| {
+            let Some(jid) = parameter.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let unread = chat_list.take_unread(&jid);
+            if unread != 0 {
+                callback(jid, unread);
+            }
+        });
+    }
+
+    /// Call `callback` with a chat marked as unread from its menu
+    pub fn setup_unread_handler<F>(&self, callback: F)
+    where
+        F: Fn(String) + 'static,
+    {
+        self.unread_action.connect_activate(move |_, parameter| {
+            if let Some(jid) = parameter.and_then(|p| p.get::<String>()) {
+                callback(jid);
+            }
+        });
+    }
+
+    /// Call `callback` with a message id and whether it should now be starred
+    /// when it is starred or unstarred from its menu. The row shows the new
+    /// state right away.
+    pub fn setup_star_handler<F>(&self, callback: F)
+    where
+        F: Fn(String, bool) + 'static,
+    {
+        let message_rows = Arc::clone(&self.message_rows);
+
+        self.star_action.connect_activate(move |_, parameter| {
+            let Some(message_id) = parameter.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let starred = match message_rows.lock().unwrap().get(&message_id) {
+                Some(row) => {
+                    row.set_starred(!row.is_starred());
+                    row.is_starred()
+                }
+                None => return,
+            };
+
+            callback(message_id, starred);
+        });
+    }
+
+    /// Call `callback` with the open chat and its new policy when a retention
+    /// choice is picked from the chat menu. `None` means the global policy.
+    pub fn setup_retention_handler<F>(&self, callback: F)
+    where
+        F: Fn(String, Option<RetentionPolicy>) + 'static,
+    {
+        let current_contact = Arc::clone(&self.current_contact);
+
+        self.retention_action
+            .connect_activate(move |action, parameter| {
+                let Some(choice) = parameter.and_then(|p| p.get::<String>()) else {
+                    return;
+                };
+                let Some(jid) = current_contact.lock().unwrap().clone() else {
+                    return;
+                };
+
+                action.set_state(&choice.to_variant());
+                callback(jid, retention_policy(&choice));
+            });
+    }
+
+    /// Call `callback` with the open chat and whether any text is left in
+    /// the entry whenever it's typed in or cleared. Switching chats doesn't
+    /// count.
+    pub fn setup_typing_handler<F>(&self, callback: F)
+    where
+        F: Fn(String, bool) + 'static,
+    {
+        let current_contact = Arc::clone(&self.current_contact);
+        let restoring_draft = Rc::clone(&self.restoring_draft);
+
+        self.message_entry.connect_changed(move |entry| {
+            if restoring_draft.get() {
+                return;
+            }
+            let current = current_contact.lock().unwrap().clone();
+            if let Some(jid) = current {
+                callback(jid, !entry.text().is_empty());
+            }
+        });
+    }
+
+    /// Call `callback` with whether this view is on screen in the focused
+    /// window, whenever the window gains or loses focus
+    pub fn setup_focus_handler<F>(&self, callback: F)
+    where
+        F: Fn(bool) + 'static,
+    {
+        let callback = Rc::new(callback);
+        let connected = Cell::new(false);
+
+        // The window is only known once the view is shown in it
+        self.widget.connect_map(move |widget| {
+            if connected.get() {
+                return;
+            }
+            let Some(window) = widget.root().and_downcast::<gtk4::Window>() else {
+                return;
+            };
+            connected.set(true);
+            let widget = widget.downgrade();
+            let callback = Rc::clone(&callback);
+            window.connect_is_active_notify(move |window| {
+                let shown = widget.upgrade().is_some_and(|w| w.is_mapped());
+                callback(window.is_active() && shown);
+            });
+        });
+    }
+
+    /// Call `callback` with the JID of the open chat, the text typed and the
+    /// id of the message it replies to, if any
+    pub fn setup_send_handler<F>(&self, callback: F)
+    where
+        F: Fn(String, String, Option<String>) + 'static,
     {
         let message_entry = self.message_entry.clone();
         let current_contact = Arc::clone(&self.current_contact);
+        let reply_bar = self.reply_bar.clone();
 
         self.send_button.connect_clicked(move |_| {
             let text = message_entry.text().to_string();
             if let Some(jid) = current_contact.lock().unwrap().as_ref() {
                 if !text.is_empty() {
-                    callback(jid.clone(), text.clone());
+                    callback(jid.clone(), text.clone(), reply_bar.take());
                     message_entry.set_text("");
                 }
             }
         });
     }
+
+    /// Call `callback` with the JID of the open chat, a file picked to send
+    /// there and its caption, which may be empty
+    pub fn setup_attach_handler<F>(&self, callback: F)
+    where
+        F: Fn(String, Attachment, String) + 'static,
+    {
+        let current_contact = Arc::clone(&self.current_contact);
+        let callback = Rc::new(callback);
+        self.attach_button.connect_clicked(move |button| {
+            let Some(jid) = current_contact.lock().unwrap().clone() else {
+                return;
+            };
+            let Some(window) = button.root().and_downcast::<gtk4::Window>() else {
+                return;
+            };
+            let callback = Rc::clone(&callback);
+            pick_attachment(&window, move |attachment, caption| {
+                callback(jid.clone(), attachment, caption)
+            });
+        });
+    }
+}
+
+/// Bar above the composer showing the message being replied to
+#[derive(Clone)]
+struct ReplyBar {
+    widget: GtkBox,
+    author: gtk4::Label,
+    text: gtk4::Label,
+    message_id: Arc<Mutex<Option<String>>>,
+}
+
+impl ReplyBar {
+    fn new() -> Self {
+        let author = gtk4::Label::builder()
+            .xalign(0.0)
+            .ellipsize(gtk4::pango::EllipsizeMode::End)
+            .css_classes(vec!["caption-heading", "accent"])
+            .build();
+        let text = gtk4::Label::builder()
+            .xalign(0.0)
+            .ellipsize(gtk4::pango::EllipsizeMode::End)
+            .css_classes(vec!["caption", "dim-label"])
+            .build();
+        let labels = GtkBox::new(Orientation::Vertical, 2);
+        labels.set_hexpand(true);
+        labels.add_css_class("reply-bar");
+        labels.append(&author);
+        labels.append(&text);
+
+        let close_button = Button::builder()
+            .icon_name("window-close-symbolic")
+            .tooltip_text("Cancel reply")
+            .valign(gtk4::Align::Center)
+            .css_classes(vec!["flat", "circular"])
+            .build();
+
+        let widget = GtkBox::new(Orientation::Horizontal, 8);
+        widget.set_margin_start(15);
+        widget.set_margin_end(15);
+        widget.set_margin_top(8);
+        widget.set_visible(false);
+        widget.append(&labels);
+        widget.append(&close_button);
+
+        let bar = Self {
+            widget,
+            author,
+            text,
+            message_id: Arc::new(Mutex::new(None)),
+        };
+        let bar_clone = bar.clone();
+        close_button.connect_clicked(move |_| bar_clone.clear());
+        bar
+    }
+
+    fn show(&self, message: &Message) {
+        self.author
+            .set_label(&format!("Replying to {}", quote_author(message)));
+        self.text.set_label(&message.content);
+        *self.message_id.lock().unwrap() = Some(message.message_id.clone());
+        self.widget.set_visible(true);
+    }
+
+    fn clear(&self) {
+        self.message_id.lock().unwrap().take();
+        self.widget.set_visible(false);
+    }
+
+    /// The message being replied to, which the reply is done with
+    fn take(&self) -> Option<String> {
+        let message_id = self.message_id.lock().unwrap().take();
+        self.widget.set_visible(false);
+        message_id
+    }
+}
+
+/// The sidebar's chats and what filters them, cloned into the handlers that
+/// refill the list
+#[derive(Clone)]
+struct ChatList {
+    db: Arc<Database>,
+    contacts_list: ListBox,
+    // Sidebar rows by JID, for in-place draft previews
+    contact_rows: Arc<Mutex<HashMap<String, ContactRow>>>,
+    current_contact: Arc<Mutex<Option<String>>>,
+    // Every chat, including those the filter hides
+    contacts: Arc<Mutex<Vec<Contact>>>,
+    filter: Arc<Mutex<ChatFilter>>,
+    // Selected filter tab, by filter_id
+    filter_action: gtk4::gio::SimpleAction,
+    filters_box: GtkBox,
+    folders: Arc<Mutex<Vec<Folder>>>,
+    // "Folders" submenu of the chat menu, toggling `chat.folder-<id>`
+    folder_menu: gtk4::gio::Menu,
+    chat_actions: gtk4::gio::SimpleActionGroup,
+    // `sidebar.mark-read`, activated when a chat with unread messages is read
+    read_action: gtk4::gio::SimpleAction,
+}
+
+impl ChatList {
+    /// Reload the chats once earlier writes have landed
+    fn reload(&self) {
+        let chat_list = self.clone();
+        glib::MainContext::default().spawn_local(async move {
+            let _ = chat_list.db.flush().await;
+            match chat_list.db.get_contacts().await {
+                Ok(contacts) => chat_list.set_contacts(contacts),
+                Err(e) => eprintln!("[MainView] Error loading contacts: {}", e),
+            }
+        });
+    }
+
+    fn set_contacts(&self, contacts: Vec<Contact>) {
+        *self.contacts.lock().unwrap() = contacts;
+        self.populate();
+
+        // Folder membership may have changed elsewhere
+        let current = self.current_contact.lock().unwrap().clone();
+        if let Some(jid) = current {
+            self.show_chat_folders(&jid);
+        }
+    }
+
+    /// Fill the list with the chats the filter lets through
+    fn populate(&self) {
+        let contacts = self.contacts.lock().unwrap();
+        let filter = *self.filter.lock().unwrap();
+        println!(
+            "[MainView] update_contacts called with {} contacts, filter {:?}",
+            contacts.len(),
+            filter
+        );
+
+        // Clear existing
+        while let Some(child) = self.contacts_list.first_child() {
+            self.contacts_list.remove(&child);
+        }
+        println!("[MainView] Cleared existing contacts from list");
+
+        let current = self.current_contact.lock().unwrap().clone();
+        let mut rows = self.contact_rows.lock().unwrap();
+        rows.clear();
+
+        for contact in contacts.iter().filter(|c| filter.matches(c)) {
+            let contact_row = ContactRow::new(contact);
+            // The open chat's draft is in the entry, not the sidebar
+            if current.as_deref() == Some(contact.jid.as_str()) {
+                contact_row.set_draft(None);
+            }
+
+            // Use ListBoxRow directly - no button wrapper
+            let row = gtk4::ListBoxRow::new();
+            row.set_child(Some(&contact_row.widget));
+            row.set_activatable(true);
+
+            // Store the JID in the row's name so we can retrieve it later
+            row.set_widget_name(&contact.jid);
+            attach_chat_menu(&row, &contact.jid, contact.unread_count != 0);
+
+            self.contacts_list.append(&row);
+            rows.insert(contact.jid.clone(), contact_row);
+        }
+
+        println!("[MainView] Finished updating contacts list");
+    }
+
+    /// Read a chat if it has unread messages
+    fn mark_read(&self, jid: &str) {
+        let unread = self
+            .contacts
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|c| c.jid == jid && c.unread_count != 0);
+        if unread {
+            self.read_action.activate(Some(&jid.to_variant()));
+        }
+    }
+
+    /// Zero a chat's unread count until the chats are reloaded, returning
+    /// what it was
+    fn take_unread(&self, jid: &str) -> i32 {
+        let mut contacts = self.contacts.lock().unwrap();
+        match contacts.iter_mut().find(|c| c.jid == jid) {
+            Some(contact) => std::mem::take(&mut contact.unread_count),
+            None => 0,
+        }
+    }
+
+    fn find_row(&self, jid: &str) -> Option<gtk4::ListBoxRow> {
+        let mut child = self.contacts_list.first_child();
+        while let Some(widget) = child {
+            if widget.widget_name() == jid {
+                return widget.downcast::<gtk4::ListBoxRow>().ok();
+            }
+            child = widget.next_sibling();
+        }
+        None
+    }
+
+    fn folder(&self, id: i64) -> Option<Folder> {
+        let folders = self.folders.lock().unwrap();
+        folders.iter().find(|f| f.id == id).cloned()
+    }
+
+    fn load_folders(&self) {
+        let chat_list = self.clone();
+        glib::MainContext::default().spawn_local(async move {
+            let _ = chat_list.db.flush().await;
+            let folders = match chat_list.db.get_folders().await {
+                Ok(folders) => folders,
+                Err(e) => {
+                    eprintln!("[MainView] Error loading folders: {}", e);
+                    return;
+                }
+            };
+
+            let old = std::mem::replace(&mut *chat_list.folders.lock().unwrap(), folders);
+            for folder in &old {
+                chat_list
+                    .chat_actions
+                    .remove_action(&format!("folder-{}", folder.id));
+            }
+            chat_list.populate_filters();
+            chat_list.populate_folder_menu();
+
+            // The folder shown may be gone
+            let filter = *chat_list.filter.lock().unwrap();
+            if let ChatFilter::Folder(id) = filter {
+                if chat_list.folder(id).is_none() {
+                    chat_list
+                        .filter_action
+                        .activate(Some(&filter_id(ChatFilter::All).to_variant()));
+                }
+            }
+
+            let current = chat_list.current_contact.lock().unwrap().clone();
+            if let Some(jid) = current {
+                chat_list.show_chat_folders(&jid);
+            }
+        });
+    }
+
+    /// One tab per built-in filter and folder, and one to add a folder
+    fn populate_filters(&self) {
+        while let Some(child) = self.filters_box.first_child() {
+            self.filters_box.remove(&child);
+        }
+
+        let tab = |label: &str, filter: ChatFilter| {
+            let button = gtk4::ToggleButton::builder()
+                .label(label)
+                .action_name("sidebar.filter")
+                .action_target(&filter_id(filter).to_variant())
+                .css_classes(vec!["flat", "filter-tab"])
+                .build();
+            self.filters_box.append(&button);
+            button
+        };
+        for filter in ChatFilter::BUILT_IN {
+            tab(filter.label(), filter);
+        }
+        for folder in self.folders.lock().unwrap().iter() {
+            let button = tab(&folder.name, ChatFilter::Folder(folder.id));
+            // Folders mirroring a Business label follow the label
+            if folder.label_id.is_none() {
+                attach_folder_menu(&button, folder.id);
+            } else {
+                button.set_tooltip_text(Some("Business label"));
+            }
+        }
+
+        let add_button = Button::builder()
+            .icon_name("list-add-symbolic")
+            .tooltip_text("New folder")
+            .action_name("sidebar.new-folder")
+            .css_classes(vec!["flat", "filter-tab"])
+            .build();
+        self.filters_box.append(&add_button);
+    }
+
+    /// A check item per folder in the chat menu
+    fn populate_folder_menu(&self) {
+        self.folder_menu.remove_all();
+
+        let folders = self.folders.lock().unwrap();
+        for folder in folders.iter() {
+            let name = format!("folder-{}", folder.id);
+            let action = gtk4::gio::SimpleAction::new_stateful(&name, None, &false.to_variant());
+            action.set_enabled(folder.label_id.is_none());
+
+            let chat_list = self.clone();
+            let folder_id = folder.id;
+            action.connect_activate(move |action, _| {
+                let Some(jid) = chat_list.current_contact.lock().unwrap().clone() else {
+                    return;
+                };
+                let member = !action
+                    .state()
+                    .and_then(|s| s.get::<bool>())
+                    .unwrap_or(false);
+                action.set_state(&member.to_variant());
+
+                let pending = chat_list.db.set_chat_folder(&jid, folder_id, member);
+                let chat_list = chat_list.clone();
+                glib::MainContext::default().spawn_local(async move {
+                    if let Err(e) = pending.await {
+                        eprintln!("[MainView] Error updating folder of {}: {}", jid, e);
+                    }
+                    chat_list.reload();
+                });
+            });
+            self.chat_actions.add_action(&action);
+            self.folder_menu
+                .append(Some(&folder.name), Some(&format!("chat.{}", name)));
+        }
+
+        if folders.is_empty() {
+            self.folder_menu.append(Some("No Folders"), None);
+        }
+    }
+
+    /// Check the folders the open chat is in
+    fn show_chat_folders(&self, jid: &str) {
+        let member_of = self
+            .contacts
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|c| c.jid == jid)
+            .map(|c| c.folders.clone())
+            .unwrap_or_default();
+
+        for folder in self.folders.lock().unwrap().iter() {
+            let action = self
+                .chat_actions
+                .lookup_action(&format!("folder-{}", folder.id))
+                .and_downcast::<gtk4::gio::SimpleAction>();
+            if let Some(action) = action {
+                action.set_state(&member_of.contains(&folder.id).to_variant());
+            }
+        }
+    }
+
+    /// Ask for a folder name and pass it to `on_accept`
+    fn prompt_name<F: Fn(String) + 'static>(&self, heading: &str, name: &str, on_accept: F) {
+        if let Some(window) = self.contacts_list.root().and_downcast::<gtk4::Window>() {
+            prompt_folder_name(&window, heading, name, on_accept);
+        }
+    }
+}
+
+/// Right-click menu of a folder tab
+fn attach_folder_menu(button: &gtk4::ToggleButton, folder_id: i64) {
+    let gesture = gtk4::GestureClick::new();
+    gesture.set_button(gtk4::gdk::BUTTON_SECONDARY);
+
+    let button_clone = button.clone();
+    gesture.connect_pressed(move |gesture, _, _, _| {
+        gesture.set_state(gtk4::EventSequenceState::Claimed);
+
+        let menu = gtk4::gio::Menu::new();
+        for (label, action) in [
+            ("Rename…", "sidebar.rename-folder"),
+            ("Delete", "sidebar.delete-folder"),
+        ] {
+            let item = gtk4::gio::MenuItem::new(Some(label), None);
+            item.set_action_and_target_value(Some(action), Some(&folder_id.to_variant()));
+            menu.append_item(&item);
+        }
+
+        let popover = gtk4::PopoverMenu::from_model(Some(&menu));
+        popover.set_parent(&button_clone);
+        // Unparenting right away would drop the item's activation
+        popover.connect_closed(|popover| {
+            let popover = popover.clone();
+            glib::idle_add_local_once(move || popover.unparent());
+        });
+        popover.popup();
+    });
+    button.add_controller(gesture);
+}
+
+/// Right-click menu of a chat in the list
+fn attach_chat_menu(row: &gtk4::ListBoxRow, jid: &str, unread: bool) {
+    let gesture = gtk4::GestureClick::new();
+    gesture.set_button(gtk4::gdk::BUTTON_SECONDARY);
+
+    let row_clone = row.clone();
+    let jid = jid.to_string();
+    gesture.connect_pressed(move |gesture, _, x, y| {
+        gesture.set_state(gtk4::EventSequenceState::Claimed);
+
+        let menu = gtk4::gio::Menu::new();
+        let (label, action) = if unread {
+            ("Mark as Read", "sidebar.mark-read")
+        } else {
+            ("Mark as Unread", "sidebar.mark-unread")
+        };
+        let item = gtk4::gio::MenuItem::new(Some(label), None);
+        item.set_action_and_target_value(Some(action), Some(&jid.to_variant()));
+        menu.append_item(&item);
+
+        let popover = gtk4::PopoverMenu::from_model(Some(&menu));
+        popover.set_parent(&row_clone);
+        popover.set_has_arrow(false);
+        popover.set_pointing_to(Some(&gtk4::gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+        // Unparenting right away would drop the item's activation
+        popover.connect_closed(|popover| {
+            let popover = popover.clone();
+            glib::idle_add_local_once(move || popover.unparent());
+        });
+        popover.popup();
+    });
+    row.add_controller(gesture);
+}
+
+/// Who wrote a message, as named in quotes of it
+fn quote_author(message: &Message) -> &str {
+    if message.is_from_me {
+        "You"
+    } else {
+        &message.sender
+    }
+}
+
+/// Target of the `sidebar.filter` action for a filter
+fn filter_id(filter: ChatFilter) -> String {
+    match filter {
+        ChatFilter::Folder(id) => format!("folder-{}", id),
+        _ => filter.label().to_lowercase(),
+    }
+}
+
+fn filter_from_id(id: &str) -> Option<ChatFilter> {
+    if let Some(folder_id) = id.strip_prefix("folder-") {
+        return folder_id.parse().ok().map(ChatFilter::Folder);
+    }
+    ChatFilter::BUILT_IN
+        .into_iter()
+        .find(|filter| filter_id(*filter) == id)
+}
+
+/// Retention choices offered in the chat menu, as action target and label
+const RETENTION_CHOICES: [(&str, &str); 6] = [
+    ("default", "Default"),
+    ("forever", "Forever"),
+    ("1y", "1 Year"),
+    ("30d", "30 Days"),
+    ("7d", "7 Days"),
+    ("1000m", "Last 1000 Messages"),
+];
+
+/// Policy a chat menu choice stands for, `None` for the global one
+fn retention_policy(choice: &str) -> Option<RetentionPolicy> {
+    let policy = |keep_days, keep_messages| {
+        Some(RetentionPolicy {
+            keep_days,
+            keep_messages,
+        })
+    };
+    match choice {
+        "forever" => policy(None, None),
+        "1y" => policy(Some(365), None),
+        "30d" => policy(Some(30), None),
+        "7d" => policy(Some(7), None),
+        "1000m" => policy(None, Some(1000)),
+        _ => None,
+    }
+}
+
+/// Chat menu choice matching a chat's policy
+fn retention_choice(policy: Option<RetentionPolicy>) -> &'static str {
+    RETENTION_CHOICES
+        .iter()
+        .map(|(id, _)| *id)
+        .find(|id| retention_policy(id) == policy)
+        .unwrap_or("default")
 }
@@ -1,12 +1,41 @@
 use gtk4::prelude::*;
-use gtk4::{Box as GtkBox, Label, Orientation};
+use gtk4::{Box as GtkBox, Label, Orientation, gio, glib};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 
+use crate::models::{MediaEntry, Message, MessageEdit, Reaction, SendState};
+
+/// Largest width an inline image is shown at
+const MAX_IMAGE_WIDTH: i32 = 280;
+
+/// Reactions offered by the quick-react picker
+const QUICK_REACTIONS: [&str; 6] = ["👍", "❤️", "😂", "😮", "😢", "🙏"];
+
+#[derive(Clone)]
 pub struct MessageRow {
     pub widget: GtkBox,
+    /// Changes once WhatsApp gives a message we sent an id of its own
+    message_id: Rc<RefCell<String>>,
+    bubble: GtkBox,
+    content_label: Label,
+    reactions_box: GtkBox,
+    edited_button: gtk4::Button,
+    star_icon: gtk4::Image,
+    starred: Rc<Cell<bool>>,
+    state_icon: gtk4::Image,
+    retry_button: gtk4::Button,
+    send_state: Rc<Cell<Option<SendState>>>,
+    upload_bar: gtk4::ProgressBar,
+    /// Our own reaction, marked in the picker
+    my_reaction: Rc<RefCell<Option<String>>>,
+    is_from_me: bool,
+    timestamp: i64,
+    /// Decides what the menu offers, e.g. only text can be edited
+    message_type: Rc<RefCell<String>>,
 }
 
 impl MessageRow {
-    pub fn new(content: &str, is_from_me: bool, timestamp: i64) -> Self {
+    pub fn new(message_id: &str, content: &str, is_from_me: bool, timestamp: i64) -> Self {
         let container = GtkBox::new(Orientation::Horizontal, 0);
         container.set_margin_start(10);
         container.set_margin_end(10);
@@ -33,31 +62,536 @@ impl MessageRow {
         let time_str = Self::format_timestamp(timestamp);
         let time_label = Label::builder()
             .label(&time_str)
-            .xalign(1.0) // Align time to the right
+            .css_classes(vec!["caption", "dim-label"])
+            .build();
+        let star_icon = gtk4::Image::builder()
+            .icon_name("starred-symbolic")
+            .pixel_size(12)
+            .tooltip_text("Starred")
+            .css_classes(vec!["dim-label"])
+            .visible(false)
+            .build();
+
+        // Opens the earlier versions of an edited message
+        let edited_button = gtk4::Button::builder()
+            .label("Edited")
+            .tooltip_text("Show earlier versions")
+            .action_name("chat.show-versions")
+            .action_target(&message_id.to_variant())
+            .css_classes(vec!["flat", "caption", "dim-label", "edited-button"])
+            .visible(false)
+            .build();
+
+        // Whether our message is still on its way out
+        let state_icon = gtk4::Image::builder().pixel_size(12).visible(false).build();
+        let retry_button = gtk4::Button::builder()
+            .label("Retry")
+            .tooltip_text("Try sending again")
+            .action_name("chat.retry-send")
+            .action_target(&message_id.to_variant())
+            .css_classes(vec!["flat", "caption", "edited-button"])
+            .visible(false)
+            .build();
+
+        // How much of an attachment we send has been uploaded
+        let upload_bar = gtk4::ProgressBar::builder()
             .margin_start(12)
             .margin_end(12)
-            .margin_top(0)
-            .margin_bottom(6)
-            .css_classes(vec!["caption", "dim-label"])
+            .visible(false)
             .build();
 
+        // Time aligned to the right, after the star
+        let footer = GtkBox::new(Orientation::Horizontal, 4);
+        footer.set_halign(gtk4::Align::End);
+        footer.set_margin_start(12);
+        footer.set_margin_end(12);
+        footer.set_margin_bottom(6);
+        footer.append(&edited_button);
+        footer.append(&star_icon);
+        footer.append(&time_label);
+        footer.append(&state_icon);
+        footer.append(&retry_button);
+
         bubble.append(&content_label);
-        bubble.append(&time_label);
+        bubble.append(&upload_bar);
+        bubble.append(&footer);
+
+        // Reaction chips sit just below the bubble
+        let reactions_box = GtkBox::new(Orientation::Horizontal, 4);
+        reactions_box.set_margin_start(16);
+        reactions_box.set_margin_end(16);
+        reactions_box.set_visible(false);
 
         if is_from_me {
             // Own messages - align right with accent color background
             bubble.add_css_class("message-sent");
             container.set_halign(gtk4::Align::End);
+            reactions_box.set_halign(gtk4::Align::End);
         } else {
             // Other messages - align left with card background
             bubble.add_css_class("card");
             bubble.add_css_class("message-received");
             container.set_halign(gtk4::Align::Start);
+            reactions_box.set_halign(gtk4::Align::Start);
+        }
+
+        let column = GtkBox::new(Orientation::Vertical, 0);
+        column.append(&bubble);
+        column.append(&reactions_box);
+
+        // Opens the quick-react picker, shown while hovering the message
+        let react_button = gtk4::Button::builder()
+            .icon_name("face-smile-symbolic")
+            .tooltip_text("React")
+            .valign(gtk4::Align::Center)
+            .opacity(0.0)
+            .css_classes(vec!["flat", "circular"])
+            .build();
+        // Beside the bubble, on the side facing the middle of the chat
+        if is_from_me {
+            container.append(&react_button);
+            container.append(&column);
+        } else {
+            container.append(&column);
+            container.append(&react_button);
+        }
+
+        let row = Self {
+            widget: container,
+            message_id: Rc::new(RefCell::new(message_id.to_string())),
+            bubble,
+            content_label,
+            reactions_box,
+            edited_button,
+            star_icon,
+            starred: Rc::new(Cell::new(false)),
+            state_icon,
+            retry_button,
+            send_state: Rc::new(Cell::new(None)),
+            upload_bar,
+            my_reaction: Rc::new(RefCell::new(None)),
+            is_from_me,
+            timestamp,
+            message_type: Rc::new(RefCell::new("text".to_string())),
+        };
+        row.attach_menu();
+        row.attach_react_picker(&react_button);
+        row
+    }
+
+    /// Point the row's actions at the id WhatsApp gave our message
+    pub fn set_message_id(&self, message_id: &str) {
+        *self.message_id.borrow_mut() = message_id.to_string();
+        self.edited_button
+            .set_action_target_value(Some(&message_id.to_variant()));
+        self.retry_button
+            .set_action_target_value(Some(&message_id.to_variant()));
+    }
+
+    /// Show the latest version of an edited message
+    pub fn set_content(&self, content: &str, edited: bool) {
+        self.content_label.set_label(content);
+        self.edited_button.set_visible(edited);
+    }
+
+    /// The text on screen, which is the latest version of an edited message
+    pub fn content(&self) -> String {
+        self.content_label.label().to_string()
+    }
+
+    /// Note the kind of message shown. One deleted for everyone loses its
+    /// content, pictures, quote and reactions.
+    pub fn set_message_type(&self, message_type: &str) {
+        *self.message_type.borrow_mut() = message_type.to_string();
+        if message_type != "revoked" {
+            return;
+        }
+
+        let footer = self.edited_button.parent();
+        let mut child = self.bubble.first_child();
+        while let Some(widget) = child {
+            child = widget.next_sibling();
+            let kept = widget == self.content_label
+                || widget == self.upload_bar
+                || Some(&widget) == footer.as_ref();
+            if !kept {
+                self.bubble.remove(&widget);
+            }
+        }
+        self.content_label.set_label(Message::REVOKED_CONTENT);
+        self.content_label.add_css_class("message-revoked");
+        self.edited_button.set_visible(false);
+        self.set_reactions(&[]);
+    }
+
+    /// Whether WhatsApp has this message and it can still be acted on
+    fn is_actionable(send_state: &Cell<Option<SendState>>, message_type: &RefCell<String>) -> bool {
+        send_state.get().is_none() && *message_type.borrow() != "revoked"
+    }
+
+    /// Pop up earlier versions of the message, oldest first, with when each
+    /// was written
+    pub fn show_versions(&self, versions: &[MessageEdit]) {
+        let list = GtkBox::new(Orientation::Vertical, 8);
+        list.set_margin_start(6);
+        list.set_margin_end(6);
+        list.set_margin_top(6);
+        list.set_margin_bottom(6);
+
+        // The newest version is the one on screen
+        let earlier = &versions[..versions.len().saturating_sub(1)];
+        for version in earlier {
+            let entry = GtkBox::new(Orientation::Vertical, 2);
+            entry.append(
+                &Label::builder()
+                    .label(&version.content)
+                    .wrap(true)
+                    .wrap_mode(gtk4::pango::WrapMode::WordChar)
+                    .max_width_chars(40)
+                    .xalign(0.0)
+                    .selectable(true)
+                    .build(),
+            );
+            entry.append(
+                &Label::builder()
+                    .label(&format_datetime(version.edited_at))
+                    .xalign(0.0)
+                    .css_classes(vec!["caption", "dim-label"])
+                    .build(),
+            );
+            list.append(&entry);
+        }
+        if earlier.is_empty() {
+            list.append(&Label::new(Some("No earlier versions")));
+        }
+
+        let popover = gtk4::Popover::builder().child(&list).build();
+        popover.set_parent(&self.edited_button);
+        popover.connect_closed(|popover| {
+            let popover = popover.clone();
+            glib::idle_add_local_once(move || popover.unparent());
+        });
+        popover.popup();
+    }
+
+    pub fn is_starred(&self) -> bool {
+        self.starred.get()
+    }
+
+    pub fn set_starred(&self, starred: bool) {
+        self.starred.set(starred);
+        self.star_icon.set_visible(starred);
+    }
+
+    /// Show a clock while our message is pending, and an error with a way
+    /// to retry once sending it failed
+    pub fn set_send_state(&self, state: Option<SendState>) {
+        self.send_state.set(state);
+        match state {
+            Some(SendState::Pending) => {
+                self.state_icon
+                    .set_icon_name(Some("document-open-recent-symbolic"));
+                self.state_icon.set_tooltip_text(Some("Sending…"));
+                self.state_icon.set_css_classes(&["dim-label"]);
+            }
+            Some(SendState::Failed) => {
+                self.state_icon.set_icon_name(Some("dialog-error-symbolic"));
+                self.state_icon.set_tooltip_text(Some("Not sent"));
+                self.state_icon.set_css_classes(&["error"]);
+            }
+            None => {}
+        }
+        self.state_icon.set_visible(state.is_some());
+        self.retry_button
+            .set_visible(state == Some(SendState::Failed));
+        if state != Some(SendState::Pending) {
+            self.upload_bar.set_visible(false);
         }
+    }
+
+    /// Show how much of our attachment has been uploaded, from 0 to 1
+    pub fn set_upload_progress(&self, fraction: f64) {
+        self.upload_bar.set_fraction(fraction);
+        self.upload_bar.set_visible(true);
+    }
 
-        container.append(&bubble);
+    /// Briefly outline the bubble, to point out a message jumped to
+    pub fn highlight(&self) {
+        self.bubble.add_css_class("message-highlight");
+        let bubble = self.bubble.clone();
+        glib::timeout_add_seconds_local_once(2, move || {
+            bubble.remove_css_class("message-highlight");
+        });
+    }
+
+    /// Right-click menu of actions on this message. Items activate actions
+    /// of the `chat` group with the message id as their target.
+    fn attach_menu(&self) {
+        let gesture = gtk4::GestureClick::new();
+        gesture.set_button(gtk4::gdk::BUTTON_SECONDARY);
+
+        let bubble = self.bubble.clone();
+        let starred = Rc::clone(&self.starred);
+        let send_state = Rc::clone(&self.send_state);
+        let message_id = Rc::clone(&self.message_id);
+        let message_type = Rc::clone(&self.message_type);
+        let is_from_me = self.is_from_me;
+        let timestamp = self.timestamp;
+        gesture.connect_pressed(move |gesture, _, x, y| {
+            gesture.set_state(gtk4::EventSequenceState::Claimed);
+            let message_id = message_id.borrow().clone();
+            let actionable = Self::is_actionable(&send_state, &message_type);
+            let age = glib::real_time() / 1_000_000 - timestamp;
 
-        Self { widget: container }
+            let menu = gio::Menu::new();
+            // Only messages WhatsApp has can be quoted
+            if actionable {
+                let item = gio::MenuItem::new(Some("Reply"), None);
+                item.set_action_and_target_value(
+                    Some("chat.reply"),
+                    Some(&message_id.to_variant()),
+                );
+                menu.append_item(&item);
+            }
+            let label = if starred.get() { "Unstar" } else { "Star" };
+            let item = gio::MenuItem::new(Some(label), None);
+            item.set_action_and_target_value(
+                Some("chat.toggle-star"),
+                Some(&message_id.to_variant()),
+            );
+            menu.append_item(&item);
+            if send_state.get() == Some(SendState::Failed) {
+                let item = gio::MenuItem::new(Some("Retry Sending"), None);
+                item.set_action_and_target_value(
+                    Some("chat.retry-send"),
+                    Some(&message_id.to_variant()),
+                );
+                menu.append_item(&item);
+            }
+            // WhatsApp only takes edits of our texts for a little while
+            if actionable
+                && is_from_me
+                && *message_type.borrow() == "text"
+                && age < Message::EDIT_WINDOW
+            {
+                let item = gio::MenuItem::new(Some("Edit"), None);
+                item.set_action_and_target_value(Some("chat.edit"), Some(&message_id.to_variant()));
+                menu.append_item(&item);
+            }
+
+            let delete_section = gio::Menu::new();
+            if send_state.get().is_none() {
+                let item = gio::MenuItem::new(Some("Delete for Me"), None);
+                item.set_action_and_target_value(
+                    Some("chat.delete"),
+                    Some(&(message_id.as_str(), false).to_variant()),
+                );
+                delete_section.append_item(&item);
+            }
+            if actionable && is_from_me && age < Message::REVOKE_WINDOW {
+                let item = gio::MenuItem::new(Some("Delete for Everyone"), None);
+                item.set_action_and_target_value(
+                    Some("chat.delete"),
+                    Some(&(message_id.as_str(), true).to_variant()),
+                );
+                delete_section.append_item(&item);
+            }
+            menu.append_section(None, &delete_section);
+
+            let popover = gtk4::PopoverMenu::from_model(Some(&menu));
+            popover.set_parent(&bubble);
+            popover.set_has_arrow(false);
+            popover.set_pointing_to(Some(&gtk4::gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+            // Unparenting right away would drop the item's activation
+            popover.connect_closed(|popover| {
+                let popover = popover.clone();
+                glib::idle_add_local_once(move || popover.unparent());
+            });
+            popover.popup();
+        });
+        self.bubble.add_controller(gesture);
+    }
+
+    /// Show the message this one replies to above its content. Clicking it
+    /// jumps to the original.
+    pub fn set_quote(&self, author: Option<&str>, text: &str, quoted_id: &str) {
+        let quote = GtkBox::new(Orientation::Vertical, 2);
+        if let Some(author) = author {
+            quote.append(
+                &Label::builder()
+                    .label(author)
+                    .xalign(0.0)
+                    .ellipsize(gtk4::pango::EllipsizeMode::End)
+                    .css_classes(vec!["caption-heading"])
+                    .build(),
+            );
+        }
+        quote.append(
+            &Label::builder()
+                .label(text)
+                .xalign(0.0)
+                .lines(2)
+                .wrap(true)
+                .wrap_mode(gtk4::pango::WrapMode::WordChar)
+                .ellipsize(gtk4::pango::EllipsizeMode::End)
+                .max_width_chars(40)
+                .css_classes(vec!["caption"])
+                .build(),
+        );
+
+        let button = gtk4::Button::builder()
+            .child(&quote)
+            .tooltip_text("Show the original message")
+            .action_name("chat.open-quoted")
+            .action_target(&quoted_id.to_variant())
+            .css_classes(vec!["flat", "message-quote"])
+            .margin_start(8)
+            .margin_end(8)
+            .margin_top(6)
+            .build();
+        self.bubble
+            .insert_child_after(&button, None::<&gtk4::Widget>);
+    }
+
+    /// Open the quick-react picker from `react_button`, shown on hover, or
+    /// by long-pressing the bubble. Messages WhatsApp doesn't have yet, or
+    /// deleted ones, can't be reacted to.
+    fn attach_react_picker(&self, react_button: &gtk4::Button) {
+        let motion = gtk4::EventControllerMotion::new();
+        let button = react_button.clone();
+        let send_state = Rc::clone(&self.send_state);
+        let message_type = Rc::clone(&self.message_type);
+        motion.connect_enter(move |_, _, _| {
+            if Self::is_actionable(&send_state, &message_type) {
+                button.set_opacity(1.0);
+            }
+        });
+        let button = react_button.clone();
+        motion.connect_leave(move |_| button.set_opacity(0.0));
+        self.widget.add_controller(motion);
+
+        let row = self.clone();
+        react_button.connect_clicked(move |button| {
+            if Self::is_actionable(&row.send_state, &row.message_type) {
+                row.show_react_picker(button);
+            }
+        });
+
+        let long_press = gtk4::GestureLongPress::new();
+        let row = self.clone();
+        long_press.connect_pressed(move |gesture, _, _| {
+            gesture.set_state(gtk4::EventSequenceState::Claimed);
+            if Self::is_actionable(&row.send_state, &row.message_type) {
+                row.show_react_picker(&row.bubble);
+            }
+        });
+        self.bubble.add_controller(long_press);
+    }
+
+    /// Pop up the quick reactions. Each activates `chat.react` with the
+    /// message id and emoji; picking our current reaction again removes it.
+    fn show_react_picker(&self, parent: &impl IsA<gtk4::Widget>) {
+        let message_id = self.message_id.borrow().clone();
+        let mine = self.my_reaction.borrow().clone();
+
+        let picker = GtkBox::new(Orientation::Horizontal, 2);
+        let popover = gtk4::Popover::builder().child(&picker).build();
+        for emoji in QUICK_REACTIONS {
+            let button = gtk4::Button::builder()
+                .label(emoji)
+                .action_name("chat.react")
+                .action_target(&(message_id.as_str(), emoji).to_variant())
+                .css_classes(vec!["flat"])
+                .build();
+            if mine.as_deref() == Some(emoji) {
+                button.add_css_class("suggested-action");
+                button.set_tooltip_text(Some("Remove reaction"));
+            }
+            let popover = popover.clone();
+            button.connect_clicked(move |_| popover.popdown());
+            picker.append(&button);
+        }
+
+        popover.set_parent(parent);
+        popover.connect_closed(|popover| {
+            let popover = popover.clone();
+            glib::idle_add_local_once(move || popover.unparent());
+        });
+        popover.popup();
+    }
+
+    /// Show a downloaded image or sticker at the top of the bubble
+    pub fn set_image(&self, media: &MediaEntry) {
+        self.show_picture(&media.local_path, media.width, media.height);
+    }
+
+    /// Show a picture we're sending straight from where it was picked
+    pub fn set_local_image(&self, path: &str) {
+        let (width, height) = match gtk4::gdk_pixbuf::Pixbuf::file_info(path) {
+            Some((_, width, height)) => (Some(width), Some(height)),
+            None => (None, None),
+        };
+        self.show_picture(path, width, height);
+    }
+
+    fn show_picture(&self, path: &str, width: Option<i32>, height: Option<i32>) {
+        let picture = gtk4::Picture::for_filename(path);
+        picture.set_margin_start(4);
+        picture.set_margin_end(4);
+        picture.set_margin_top(4);
+
+        // Reserve the final size up front so the chat doesn't jump around
+        if let (Some(width), Some(height)) = (width, height) {
+            if width > 0 && height > 0 {
+                let shown_width = width.min(MAX_IMAGE_WIDTH);
+                picture.set_size_request(shown_width, height * shown_width / width);
+            }
+        }
+
+        // Below the quote of a reply, if there is one
+        self.bubble
+            .insert_child_after(&picture, self.content_label.prev_sibling().as_ref());
+    }
+
+    /// Show one chip per emoji with the number of people who used it. The
+    /// tooltip lists who reacted.
+    pub fn set_reactions(&self, reactions: &[Reaction]) {
+        *self.my_reaction.borrow_mut() = reactions
+            .iter()
+            .find(|reaction| reaction.reactor == "me")
+            .map(|reaction| reaction.emoji.clone());
+
+        while let Some(child) = self.reactions_box.first_child() {
+            self.reactions_box.remove(&child);
+        }
+
+        // Group by emoji, keeping the order in which each emoji first appeared
+        let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+        for reaction in reactions {
+            let reactor = if reaction.reactor == "me" {
+                "You"
+            } else {
+                reaction.reactor.as_str()
+            };
+            match groups
+                .iter_mut()
+                .find(|(emoji, _)| *emoji == reaction.emoji)
+            {
+                Some((_, reactors)) => reactors.push(reactor),
+                None => groups.push((reaction.emoji.as_str(), vec![reactor])),
+            }
+        }
+
+        for (emoji, reactors) in &groups {
+            let chip = Label::builder()
+                .label(&format!("{} {}", emoji, reactors.len()))
+                .tooltip_text(&reactors.join(", "))
+                .css_classes(vec!["reaction-chip"])
+                .build();
+            self.reactions_box.append(&chip);
+        }
+
+        self.reactions_box.set_visible(!groups.is_empty());
     }
 
     fn format_timestamp(timestamp: i64) -> String {
@@ -81,3 +615,11 @@ impl MessageRow {
         }
     }
 }
+
+/// Local date and time, e.g. "12/03/2024 14:05"
+fn format_datetime(timestamp: i64) -> String {
+    glib::DateTime::from_unix_local(timestamp)
+        .and_then(|date| date.format("%x %H:%M"))
+        .map(|date| date.to_string())
+        .unwrap_or_default()
+}
@@ -1,11 +1,15 @@
 pub mod contact_row;
 pub mod main_view;
 pub mod message_row;
+pub mod preferences_window;
 pub mod qr_view;
+pub mod starred_view;
 pub mod widgets;
 
 pub use contact_row::ContactRow;
 pub use main_view::MainView;
 pub use message_row::MessageRow;
+pub use preferences_window::PreferencesWindow;
 pub use qr_view::QrView;
+pub use starred_view::StarredView;
 pub use widgets::*;
@@ -0,0 +1,91 @@
+use adw::prelude::*;
+use gtk4::prelude::*;
+use libadwaita as adw;
+
+/// Preferences of the active account and of the app
+pub struct PreferencesWindow {
+    window: adw::PreferencesWindow,
+    repair_button: gtk4::Button,
+}
+
+impl PreferencesWindow {
+    pub fn new(parent: &impl IsA<gtk4::Window>) -> Self {
+        let window = adw::PreferencesWindow::builder()
+            .transient_for(parent)
+            .modal(true)
+            .search_enabled(false)
+            .build();
+        // Reaches the app's actions through it
+        window.set_application(parent.as_ref().application().as_ref());
+
+        let repair_button = gtk4::Button::builder()
+            .label("Check")
+            .valign(gtk4::Align::Center)
+            .build();
+        let repair_row = adw::ActionRow::builder()
+            .title("Check and Repair")
+            .subtitle("Look for damage, remove broken entries and compact the database")
+            .build();
+        repair_row.add_suffix(&repair_button);
+        repair_row.set_activatable_widget(Some(&repair_button));
+
+        let database_group = adw::PreferencesGroup::builder().title("Database").build();
+        database_group.add(&repair_row);
+
+        let storage_page = adw::PreferencesPage::builder()
+            .title("Storage")
+            .icon_name("drive-harddisk-symbolic")
+            .build();
+        storage_page.add(&database_group);
+        window.add(&storage_page);
+
+        // Toggles `app.share-presence`
+        let presence_switch = gtk4::Switch::builder()
+            .action_name("app.share-presence")
+            .valign(gtk4::Align::Center)
+            .build();
+        let presence_row = adw::ActionRow::builder()
+            .title("Share Online and Typing Status")
+            .subtitle("Let contacts see when you're online and typing")
+            .build();
+        presence_row.add_suffix(&presence_switch);
+        presence_row.set_activatable_widget(Some(&presence_switch));
+
+        let presence_group = adw::PreferencesGroup::builder().title("Presence").build();
+        presence_group.add(&presence_row);
+
+        let privacy_page = adw::PreferencesPage::builder()
+            .title("Privacy")
+            .icon_name("security-high-symbolic")
+            .build();
+        privacy_page.add(&presence_group);
+        window.add(&privacy_page);
+
+        Self {
+            window,
+            repair_button,
+        }
+    }
+
+    /// Call `callback` when a check and repair of the database is asked for
+    pub fn connect_repair<F: Fn() + 'static>(&self, callback: F) {
+        self.repair_button.connect_clicked(move |_| callback());
+    }
+
+    /// Show that a repair is running, which can't be started again meanwhile
+    pub fn set_repairing(&self, repairing: bool) {
+        self.repair_button.set_sensitive(!repairing);
+        self.repair_button
+            .set_label(if repairing { "Checking…" } else { "Check" });
+    }
+
+    pub fn show_report(&self, heading: &str, report: &str) {
+        let dialog = adw::MessageDialog::new(Some(&self.window), Some(heading), Some(report));
+        dialog.add_response("close", "Close");
+        dialog.present();
+    }
+
+    pub fn present(&self) {
+        self.window.present();
+    }
+}
@@ -0,0 +1,141 @@
+use adw::prelude::*;
+use gtk4::prelude::*;
+use gtk4::{Box as GtkBox, Label, ListBox, Orientation, ScrolledWindow};
+use libadwaita as adw;
+
+use crate::models::Message;
+
+/// Window listing starred messages of all chats, newest first
+pub struct StarredView {
+    window: adw::Window,
+    // Chat and message id of each row, by row index
+    targets: Vec<(String, String)>,
+    list: ListBox,
+}
+
+impl StarredView {
+    /// `starred` holds each message with the name of its chat
+    pub fn new(parent: Option<&gtk4::Window>, starred: &[(Message, String)]) -> Self {
+        let window = adw::Window::builder()
+            .title("Starred Messages")
+            .default_width(420)
+            .default_height(560)
+            .build();
+        if let Some(parent) = parent {
+            window.set_transient_for(Some(parent));
+        }
+
+        let content = GtkBox::new(Orientation::Vertical, 0);
+        content.append(&adw::HeaderBar::new());
+
+        let list = ListBox::new();
+        list.set_css_classes(&["navigation-sidebar"]);
+        list.set_selection_mode(gtk4::SelectionMode::None);
+
+        let mut targets = Vec::new();
+        for (message, chat_name) in starred {
+            let row = gtk4::ListBoxRow::new();
+            row.set_child(Some(&Self::create_row(message, chat_name)));
+            row.set_activatable(true);
+            list.append(&row);
+            targets.push((message.jid.clone(), message.message_id.clone()));
+        }
+
+        if starred.is_empty() {
+            let empty = adw::StatusPage::builder()
+                .icon_name("starred-symbolic")
+                .title("No Starred Messages")
+                .description("Star messages from their menu to find them here")
+                .vexpand(true)
+                .build();
+            content.append(&empty);
+        } else {
+            let scrolled = ScrolledWindow::builder()
+                .hscrollbar_policy(gtk4::PolicyType::Never)
+                .vexpand(true)
+                .child(&list)
+                .build();
+            content.append(&scrolled);
+        }
+        window.set_content(Some(&content));
+
+        Self {
+            window,
+            targets,
+            list,
+        }
+    }
+
+    /// Call `callback` with the chat and message id of the row picked
+    pub fn connect_open<F: Fn(&str, &str) + 'static>(&self, callback: F) {
+        let targets = self.targets.clone();
+        self.list.connect_row_activated(move |_, row| {
+            let Some((jid, message_id)) = usize::try_from(row.index())
+                .ok()
+                .and_then(|index| targets.get(index))
+            else {
+                return;
+            };
+            callback(jid, message_id);
+        });
+    }
+
+    pub fn present(&self) {
+        self.window.present();
+    }
+
+    fn create_row(message: &Message, chat_name: &str) -> GtkBox {
+        let row = GtkBox::new(Orientation::Vertical, 4);
+        row.set_margin_start(12);
+        row.set_margin_end(12);
+        row.set_margin_top(8);
+        row.set_margin_bottom(8);
+
+        let header = GtkBox::new(Orientation::Horizontal, 8);
+        let sender = if message.is_from_me {
+            "You".to_string()
+        } else {
+            message.sender.split('@').next().unwrap_or("").to_string()
+        };
+        let title = if sender.is_empty() || sender == chat_name {
+            chat_name.to_string()
+        } else {
+            format!("{} › {}", sender, chat_name)
+        };
+        let name_label = Label::builder()
+            .label(&title)
+            .halign(gtk4::Align::Start)
+            .hexpand(true)
+            .css_classes(vec!["heading"])
+            .ellipsize(gtk4::pango::EllipsizeMode::End)
+            .build();
+        let time_label = Label::builder()
+            .label(&format_date(message.timestamp))
+            .css_classes(vec!["caption", "dim-label"])
+            .build();
+        header.append(&name_label);
+        header.append(&time_label);
+
+        let content_label = Label::builder()
+            .label(&message.content)
+            .halign(gtk4::Align::Start)
+            .xalign(0.0)
+            .wrap(true)
+            .wrap_mode(gtk4::pango::WrapMode::WordChar)
+            .lines(3)
+            .ellipsize(gtk4::pango::EllipsizeMode::End)
+            .build();
+
+        row.append(&header);
+        row.append(&content_label);
+        row
+    }
+}
+
+/// Local date of a message, e.g. "12/03/2024"
+fn format_date(timestamp: i64) -> String {
+    gtk4::glib::DateTime::from_unix_local(timestamp)
+        .and_then(|date| date.format("%x"))
+        .map(|date| date.to_string())
+        .unwrap_or_default()
+}
@@ -1,10 +1,13 @@
+use adw::prelude::*;
 use gtk4::gdk_pixbuf::Pixbuf;
-use gtk4::gio::Cancellable;
+use gtk4::gio::{self, Cancellable};
 use gtk4::prelude::*;
 use libadwaita as adw;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use crate::models::Attachment;
+
 /// Create an avatar widget with profile picture URL or initials fallback
 pub fn create_avatar_with_pic(profile_pic_url: Option<&str>, name: &str, size: i32) -> adw::Avatar {
     let avatar = adw::Avatar::builder().size(size).text(name).build();
@@ -46,6 +49,7 @@ async fn download_and_create_texture(
 pub struct ProfileButton {
     button: gtk4::MenuButton,
     avatar: adw::Avatar,
+    menu: gtk4::gio::Menu,
     profile_pic_url: Rc<RefCell<Option<String>>>,
 }
 
@@ -54,13 +58,15 @@ impl ProfileButton {
         let avatar = adw::Avatar::builder().size(32).text("ME").build();
 
         let button = gtk4::MenuButton::new();
-        button.set_icon_name("avatar-default-symbolic");
+        button.set_child(Some(&avatar));
+        button.set_tooltip_text(Some("Accounts"));
         button.add_css_class("flat");
         button.add_css_class("circular");
 
         // Create popover menu
         let menu = gtk4::gio::Menu::new();
         menu.append(Some("Preferences"), Some("win.preferences"));
+        menu.append(Some("Back Up Account…"), Some("app.backup"));
         menu.append(Some("About"), Some("win.about"));
 
         let popover = gtk4::PopoverMenu::builder().menu_model(&menu).build();
@@ -70,10 +76,18 @@ impl ProfileButton {
         Self {
             button,
             avatar: avatar.clone(),
+            menu,
             profile_pic_url: Rc::new(RefCell::new(None)),
         }
     }
 
+    /// Add the account switcher above the regular menu entries. The section
+    /// is shared by every account's header, so an account added to it shows
+    /// up in all of them.
+    pub fn set_accounts_section(&self, section: &gtk4::gio::Menu) {
+        self.menu.prepend_section(Some("Accounts"), section);
+    }
+
     /// Set the profile picture URL and update the avatar
     pub fn set_profile_pic(&self, url: Option<String>, name: Option<&str>) {
         *self.profile_pic_url.borrow_mut() = url.clone();
@@ -104,6 +118,202 @@ impl Default for ProfileButton {
     }
 }
 
+/// Ask for the display name of a new account and pass it to `on_accept`
+pub fn prompt_account_name<F: Fn(String) + 'static>(parent: &impl IsA<gtk4::Window>, on_accept: F) {
+    let entry = gtk4::Entry::builder()
+        .placeholder_text("e.g. Work")
+        .activates_default(true)
+        .build();
+
+    let dialog = adw::MessageDialog::new(
+        Some(parent),
+        Some("Add Account"),
+        Some("Each account links its own WhatsApp number and keeps its own chats."),
+    );
+    dialog.add_responses(&[("cancel", "Cancel"), ("add", "Add")]);
+    dialog.set_response_appearance("add", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("add"));
+    dialog.set_close_response("cancel");
+    dialog.set_extra_child(Some(&entry));
+
+    dialog.connect_response(None, move |_, response| {
+        let name = entry.text().trim().to_string();
+        if response == "add" && !name.is_empty() {
+            on_accept(name);
+        }
+    });
+
+    dialog.present();
+}
+
+/// Ask for the name of a folder, prefilled with `name`, and pass it to
+/// `on_accept`
+pub fn prompt_folder_name<F: Fn(String) + 'static>(
+    parent: &impl IsA<gtk4::Window>,
+    heading: &str,
+    name: &str,
+    on_accept: F,
+) {
+    let entry = gtk4::Entry::builder()
+        .text(name)
+        .placeholder_text("e.g. Family")
+        .activates_default(true)
+        .build();
+
+    let dialog = adw::MessageDialog::new(Some(parent), Some(heading), None);
+    dialog.add_responses(&[("cancel", "Cancel"), ("save", "Save")]);
+    dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("save"));
+    dialog.set_close_response("cancel");
+    dialog.set_extra_child(Some(&entry));
+
+    dialog.connect_response(None, move |_, response| {
+        let name = entry.text().trim().to_string();
+        if response == "save" && !name.is_empty() {
+            on_accept(name);
+        }
+    });
+
+    dialog.present();
+}
+
+/// Ask for the new text of a message we sent, prefilled with `text`, and
+/// pass it to `on_accept` if it changed
+pub fn prompt_message_edit<F: Fn(String) + 'static>(
+    parent: &impl IsA<gtk4::Window>,
+    text: &str,
+    on_accept: F,
+) {
+    let entry = gtk4::Entry::builder()
+        .text(text)
+        .activates_default(true)
+        .build();
+
+    let dialog = adw::MessageDialog::new(Some(parent), Some("Edit Message"), None);
+    dialog.add_responses(&[("cancel", "Cancel"), ("save", "Save")]);
+    dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("save"));
+    dialog.set_close_response("cancel");
+    dialog.set_extra_child(Some(&entry));
+
+    let original = text.to_string();
+    dialog.connect_response(None, move |_, response| {
+        let text = entry.text().trim().to_string();
+        if response == "save" && !text.is_empty() && text != original {
+            on_accept(text);
+        }
+    });
+
+    dialog.present();
+}
+
+/// Ask whether to really delete a message, for everyone in the chat or only
+/// for us, and call `on_confirm` if so
+pub fn confirm_message_delete<F: Fn() + 'static>(
+    parent: &impl IsA<gtk4::Window>,
+    for_everyone: bool,
+    on_confirm: F,
+) {
+    let body = if for_everyone {
+        "Everyone in the chat will see that a message was deleted."
+    } else {
+        "The message will be removed from your devices only."
+    };
+    let dialog = adw::MessageDialog::new(Some(parent), Some("Delete Message?"), Some(body));
+    dialog.add_responses(&[("cancel", "Cancel"), ("delete", "Delete")]);
+    dialog.set_response_appearance("delete", adw::ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("cancel"));
+    dialog.set_close_response("cancel");
+
+    dialog.connect_response(None, move |_, response| {
+        if response == "delete" {
+            on_confirm();
+        }
+    });
+
+    dialog.present();
+}
+
+/// Ask for a file to send and a caption for it, then pass both to `on_pick`.
+/// The MIME type is sniffed from the file's contents.
+pub fn pick_attachment<F: Fn(Attachment, String) + 'static>(
+    parent: &impl IsA<gtk4::Window>,
+    on_pick: F,
+) {
+    let chooser = gtk4::FileChooserNative::new(
+        Some("Attach File"),
+        Some(parent),
+        gtk4::FileChooserAction::Open,
+        Some("Attach"),
+        Some("Cancel"),
+    );
+
+    let parent = parent.clone().upcast::<gtk4::Window>();
+    let on_pick = Rc::new(on_pick);
+    // Native dialogs aren't kept alive by GTK while they're shown
+    let keep_alive = RefCell::new(Some(chooser.clone()));
+    chooser.connect_response(move |chooser, response| {
+        let file = chooser.file();
+        chooser.destroy();
+        keep_alive.take();
+        let (gtk4::ResponseType::Accept, Some(file)) = (response, file) else {
+            return;
+        };
+        let Some(path) = file.path() else {
+            return;
+        };
+
+        let mime_type = file
+            .query_info(
+                "standard::content-type",
+                gio::FileQueryInfoFlags::NONE,
+                None::<&Cancellable>,
+            )
+            .ok()
+            .and_then(|info| info.content_type())
+            .and_then(|content_type| gio::content_type_get_mime_type(&content_type))
+            .map(|mime_type| mime_type.to_string())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let attachment = Attachment {
+            size: std::fs::metadata(&path).ok().map(|m| m.len()),
+            path: path.to_string_lossy().into_owned(),
+            mime_type,
+        };
+        prompt_caption(&parent, attachment, Rc::clone(&on_pick));
+    });
+    chooser.show();
+}
+
+/// Confirm sending a file, with a caption where WhatsApp shows one
+fn prompt_caption<F: Fn(Attachment, String) + 'static>(
+    parent: &gtk4::Window,
+    attachment: Attachment,
+    on_send: Rc<F>,
+) {
+    let heading = format!("Send {}", attachment.file_name());
+    let dialog = adw::MessageDialog::new(Some(parent), Some(&heading), None);
+    dialog.add_responses(&[("cancel", "Cancel"), ("send", "Send")]);
+    dialog.set_response_appearance("send", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("send"));
+    dialog.set_close_response("cancel");
+
+    let entry = gtk4::Entry::builder()
+        .placeholder_text("Add a caption")
+        .activates_default(true)
+        .build();
+    if attachment.has_caption() {
+        dialog.set_extra_child(Some(&entry));
+    }
+
+    dialog.connect_response(None, move |_, response| {
+        if response == "send" {
+            on_send(attachment.clone(), entry.text().trim().to_string());
+        }
+    });
+
+    dialog.present();
+}
+
 /// Generate avatar color from string (for consistent colors)
 pub fn generate_avatar_color(text: &str) -> (f64, f64, f64) {
     // Simple hash function to generate consistent colors